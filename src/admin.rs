@@ -0,0 +1,237 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! A local control socket for operators.
+//!
+//! A running vault listens on a Unix-domain socket, and each connection to it carries requests
+//! and responses as lines of JSON.  A request such as `{"command":"close_group","argument":"<hex
+//! name>"}` is answered with either `{"ok":true,"result":<JSON>}` or `{"ok":false,"error":"..."}`.
+
+use error::InternalError;
+use log::LogLevelFilter;
+use logger;
+use rustc_serialize::hex::{FromHex, ToHex};
+use rustc_serialize::json::{self, Json};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use unix_socket::{UnixListener, UnixStream};
+use xor_name::{XOR_NAME_LEN, XorName};
+
+/// A request as sent over the socket.
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
+pub struct AdminRequest {
+    pub command: String,
+    pub argument: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum AdminCommand {
+    /// List the names of the accounts held by each persona.
+    Accounts,
+    /// Show the used and maximum space of each chunk store.
+    ChunkStores,
+    /// Show the requests each persona is waiting on.
+    Ongoing,
+    /// Show the vault's metrics in the Prometheus text format.
+    Metrics,
+    /// Show the close group of a name, if we're in it.
+    CloseGroup(XorName),
+    /// Change the log level.
+    LogLevel(LogLevelFilter),
+    /// Hand off the vault's state to the network, then stop it.
+    Drain,
+    /// Stop the vault immediately.
+    Shutdown,
+}
+
+impl AdminCommand {
+    fn parse(request: AdminRequest) -> Result<AdminCommand, String> {
+        match (&request.command[..], request.argument) {
+            ("accounts", None) => Ok(AdminCommand::Accounts),
+            ("chunk_stores", None) => Ok(AdminCommand::ChunkStores),
+            ("ongoing", None) => Ok(AdminCommand::Ongoing),
+            ("metrics", None) => Ok(AdminCommand::Metrics),
+            ("close_group", Some(name)) => name_from_hex(&name).map(AdminCommand::CloseGroup),
+            ("log_level", Some(level)) => logger::parse_level(&level).map(AdminCommand::LogLevel),
+            ("drain", None) => Ok(AdminCommand::Drain),
+            ("shutdown", None) => Ok(AdminCommand::Shutdown),
+            (command, argument) => {
+                Err(format!("Invalid command {:?} with argument {:?}", command, argument))
+            }
+        }
+    }
+}
+
+/// The outcome of a command: a JSON result, or an error message.
+pub type AdminResponse = Result<Json, String>;
+
+pub fn name_to_hex(name: &XorName) -> String {
+    name.0.to_hex()
+}
+
+fn name_from_hex(hex: &str) -> Result<XorName, String> {
+    let bytes = try!(hex.from_hex().map_err(|error| format!("Invalid name {}: {}", hex, error)));
+    if bytes.len() != XOR_NAME_LEN {
+        return Err(format!("Invalid name {}: expected {} bytes", hex, XOR_NAME_LEN));
+    }
+    let mut name = [0u8; XOR_NAME_LEN];
+    for (dst, src) in name.iter_mut().zip(bytes) {
+        *dst = src;
+    }
+    Ok(XorName(name))
+}
+
+/// Listens on `socket_path` on a new thread, answering each command using `handler`.  Any stale
+/// socket file left by a previous run is replaced.
+pub fn start<F>(socket_path: &Path, handler: F) -> Result<(), InternalError>
+    where F: Fn(AdminCommand) -> AdminResponse + Send + Sync + 'static
+{
+    if socket_path.exists() {
+        try!(fs::remove_file(socket_path));
+    }
+    let listener = try!(UnixListener::bind(socket_path));
+    info!("Listening for admin commands on {}", socket_path.display());
+    let handler = Arc::new(handler);
+    let _ = thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let handler = handler.clone();
+                    let _ = thread::spawn(move || serve(stream, &*handler));
+                }
+                Err(error) => warn!("Failed to accept admin connection: {:?}", error),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn serve<F: Fn(AdminCommand) -> AdminResponse>(stream: UnixStream, handler: &F) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(error) => {
+            warn!("Failed to set up admin connection: {:?}", error);
+            return;
+        }
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let response = json::decode::<AdminRequest>(&line)
+                           .map_err(|error| format!("Invalid request: {}", error))
+                           .and_then(AdminCommand::parse)
+                           .and_then(|command| {
+                               info!("Handling admin command {:?}", command);
+                               handler(command)
+                           });
+        let mut object = BTreeMap::new();
+        let _ = object.insert("ok".to_owned(), Json::Boolean(response.is_ok()));
+        match response {
+            Ok(result) => {
+                let _ = object.insert("result".to_owned(), result);
+            }
+            Err(error) => {
+                let _ = object.insert("error".to_owned(), Json::String(error));
+            }
+        }
+        if writeln!(writer, "{}", Json::Object(object)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends a single request to the vault listening on `socket_path`, and returns its response.
+pub fn send_request(socket_path: &Path, request: &AdminRequest) -> AdminResponse {
+    let mut stream = try!(UnixStream::connect(socket_path).map_err(|error| {
+        format!("Failed to connect to {}: {}", socket_path.display(), error)
+    }));
+    let encoded = try!(json::encode(request).map_err(|error| error.to_string()));
+    try!(writeln!(stream, "{}", encoded).map_err(|error| error.to_string()));
+    let mut line = String::new();
+    let _ = try!(BufReader::new(stream).read_line(&mut line).map_err(|error| error.to_string()));
+    let response = try!(Json::from_str(&line).map_err(|error| error.to_string()));
+    match (response.find("ok").and_then(Json::as_boolean), response.find("result")) {
+        (Some(true), Some(result)) => Ok(result.clone()),
+        _ => {
+            Err(response.find("error")
+                        .and_then(Json::as_string)
+                        .unwrap_or("Invalid response")
+                        .to_owned())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use rand::random;
+    use rustc_serialize::json::Json;
+    use std::env;
+    use xor_name::XorName;
+
+    #[test]
+    fn request_round_trip() {
+        let socket_path = env::temp_dir().join(format!("safe_vault_admin_{:016x}",
+                                                       random::<u64>()));
+        unwrap_result!(start(&socket_path, |command| {
+            match command {
+                AdminCommand::CloseGroup(name) => Ok(Json::String(name_to_hex(&name))),
+                command @ AdminCommand::LogLevel(_) => Ok(Json::String(format!("{:?}", command))),
+                _ => Err("Unsupported".to_owned()),
+            }
+        }));
+
+        let name = random::<XorName>();
+        let request = AdminRequest {
+            command: "close_group".to_owned(),
+            argument: Some(name_to_hex(&name)),
+        };
+        assert_eq!(send_request(&socket_path, &request),
+                   Ok(Json::String(name_to_hex(&name))));
+
+        let request = AdminRequest {
+            command: "log_level".to_owned(),
+            argument: Some("debug".to_owned()),
+        };
+        assert_eq!(send_request(&socket_path, &request),
+                   Ok(Json::String("LogLevel(Debug)".to_owned())));
+
+        let request = AdminRequest {
+            command: "log_level".to_owned(),
+            argument: Some("verbose".to_owned()),
+        };
+        assert!(send_request(&socket_path, &request).is_err());
+
+        let request = AdminRequest {
+            command: "shutdown".to_owned(),
+            argument: None,
+        };
+        assert_eq!(send_request(&socket_path, &request), Err("Unsupported".to_owned()));
+
+        let request = AdminRequest {
+            command: "close_group".to_owned(),
+            argument: Some("not hex".to_owned()),
+        };
+        assert!(send_request(&socket_path, &request).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! The time source used by the personas.
+//!
+//! Normally this is the system's steady clock, until the clock is first advanced, after which
+//! it's a virtual clock which only moves when advanced.  Replaying a journal advances it by the
+//! recorded times.  In mock-routing tests the clock is virtual from the start, so timeouts and
+//! expiry can be exercised instantly and deterministically.  The virtual clock is per-thread, so
+//! concurrently-running tests don't affect one another.
+
+use std::cell::RefCell;
+use time::{self, Duration, SteadyTime};
+
+thread_local!(static VIRTUAL_NOW: RefCell<Option<SteadyTime>> = RefCell::new(None));
+
+#[cfg(not(all(test, feature = "use-mock-routing")))]
+pub fn now() -> SteadyTime {
+    VIRTUAL_NOW.with(|virtual_now| virtual_now.borrow().unwrap_or_else(SteadyTime::now))
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+pub fn now() -> SteadyTime {
+    VIRTUAL_NOW.with(|virtual_now| {
+        let mut virtual_now = virtual_now.borrow_mut();
+        if virtual_now.is_none() {
+            *virtual_now = Some(SteadyTime::now());
+        }
+        unwrap_option!(*virtual_now, "Virtual time has just been set")
+    })
+}
+
+/// Returns the milliseconds since the Unix epoch, for times which must mean the same to other
+/// vaults and after a restart.  The system's wall clock is moved forward with the virtual clock.
+pub fn wall_time_ms() -> i64 {
+    let time = time::get_time() + (now() - SteadyTime::now());
+    time.sec * 1000 + time.nsec as i64 / 1_000_000
+}
+
+/// Moves this thread's virtual clock forward by `duration`.
+pub fn advance(duration: Duration) {
+    let advanced = now() + duration;
+    VIRTUAL_NOW.with(|virtual_now| *virtual_now.borrow_mut() = Some(advanced));
+}
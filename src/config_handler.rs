@@ -0,0 +1,135 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Vault configuration, read from `<exe stem>.vault.config` and optionally overridden on the
+//! command line.
+
+use config_file_handler::{self, FileHandler};
+use error::InternalError;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+const DEFAULT_ROOT_DIR_NAME: &'static str = "safe-vault";
+const DEFAULT_ADMIN_SOCKET_NAME: &'static str = "admin.sock";
+const DEFAULT_CAPACITY: u64 = 1073741824;  // 1 GB
+const DEFAULT_TICK_INTERVAL_MS: u64 = 1000;
+const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_TOMBSTONE_RETENTION_SECS: u64 = 86400;  // 1 day
+
+/// All fields are optional; any which are `None` fall back to the defaults.
+#[derive(PartialEq, Eq, Debug, Clone, Default, RustcEncodable, RustcDecodable)]
+pub struct Config {
+    /// Root directory under which each persona's chunk store and persisted state is created.
+    pub root_dir: Option<String>,
+    /// Maximum space in bytes for the PmidNode's chunk store.
+    pub pmid_node_capacity: Option<u64>,
+    /// Maximum space in bytes for the StructuredDataManager's chunk store.
+    pub structured_data_manager_capacity: Option<u64>,
+    /// Maximum space in bytes for the MpidManager's inbox chunk store.
+    pub mpid_manager_inbox_capacity: Option<u64>,
+    /// Maximum space in bytes for the MpidManager's outbox chunk store.
+    pub mpid_manager_outbox_capacity: Option<u64>,
+    /// Interval in milliseconds at which the personas carry out time-based maintenance.
+    pub tick_interval_ms: Option<u64>,
+    /// Path of the Unix-domain socket on which the vault accepts admin commands.  Defaults to a
+    /// file in the root directory.
+    pub admin_socket: Option<String>,
+    /// Maximum time in seconds for which a stopping vault waits for its chunks to be re-homed
+    /// before leaving the network.
+    pub drain_timeout_secs: Option<u64>,
+    /// Number of past versions of each StructuredData which the StructuredDataManager keeps.
+    pub structured_data_history: Option<u64>,
+    /// Overrides `structured_data_history` for StructuredData of the given type tags.
+    pub structured_data_history_by_type_tag: Option<BTreeMap<u64, u64>>,
+    /// Time in seconds for which the StructuredDataManager keeps the tombstone of a deleted
+    /// StructuredData, refusing Puts of the same name.
+    pub structured_data_tombstone_retention_secs: Option<u64>,
+}
+
+impl Config {
+    pub fn root_dir(&self) -> PathBuf {
+        match self.root_dir {
+            Some(ref root_dir) => PathBuf::from(root_dir),
+            None => env::temp_dir().join(DEFAULT_ROOT_DIR_NAME),
+        }
+    }
+
+    pub fn pmid_node_capacity(&self) -> u64 {
+        self.pmid_node_capacity.unwrap_or(DEFAULT_CAPACITY)
+    }
+
+    pub fn structured_data_manager_capacity(&self) -> u64 {
+        self.structured_data_manager_capacity.unwrap_or(DEFAULT_CAPACITY)
+    }
+
+    pub fn mpid_manager_inbox_capacity(&self) -> u64 {
+        self.mpid_manager_inbox_capacity.unwrap_or(DEFAULT_CAPACITY)
+    }
+
+    pub fn mpid_manager_outbox_capacity(&self) -> u64 {
+        self.mpid_manager_outbox_capacity.unwrap_or(DEFAULT_CAPACITY)
+    }
+
+    pub fn tick_interval_ms(&self) -> u64 {
+        self.tick_interval_ms.unwrap_or(DEFAULT_TICK_INTERVAL_MS)
+    }
+
+    pub fn drain_timeout_secs(&self) -> u64 {
+        self.drain_timeout_secs.unwrap_or(DEFAULT_DRAIN_TIMEOUT_SECS)
+    }
+
+    pub fn structured_data_history(&self, type_tag: u64) -> u64 {
+        self.structured_data_history_by_type_tag
+            .as_ref()
+            .and_then(|by_type_tag| by_type_tag.get(&type_tag).cloned())
+            .or(self.structured_data_history)
+            .unwrap_or(0)
+    }
+
+    pub fn structured_data_tombstone_retention_secs(&self) -> u64 {
+        self.structured_data_tombstone_retention_secs.unwrap_or(DEFAULT_TOMBSTONE_RETENTION_SECS)
+    }
+
+    pub fn admin_socket_path(&self) -> PathBuf {
+        match self.admin_socket {
+            Some(ref admin_socket) => PathBuf::from(admin_socket),
+            None => self.root_dir().join(DEFAULT_ADMIN_SOCKET_NAME),
+        }
+    }
+}
+
+/// Reads the config file.  If it doesn't exist, a default one is written and returned, but a file
+/// which can't be read or parsed is an error.
+pub fn read_config_file() -> Result<Config, InternalError> {
+    let file_handler = try!(FileHandler::new(&try!(get_file_name())));
+    if !file_handler.path().exists() {
+        debug!("No vault config file at {} - writing default one.",
+               file_handler.path().display());
+        let config = Config::default();
+        try!(file_handler.write_file(&config));
+        return Ok(config);
+    }
+    Ok(try!(file_handler.read_file::<Config>()))
+}
+
+fn get_file_name() -> Result<OsString, InternalError> {
+    let mut name = try!(config_file_handler::exe_file_stem());
+    name.push(".vault.config");
+    Ok(name)
+}
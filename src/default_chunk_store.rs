@@ -15,14 +15,18 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-//! Default parameters for ChunkStore.
+//! Construction of the ChunkStores used by the personas.
 
 use chunk_store::{ChunkStore, Error};
+use config_handler::Config;
 
-const PREFIX: &'static str = "safe-vault";
-const SIZE: u64 = 1073741824;
+pub const PMID_NODE: &'static str = "pmid_node";
+pub const STRUCTURED_DATA_MANAGER: &'static str = "structured_data_manager";
+pub const STRUCTURED_DATA_HISTORY: &'static str = "structured_data_history";
+pub const MPID_MANAGER_INBOX: &'static str = "mpid_manager_inbox";
+pub const MPID_MANAGER_OUTBOX: &'static str = "mpid_manager_outbox";
 
-// Construct a new ChunkStore initialised with the default parameters.
-pub fn new() -> Result<ChunkStore, Error> {
-    ChunkStore::new(PREFIX, SIZE)
+// Construct a new ChunkStore in the directory `name` under the configured root directory.
+pub fn new(config: &Config, name: &str, capacity: u64) -> Result<ChunkStore, Error> {
+    ChunkStore::new(config.root_dir().join(name), capacity)
 }
@@ -16,19 +16,65 @@
 // relating to use of the SAFE Network Software.
 
 use chunk_store;
+use config_file_handler;
 use mpid_messaging;
 use maidsafe_utilities::serialisation::SerialisationError;
 use routing::{Authority, InterfaceError, MessageId, RoutingError, RoutingMessage};
 use std::io;
 use types::Refresh;
 
-#[derive(Debug, RustcEncodable, RustcDecodable)]
+#[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
 pub enum ClientError {
     NoSuchAccount,
     AccountExists,
     NoSuchData,
     DataExists,
     LowBalance,
+    /// No response was received from the network in time.
+    Timeout,
+    /// The new version of the StructuredData isn't a valid successor of the stored one, e.g.
+    /// because its type tag is wrong.
+    InvalidSuccessor,
+    /// The new version of the StructuredData doesn't directly follow the stored one, whose
+    /// version number is given.  A Post or Delete of version `n` only succeeds if the stored
+    /// version is `n - 1`, so this means another client has updated the data first.
+    VersionConflict(u64),
+    /// The new version of the StructuredData isn't validly signed by the owners of the stored
+    /// one.
+    BadSignature,
+    /// The vault failed to store or read the data.  The request may succeed if retried.
+    StoreError,
+    /// The receiver's inbox has no room for the message's header.
+    InboxFull,
+    /// The sender's outbox has no room for the message.
+    OutboxFull,
+    /// The request isn't allowed, e.g. because the client isn't registered with the account or
+    /// isn't the recipient of the message.
+    InvalidOperation,
+    /// The data has been deleted, and its name can't be reused until its tombstone expires.
+    DataDeleted,
+}
+
+impl ClientError {
+    /// The name of the error's variant, without any values it holds.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ClientError::NoSuchAccount => "NoSuchAccount",
+            ClientError::AccountExists => "AccountExists",
+            ClientError::NoSuchData => "NoSuchData",
+            ClientError::DataExists => "DataExists",
+            ClientError::LowBalance => "LowBalance",
+            ClientError::Timeout => "Timeout",
+            ClientError::InvalidSuccessor => "InvalidSuccessor",
+            ClientError::VersionConflict(_) => "VersionConflict",
+            ClientError::BadSignature => "BadSignature",
+            ClientError::StoreError => "StoreError",
+            ClientError::InboxFull => "InboxFull",
+            ClientError::OutboxFull => "OutboxFull",
+            ClientError::InvalidOperation => "InvalidOperation",
+            ClientError::DataDeleted => "DataDeleted",
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -41,6 +87,7 @@ pub enum InternalError {
     NotInCloseGroup,
     UnableToAllocateNewPmidNode,
     ChunkStore(chunk_store::Error),
+    FileHandler(config_file_handler::Error),
     MpidMessaging(mpid_messaging::Error),
     Serialisation(SerialisationError),
     Routing(InterfaceError),
@@ -60,6 +107,12 @@ impl From<chunk_store::Error> for InternalError {
     }
 }
 
+impl From<config_file_handler::Error> for InternalError {
+    fn from(error: config_file_handler::Error) -> InternalError {
+        InternalError::FileHandler(error)
+    }
+}
+
 impl From<mpid_messaging::Error> for InternalError {
     fn from(error: mpid_messaging::Error) -> InternalError {
         InternalError::MpidMessaging(error)
@@ -0,0 +1,150 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use clock;
+use std::collections::HashMap;
+use std::hash::Hash;
+use time::{Duration, SteadyTime};
+
+/// A map whose entries expire a fixed time after they were inserted, as measured by
+/// `clock::now()`.  Expired entries are no longer returned by `get_mut` or `remove`, and are
+/// handed back by `pop_expired` so the owner can act on them.  Once at capacity, inserting a new
+/// entry evicts the oldest one, which is handed back by `insert` for the same reason.
+pub struct ExpiringMap<K, V> {
+    entries: HashMap<K, (V, SteadyTime)>,
+    time_to_live: Duration,
+    capacity: usize,
+}
+
+impl<K: Clone + Eq + Hash, V> ExpiringMap<K, V> {
+    pub fn new(time_to_live: Duration, capacity: usize) -> ExpiringMap<K, V> {
+        ExpiringMap {
+            entries: HashMap::new(),
+            time_to_live: time_to_live,
+            capacity: capacity,
+        }
+    }
+
+    /// Inserts `value` under `key`, returning the entry it displaced, if any: either the previous
+    /// entry for `key` (whether or not it had expired) or, if the map was full, the oldest entry.
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        let mut evicted = None;
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let oldest = self.entries
+                             .iter()
+                             .min_by_key(|&(_, &(_, inserted))| inserted)
+                             .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                evicted = self.entries.remove(&oldest).map(|(value, _)| (oldest, value));
+            }
+        }
+        match self.entries.insert(key.clone(), (value, clock::now())) {
+            Some((previous, _)) => Some((key, previous)),
+            None => evicted,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let expiry = clock::now() - self.time_to_live;
+        match self.entries.get_mut(key) {
+            Some(&mut (ref mut value, ref inserted)) if *inserted > expiry => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let expiry = clock::now() - self.time_to_live;
+        match self.entries.remove(key) {
+            Some((value, inserted)) => {
+                if inserted > expiry {
+                    Some(value)
+                } else {
+                    // Leave it to be returned by `pop_expired`.
+                    let _ = self.entries.insert(key.clone(), (value, inserted));
+                    None
+                }
+            }
+            None => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the keys of the entries which haven't yet expired.
+    pub fn keys(&self) -> Vec<K> {
+        let expiry = clock::now() - self.time_to_live;
+        self.entries
+            .iter()
+            .filter(|&(_, &(_, inserted))| inserted > expiry)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Removes and returns all the expired entries.
+    pub fn pop_expired(&mut self) -> Vec<(K, V)> {
+        let expiry = clock::now() - self.time_to_live;
+        let expired_keys = self.entries
+                               .iter()
+                               .filter(|&(_, &(_, inserted))| inserted <= expiry)
+                               .map(|(key, _)| key.clone())
+                               .collect::<Vec<_>>();
+        expired_keys.into_iter()
+                    .filter_map(|key| {
+                        self.entries.remove(&key).map(|(value, _)| (key, value))
+                    })
+                    .collect()
+    }
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use clock;
+    use time::Duration;
+
+    #[test]
+    fn expiry() {
+        let mut map = ExpiringMap::new(Duration::minutes(1), 10);
+        assert!(map.insert(0, "a").is_none());
+        clock::advance(Duration::seconds(30));
+        assert!(map.insert(1, "b").is_none());
+        assert!(map.pop_expired().is_empty());
+        assert_eq!(map.get_mut(&0).map(|value| *value), Some("a"));
+
+        clock::advance(Duration::seconds(30));
+        assert!(map.get_mut(&0).is_none());
+        assert!(map.remove(&0).is_none());
+        assert_eq!(map.pop_expired(), vec![(0, "a")]);
+        assert_eq!(map.remove(&1), Some("b"));
+        assert!(map.pop_expired().is_empty());
+    }
+
+    #[test]
+    fn capacity() {
+        let mut map = ExpiringMap::new(Duration::minutes(1), 2);
+        assert!(map.insert(0, "a").is_none());
+        clock::advance(Duration::seconds(1));
+        assert!(map.insert(1, "b").is_none());
+        assert_eq!(map.insert(2, "c"), Some((0, "a")));
+        assert_eq!(map.insert(2, "d"), Some((2, "c")));
+        assert!(map.get_mut(&0).is_none());
+        assert!(map.get_mut(&1).is_some());
+        assert!(map.get_mut(&2).is_some());
+    }
+}
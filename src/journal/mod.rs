@@ -0,0 +1,211 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Recording of a vault's inputs and outputs, for replaying offline.
+//!
+//! When recording, every event from routing, every tick and every message the vault sends is
+//! appended to a journal, along with routing's answers to the vault's queries (its name and close
+//! groups).  Replaying feeds the recorded events and ticks to a fresh vault whose routing node
+//! answers queries from the journal and captures the messages sent instead of sending them, so
+//! they can be compared with the recorded ones.  The personas' clock follows the recorded times,
+//! so timeouts fire as they originally did, and the personas' RNG is seeded with the recorded seed,
+//! so their message IDs, nonces and other random choices are repeated too.
+//!
+//! Each journal record is a four-byte big-endian length followed by the serialised `Entry`.
+
+#[cfg(not(all(test, feature = "use-mock-routing")))]
+mod node;
+mod replay;
+
+#[cfg(not(all(test, feature = "use-mock-routing")))]
+pub use self::node::Node;
+pub use self::replay::{Difference, replay};
+
+use clock;
+use error::InternalError;
+use maidsafe_utilities::serialisation::{deserialise, serialise};
+use rand;
+use rng;
+use routing::{Event, RequestMessage, ResponseMessage};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use time::SteadyTime;
+use utils;
+use xor_name::XorName;
+
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Record {
+    // Inputs to the vault.
+    Request(RequestMessage),
+    Response(ResponseMessage),
+    NodeAdded(XorName),
+    NodeLost(XorName),
+    Connected,
+    Disconnected,
+    Tick,
+    // The seed of the personas' RNG.
+    Seed([u32; 4]),
+    // Routing's answers to the vault's queries.
+    Name(XorName),
+    CloseGroup(XorName, Option<Vec<XorName>>),
+    // Messages sent by the vault.
+    SentRequest(RequestMessage),
+    SentResponse(ResponseMessage),
+}
+
+impl Record {
+    pub fn from_event(event: &Event) -> Record {
+        match *event {
+            Event::Request(ref request) => Record::Request(request.clone()),
+            Event::Response(ref response) => Record::Response(response.clone()),
+            Event::NodeAdded(ref node_added) => Record::NodeAdded(*node_added),
+            Event::NodeLost(ref node_lost) => Record::NodeLost(*node_lost),
+            Event::Connected => Record::Connected,
+            Event::Disconnected => Record::Disconnected,
+        }
+    }
+}
+
+#[derive(Debug, RustcEncodable, RustcDecodable)]
+pub struct Entry {
+    /// Milliseconds since the journal was created.
+    pub elapsed_ms: u64,
+    pub record: Record,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    start: SteadyTime,
+}
+
+impl Journal {
+    /// Creates the journal at `path`, replacing any existing file, and seeds this thread's
+    /// persona RNG with a seed which is recorded first.
+    pub fn create(path: &Path) -> Result<Journal, InternalError> {
+        let file = try!(File::create(path));
+        info!("Recording to {}", path.display());
+        let mut journal = Journal {
+            path: path.to_path_buf(),
+            file: file,
+            start: clock::now(),
+        };
+        let seed = [rand::random(), rand::random(), rand::random(), rand::random()];
+        rng::seed(seed);
+        journal.append(Record::Seed(seed));
+        Ok(journal)
+    }
+
+    pub fn append(&mut self, record: Record) {
+        let entry = Entry {
+            elapsed_ms: (clock::now() - self.start).num_milliseconds() as u64,
+            record: record,
+        };
+        let serialised_entry = match serialise(&entry) {
+            Ok(serialised_entry) => serialised_entry,
+            Err(error) => {
+                error!("Failed to serialise {:?}: {:?}", entry, error);
+                return;
+            }
+        };
+        if let Err(error) = utils::write_record(&mut self.file, &serialised_entry) {
+            error!("Failed to write to {}: {:?}", self.path.display(), error);
+        }
+    }
+}
+
+/// Reads all the entries of the journal at `path`.  A torn entry at the end of the journal (e.g.
+/// from the recording vault being killed mid-write) is ignored.
+pub fn read(path: &Path) -> Result<Vec<Entry>, InternalError> {
+    let mut journal = Vec::new();
+    let _ = try!(try!(File::open(path)).read_to_end(&mut journal));
+    let (records, length) = utils::read_records(&journal);
+    let mut entries = Vec::new();
+    for record in records {
+        entries.push(try!(deserialise::<Entry>(record)));
+    }
+    if length != journal.len() {
+        warn!("Ignored {} trailing bytes of {}",
+              journal.len() - length,
+              path.display());
+    }
+    Ok(entries)
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use clock;
+    use rand::random;
+    use routing::{Authority, MessageId, RequestContent, RequestMessage, ResponseContent,
+                  ResponseMessage};
+    use std::io::Write;
+    use time::Duration;
+    use utils;
+    use xor_name::XorName;
+
+    #[test]
+    fn read_returns_appended_records() {
+        let config = utils::test_config();
+        unwrap_result!(::std::fs::create_dir_all(config.root_dir()));
+        let path = config.root_dir().join("journal");
+        let (name, member) = (random::<XorName>(), random::<XorName>());
+        let request = RequestMessage {
+            src: Authority::NaeManager(name),
+            dst: Authority::NaeManager(name),
+            content: RequestContent::Refresh(vec![1, 2, 3]),
+        };
+        let response = ResponseMessage {
+            src: Authority::NaeManager(name),
+            dst: Authority::NaeManager(name),
+            content: ResponseContent::GetFailure {
+                id: MessageId::new(),
+                request: request.clone(),
+                external_error_indicator: vec![],
+            },
+        };
+        let records = vec![Record::Name(name),
+                           Record::Request(request.clone()),
+                           Record::CloseGroup(name, Some(vec![member])),
+                           Record::SentRequest(request),
+                           Record::SentResponse(response),
+                           Record::Tick];
+        {
+            let mut journal = unwrap_result!(Journal::create(&path));
+            for (index, record) in records.iter().enumerate() {
+                if index == 5 {
+                    clock::advance(Duration::seconds(1));
+                }
+                journal.append(record.clone());
+            }
+            // Simulate the vault being killed part way through writing the next entry.
+            unwrap_result!(journal.file.write_all(&[0, 0, 1]));
+        }
+
+        // The journal starts with the seed of the personas' RNG.
+        let entries = unwrap_result!(read(&path));
+        match entries[0].record {
+            Record::Seed(_) => (),
+            ref record => panic!("Expected a seed, found {:?}", record),
+        }
+        assert_eq!(entries[1..].iter().map(|entry| entry.record.clone()).collect::<Vec<_>>(),
+                   records);
+        assert_eq!(entries[5].elapsed_ms, 0);
+        assert_eq!(entries[6].elapsed_ms, 1000);
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use rand;
+use routing::{self, Authority, Data, DataRequest, Event, InterfaceError, MessageId,
+              RequestContent, RequestMessage, ResponseContent, ResponseMessage, RoutingError};
+use sodiumoxide::crypto::hash::sha512;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use super::{Journal, Record};
+use xor_name::XorName;
+
+/// The vault's routing node.  This is either a node connected to the network, optionally
+/// recording the messages sent and the answers to the vault's queries, or a stand-in for one
+/// while replaying a journal.
+pub struct Node {
+    backend: Backend,
+}
+
+enum Backend {
+    Live {
+        routing_node: routing::Node,
+        journal: Option<Arc<Mutex<Journal>>>,
+        // The name last recorded, so that it's only recorded again if it changes.
+        recorded_name: Cell<Option<XorName>>,
+    },
+    Replay(RefCell<Replay>),
+}
+
+struct Replay {
+    name: XorName,
+    close_groups: HashMap<XorName, Option<Vec<XorName>>>,
+    sent: Vec<Record>,
+}
+
+impl Node {
+    pub fn new(event_sender: Sender<Event>,
+               journal: Option<Arc<Mutex<Journal>>>)
+               -> Result<Node, RoutingError> {
+        Ok(Node {
+            backend: Backend::Live {
+                routing_node: try!(routing::Node::new(event_sender)),
+                journal: journal,
+                recorded_name: Cell::new(None),
+            },
+        })
+    }
+
+    /// Creates a stand-in node for replaying a journal.  It has a random name until given the
+    /// recorded one.
+    pub fn replay() -> Node {
+        Node {
+            backend: Backend::Replay(RefCell::new(Replay {
+                name: rand::random(),
+                close_groups: HashMap::new(),
+                sent: Vec::new(),
+            })),
+        }
+    }
+
+    /// When replaying, makes the node answer queries as recorded by `record`.
+    pub fn apply_answer(&self, record: &Record) {
+        if let Backend::Replay(ref replay) = self.backend {
+            let mut replay = replay.borrow_mut();
+            match *record {
+                Record::Name(name) => replay.name = name,
+                Record::CloseGroup(name, ref close_group) => {
+                    let _ = replay.close_groups.insert(name, close_group.clone());
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// When replaying, returns the messages sent since this was last called.
+    pub fn take_sent(&self) -> Vec<Record> {
+        match self.backend {
+            Backend::Replay(ref replay) => mem::replace(&mut replay.borrow_mut().sent, Vec::new()),
+            Backend::Live { .. } => Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> Result<XorName, InterfaceError> {
+        match self.backend {
+            Backend::Live { ref routing_node, ref journal, ref recorded_name } => {
+                let name = try!(routing_node.name());
+                if let Some(ref journal) = *journal {
+                    if recorded_name.get() != Some(name) {
+                        unwrap_result!(journal.lock()).append(Record::Name(name));
+                        recorded_name.set(Some(name));
+                    }
+                }
+                Ok(name)
+            }
+            Backend::Replay(ref replay) => Ok(replay.borrow().name),
+        }
+    }
+
+    pub fn close_group(&self, name: XorName) -> Result<Option<Vec<XorName>>, InterfaceError> {
+        match self.backend {
+            Backend::Live { ref routing_node, ref journal, .. } => {
+                let close_group = try!(routing_node.close_group(name));
+                if let Some(ref journal) = *journal {
+                    unwrap_result!(journal.lock())
+                        .append(Record::CloseGroup(name, close_group.clone()));
+                }
+                Ok(close_group)
+            }
+            Backend::Replay(ref replay) => {
+                match replay.borrow().close_groups.get(&name) {
+                    Some(close_group) => Ok(close_group.clone()),
+                    None => {
+                        warn!("No close group of {} was recorded", name);
+                        Ok(None)
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn send_get_request(&self,
+                            src: Authority,
+                            dst: Authority,
+                            data_request: DataRequest,
+                            id: MessageId)
+                            -> Result<(), InterfaceError> {
+        self.sent_request(&src, &dst, || RequestContent::Get(data_request.clone(), id.clone()));
+        self.live().map_or(Ok(()), |node| node.send_get_request(src, dst, data_request, id))
+    }
+
+    pub fn send_put_request(&self,
+                            src: Authority,
+                            dst: Authority,
+                            data: Data,
+                            id: MessageId)
+                            -> Result<(), InterfaceError> {
+        self.sent_request(&src, &dst, || RequestContent::Put(data.clone(), id.clone()));
+        self.live().map_or(Ok(()), |node| node.send_put_request(src, dst, data, id))
+    }
+
+    pub fn send_post_request(&self,
+                             src: Authority,
+                             dst: Authority,
+                             data: Data,
+                             id: MessageId)
+                             -> Result<(), InterfaceError> {
+        self.sent_request(&src, &dst, || RequestContent::Post(data.clone(), id.clone()));
+        self.live().map_or(Ok(()), |node| node.send_post_request(src, dst, data, id))
+    }
+
+    pub fn send_delete_request(&self,
+                               src: Authority,
+                               dst: Authority,
+                               data: Data,
+                               id: MessageId)
+                               -> Result<(), InterfaceError> {
+        self.sent_request(&src, &dst, || RequestContent::Delete(data.clone(), id.clone()));
+        self.live().map_or(Ok(()), |node| node.send_delete_request(src, dst, data, id))
+    }
+
+    pub fn send_refresh_request(&self,
+                                src: Authority,
+                                content: Vec<u8>)
+                                -> Result<(), InterfaceError> {
+        self.sent_request(&src, &src, || RequestContent::Refresh(content.clone()));
+        self.live().map_or(Ok(()), |node| node.send_refresh_request(src, content))
+    }
+
+    pub fn send_get_success(&self,
+                            src: Authority,
+                            dst: Authority,
+                            data: Data,
+                            id: MessageId)
+                            -> Result<(), InterfaceError> {
+        self.sent_response(&src, &dst, || ResponseContent::GetSuccess(data.clone(), id.clone()));
+        self.live().map_or(Ok(()), |node| node.send_get_success(src, dst, data, id))
+    }
+
+    pub fn send_get_failure(&self,
+                            src: Authority,
+                            dst: Authority,
+                            request: RequestMessage,
+                            external_error_indicator: Vec<u8>,
+                            id: MessageId)
+                            -> Result<(), InterfaceError> {
+        self.sent_response(&src, &dst, || {
+            ResponseContent::GetFailure {
+                id: id.clone(),
+                request: request.clone(),
+                external_error_indicator: external_error_indicator.clone(),
+            }
+        });
+        self.live().map_or(Ok(()), |node| {
+            node.send_get_failure(src, dst, request, external_error_indicator, id)
+        })
+    }
+
+    pub fn send_put_success(&self,
+                            src: Authority,
+                            dst: Authority,
+                            request_hash: sha512::Digest,
+                            id: MessageId)
+                            -> Result<(), InterfaceError> {
+        self.sent_response(&src,
+                           &dst,
+                           || ResponseContent::PutSuccess(request_hash, id.clone()));
+        self.live().map_or(Ok(()), |node| node.send_put_success(src, dst, request_hash, id))
+    }
+
+    pub fn send_put_failure(&self,
+                            src: Authority,
+                            dst: Authority,
+                            request: RequestMessage,
+                            external_error_indicator: Vec<u8>,
+                            id: MessageId)
+                            -> Result<(), InterfaceError> {
+        self.sent_response(&src, &dst, || {
+            ResponseContent::PutFailure {
+                id: id.clone(),
+                request: request.clone(),
+                external_error_indicator: external_error_indicator.clone(),
+            }
+        });
+        self.live().map_or(Ok(()), |node| {
+            node.send_put_failure(src, dst, request, external_error_indicator, id)
+        })
+    }
+
+    pub fn send_post_success(&self,
+                             src: Authority,
+                             dst: Authority,
+                             request_hash: sha512::Digest,
+                             id: MessageId)
+                             -> Result<(), InterfaceError> {
+        self.sent_response(&src,
+                           &dst,
+                           || ResponseContent::PostSuccess(request_hash, id.clone()));
+        self.live().map_or(Ok(()), |node| node.send_post_success(src, dst, request_hash, id))
+    }
+
+    pub fn send_post_failure(&self,
+                             src: Authority,
+                             dst: Authority,
+                             request: RequestMessage,
+                             external_error_indicator: Vec<u8>,
+                             id: MessageId)
+                             -> Result<(), InterfaceError> {
+        self.sent_response(&src, &dst, || {
+            ResponseContent::PostFailure {
+                id: id.clone(),
+                request: request.clone(),
+                external_error_indicator: external_error_indicator.clone(),
+            }
+        });
+        self.live().map_or(Ok(()), |node| {
+            node.send_post_failure(src, dst, request, external_error_indicator, id)
+        })
+    }
+
+    pub fn send_delete_success(&self,
+                               src: Authority,
+                               dst: Authority,
+                               request_hash: sha512::Digest,
+                               id: MessageId)
+                               -> Result<(), InterfaceError> {
+        self.sent_response(&src,
+                           &dst,
+                           || ResponseContent::DeleteSuccess(request_hash, id.clone()));
+        self.live().map_or(Ok(()), |node| node.send_delete_success(src, dst, request_hash, id))
+    }
+
+    pub fn send_delete_failure(&self,
+                               src: Authority,
+                               dst: Authority,
+                               request: RequestMessage,
+                               external_error_indicator: Vec<u8>,
+                               id: MessageId)
+                               -> Result<(), InterfaceError> {
+        self.sent_response(&src, &dst, || {
+            ResponseContent::DeleteFailure {
+                id: id.clone(),
+                request: request.clone(),
+                external_error_indicator: external_error_indicator.clone(),
+            }
+        });
+        self.live().map_or(Ok(()), |node| {
+            node.send_delete_failure(src, dst, request, external_error_indicator, id)
+        })
+    }
+
+    fn live(&self) -> Option<&routing::Node> {
+        match self.backend {
+            Backend::Live { ref routing_node, .. } => Some(routing_node),
+            Backend::Replay(_) => None,
+        }
+    }
+
+    fn sent_request<F>(&self, src: &Authority, dst: &Authority, content: F)
+        where F: FnOnce() -> RequestContent
+    {
+        self.sent(|| {
+            Record::SentRequest(RequestMessage {
+                src: src.clone(),
+                dst: dst.clone(),
+                content: content(),
+            })
+        })
+    }
+
+    fn sent_response<F>(&self, src: &Authority, dst: &Authority, content: F)
+        where F: FnOnce() -> ResponseContent
+    {
+        self.sent(|| {
+            Record::SentResponse(ResponseMessage {
+                src: src.clone(),
+                dst: dst.clone(),
+                content: content(),
+            })
+        })
+    }
+
+    // The record is only built if it's needed, to avoid copying every message sent otherwise.
+    fn sent<F: FnOnce() -> Record>(&self, record: F) {
+        match self.backend {
+            Backend::Live { journal: Some(ref journal), .. } => {
+                unwrap_result!(journal.lock()).append(record())
+            }
+            Backend::Live { journal: None, .. } => (),
+            Backend::Replay(ref replay) => replay.borrow_mut().sent.push(record()),
+        }
+    }
+}
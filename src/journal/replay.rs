@@ -0,0 +1,197 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use clock;
+use config_handler::Config;
+use error::InternalError;
+use rng;
+use routing::Event;
+use std::path::Path;
+use std::sync::mpsc;
+use super::{Record, read};
+use time::Duration;
+use vault::{RoutingNode, Vault};
+
+/// A recorded input after which the replayed vault's messages differed from the recorded ones.
+#[derive(Debug)]
+pub struct Difference {
+    pub elapsed_ms: u64,
+    pub input: Record,
+    /// Messages recorded but not sent when replaying.
+    pub missing: Vec<Record>,
+    /// Messages sent when replaying but not recorded.
+    pub unexpected: Vec<Record>,
+}
+
+/// Replays the journal at `path` into a new vault created with `config`, returning every input
+/// after which the messages sent differ from the recorded ones.  The messages sent in response to
+/// each input are compared regardless of their order.
+pub fn replay(path: &Path, config: Config) -> Result<Vec<Difference>, InternalError> {
+    let entries = try!(read(path));
+    info!("Replaying {} entries from {}", entries.len(), path.display());
+    let mut vault = try!(Vault::new(None, mpsc::channel().1, config));
+    let node = RoutingNode::replay();
+    let mut differences = Vec::new();
+    let mut elapsed_ms = 0;
+    let mut index = 0;
+    while index < entries.len() {
+        // Each input is followed by the queries answered and messages sent while handling it.
+        let end = entries[index + 1..]
+                      .iter()
+                      .position(|entry| is_input(&entry.record))
+                      .map_or(entries.len(), |position| index + 1 + position);
+        let outputs = &entries[index + 1..end];
+        for entry in outputs {
+            apply_answer(&node, &entry.record);
+        }
+
+        let input = &entries[index];
+        index = end;
+        if !is_input(&input.record) {
+            // Answers recorded before the first input.
+            apply_answer(&node, &input.record);
+            continue;
+        }
+
+        if input.elapsed_ms > elapsed_ms {
+            clock::advance(Duration::milliseconds((input.elapsed_ms - elapsed_ms) as i64));
+            elapsed_ms = input.elapsed_ms;
+        }
+        match to_event(&input.record) {
+            Some(event) => vault.handle_event(&node, event),
+            None => vault.handle_tick(&node),
+        }
+
+        let mut unexpected = node.take_sent();
+        let mut missing = Vec::new();
+        for entry in outputs.iter().filter(|entry| is_sent(&entry.record)) {
+            match unexpected.iter().position(|sent| *sent == entry.record) {
+                Some(position) => {
+                    let _ = unexpected.remove(position);
+                }
+                None => missing.push(entry.record.clone()),
+            }
+        }
+        if !missing.is_empty() || !unexpected.is_empty() {
+            differences.push(Difference {
+                elapsed_ms: input.elapsed_ms,
+                input: input.record.clone(),
+                missing: missing,
+                unexpected: unexpected,
+            });
+        }
+    }
+    Ok(differences)
+}
+
+// Makes the node answer queries as recorded, and seeds the personas' RNG as recorded.
+fn apply_answer(node: &RoutingNode, record: &Record) {
+    match *record {
+        Record::Seed(seed) => rng::seed(seed),
+        _ => node.apply_answer(record),
+    }
+}
+
+fn is_input(record: &Record) -> bool {
+    match *record {
+        Record::Seed(_) |
+        Record::Name(_) |
+        Record::CloseGroup(..) |
+        Record::SentRequest(_) |
+        Record::SentResponse(_) => false,
+        _ => true,
+    }
+}
+
+fn is_sent(record: &Record) -> bool {
+    match *record {
+        Record::SentRequest(_) |
+        Record::SentResponse(_) => true,
+        _ => false,
+    }
+}
+
+// Returns the routing event for an input, or `None` for a tick.
+fn to_event(record: &Record) -> Option<Event> {
+    match *record {
+        Record::Request(ref request) => Some(Event::Request(request.clone())),
+        Record::Response(ref response) => Some(Event::Response(response.clone())),
+        Record::NodeAdded(node_added) => Some(Event::NodeAdded(node_added)),
+        Record::NodeLost(node_lost) => Some(Event::NodeLost(node_lost)),
+        Record::Connected => Some(Event::Connected),
+        Record::Disconnected => Some(Event::Disconnected),
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use journal::{Journal, Record, read};
+    use rand::random;
+    use routing::{Authority, Data, Event, ImmutableData, ImmutableDataType, MessageId,
+                  RequestContent, RequestMessage};
+    use std::sync::{Arc, Mutex, mpsc};
+    use utils::{self, generate_random_vec_u8};
+    use vault::{RoutingNode, Vault};
+
+    #[test]
+    fn replay_repeats_recorded_messages() {
+        let config = utils::test_config();
+        unwrap_result!(::std::fs::create_dir_all(config.root_dir()));
+        let path = config.root_dir().join("journal");
+        {
+            let journal = Arc::new(Mutex::new(unwrap_result!(Journal::create(&path))));
+            let mut vault = unwrap_result!(Vault::new(None, mpsc::channel().1, config.clone()));
+            vault.record_to(journal.clone());
+            let routing_node = unwrap_result!(RoutingNode::with_journal(mpsc::channel().0,
+                                                                        journal));
+            let data;
+            loop {
+                let candidate = ImmutableData::new(ImmutableDataType::Normal,
+                                                   generate_random_vec_u8(1024));
+                if unwrap_result!(routing_node.close_group(candidate.name())).is_some() {
+                    data = candidate;
+                    break;
+                }
+            }
+
+            // Storing the chunk sends its copies to the holders with new message IDs.
+            let request = RequestMessage {
+                src: Authority::ClientManager(random()),
+                dst: Authority::NaeManager(data.name()),
+                content: RequestContent::Put(Data::Immutable(data), MessageId::new()),
+            };
+            vault.handle_event(&routing_node, Event::Request(request));
+        }
+
+        let entries = unwrap_result!(read(&path));
+        let puts_sent = entries.iter()
+                               .filter(|entry| {
+                                   match entry.record {
+                                       Record::SentRequest(RequestMessage {
+                                           content: RequestContent::Put(..), ..
+                                       }) => true,
+                                       _ => false,
+                                   }
+                               })
+                               .count();
+        assert!(puts_sent > 0);
+        let differences = unwrap_result!(replay(&path, utils::test_config()));
+        assert!(differences.is_empty(), "{:?}", differences);
+    }
+}
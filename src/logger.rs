@@ -0,0 +1,127 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! The vault's logger.
+//!
+//! Messages at or above the current level are written to stderr, and to a log file if one was
+//! given.  The level starts as the one named by the `RUST_LOG` environment variable (`warn` if
+//! unset) and can be changed while the vault runs, e.g. by the `log_level` admin command.
+
+use log::{self, Log, LogLevelFilter, LogMetadata, LogRecord};
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use time;
+
+const DEFAULT_LEVEL: LogLevelFilter = LogLevelFilter::Warn;
+const LEVELS: [LogLevelFilter; 6] = [LogLevelFilter::Off,
+                                     LogLevelFilter::Error,
+                                     LogLevelFilter::Warn,
+                                     LogLevelFilter::Info,
+                                     LogLevelFilter::Debug,
+                                     LogLevelFilter::Trace];
+
+// The index in `LEVELS` of the current level.
+static LEVEL: AtomicUsize = ATOMIC_USIZE_INIT;
+
+struct Logger {
+    file: Option<Mutex<File>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= level()
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{} {} [{}:{}] {}",
+                           record.level(),
+                           time::now_utc().rfc3339(),
+                           record.location().module_path(),
+                           record.location().line(),
+                           record.args());
+        let _ = writeln!(io::stderr(), "{}", line);
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+}
+
+/// Installs the logger, also writing to `log_file` if given.  Any existing file is truncated.
+pub fn init(log_file: Option<&str>) -> Result<(), String> {
+    let level = match env::var("RUST_LOG") {
+        Ok(name) => try!(parse_level(&name)),
+        Err(_) => DEFAULT_LEVEL,
+    };
+    set_level(level);
+    let file = match log_file {
+        Some(path) => {
+            let file = try!(File::create(path).map_err(|error| {
+                format!("Failed to create log file {}: {}", path, error)
+            }));
+            Some(Mutex::new(file))
+        }
+        None => None,
+    };
+    // Every message is passed to the logger, which applies the current level itself.
+    log::set_logger(|max_log_level| {
+        max_log_level.set(LogLevelFilter::Trace);
+        Box::new(Logger { file: file })
+    })
+        .map_err(|error| format!("Failed to initialise logger: {}", error))
+}
+
+/// Returns the level named by `name`, e.g. "debug".
+pub fn parse_level(name: &str) -> Result<LogLevelFilter, String> {
+    name.parse().map_err(|()| {
+        format!("Invalid log level {:?}: expected one of off, error, warn, info, debug or trace",
+                name)
+    })
+}
+
+pub fn level() -> LogLevelFilter {
+    LEVELS[LEVEL.load(Ordering::Relaxed)]
+}
+
+pub fn set_level(level: LogLevelFilter) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use log::LogLevelFilter;
+
+    #[test]
+    fn change_level() {
+        let initial_level = level();
+        assert_eq!(unwrap_result!(parse_level("DEBUG")), LogLevelFilter::Debug);
+        assert!(parse_level("verbose").is_err());
+        for &new_level in &[LogLevelFilter::Off, LogLevelFilter::Trace, LogLevelFilter::Info] {
+            set_level(new_level);
+            assert_eq!(level(), new_level);
+        }
+        set_level(initial_level);
+    }
+}
@@ -51,25 +51,37 @@ extern crate ctrlc;
 extern crate docopt;
 #[cfg(all(test, feature = "use-mock-routing"))]
 extern crate kademlia_routing_table;
-extern crate lru_time_cache;
 extern crate mpid_messaging;
-#[cfg(all(test, feature = "use-mock-routing"))]
 extern crate rand;
 extern crate routing;
 extern crate rustc_serialize;
 extern crate sodiumoxide;
 extern crate time;
+#[cfg(unix)]
+extern crate unix_socket;
 extern crate xor_name;
 
+#[cfg(unix)]
+mod admin;
+mod clock;
+mod config_handler;
 mod default_chunk_store;
 mod error;
+mod expiring_map;
+mod journal;
+mod logger;
+mod metrics;
 mod mock_routing;
 mod personas;
+mod rng;
+mod state_store;
 mod types;
 mod utils;
 mod vault;
 
 use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 use docopt::Docopt;
 
@@ -77,18 +89,73 @@ use docopt::Docopt;
 static USAGE: &'static str = "
 Usage:
   safe_vault [options]
+  safe_vault admin [options] <command> [<argument>]
+  safe_vault replay [options] <file>
+
+Admin commands, sent to a running vault:
+  accounts                      List the accounts held by each persona.
+  chunk_stores                  Show the used and maximum space of each chunk
+                                store.
+  ongoing                       Show the requests each persona is waiting on.
+  metrics                       Show the vault's metrics in the Prometheus text
+                                format.
+  close_group <name>            Show the close group of the hex-encoded <name>.
+  log_level <level>             Change the log level to one of off, error, warn,
+                                info, debug or trace.
+  drain                         Hand off the vault's state to the network, then
+                                stop it.
+  shutdown                      Stop the vault immediately.
+
+Replaying feeds the events recorded in <file> by --record into a new vault, and
+lists the messages sent which differ from those recorded.  Unless --root-dir is
+given, the vault starts with empty state in a temporary directory.
+
+Messages are logged at the level named by the RUST_LOG environment variable, or
+warn if it's unset, until changed by the log_level admin command.
 
 Options:
   -o <file>, --output=<file>    Direct log output to stderr _and_ <file>.  If
                                 <file> does not exist it will be created,
                                 otherwise it will be truncated.
+  --root-dir=<dir>              Root directory for the vault's chunk stores and
+                                persisted state.  Overrides the value in the
+                                config file.
+  --pmid-node-capacity=<bytes>  Overrides the PmidNode's chunk store capacity.
+  --sd-capacity=<bytes>         Overrides the StructuredDataManager's chunk
+                                store capacity.
+  --inbox-capacity=<bytes>      Overrides the MpidManager's inbox chunk store
+                                capacity.
+  --outbox-capacity=<bytes>     Overrides the MpidManager's outbox chunk store
+                                capacity.
+  --tick-interval=<ms>          Overrides the interval between the personas'
+                                time-based maintenance.
+  --admin-socket=<path>         Path of the vault's admin socket.  Overrides the
+                                value in the config file.
+  --drain-timeout=<secs>        Overrides the maximum time a stopping vault waits
+                                for its chunks to be re-homed.
+  --record=<file>               Record the vault's events and the messages it
+                                sends to <file>, for replaying later.
   -V, --version                 Display version info and exit.
   -h, --help                    Display this help message and exit.
 ";
 
 #[derive(PartialEq, Eq, Debug, Clone, RustcDecodable)]
 struct Args {
+    cmd_admin: bool,
+    cmd_replay: bool,
+    arg_command: String,
+    arg_argument: Option<String>,
+    arg_file: String,
     flag_output: Option<String>,
+    flag_root_dir: Option<String>,
+    flag_pmid_node_capacity: Option<u64>,
+    flag_sd_capacity: Option<u64>,
+    flag_inbox_capacity: Option<u64>,
+    flag_outbox_capacity: Option<u64>,
+    flag_tick_interval: Option<u64>,
+    flag_admin_socket: Option<String>,
+    flag_drain_timeout: Option<u64>,
+    flag_record: Option<String>,
     flag_version: bool,
     flag_help: bool,
 }
@@ -106,15 +173,142 @@ pub fn main() {
         process::exit(0);
     }
 
-    if let Some(log_file) = args.flag_output {
-        unwrap_result!(maidsafe_utilities::log::init_to_file(false, log_file));
-    } else {
-        maidsafe_utilities::log::init(false);
+    if args.cmd_admin {
+        let (command, argument) = (args.arg_command.clone(), args.arg_argument.clone());
+        let mut config = read_config();
+        apply_overrides(&mut config, args);
+        run_admin_command(&config, command, argument);
     }
 
+    unwrap_result!(logger::init(args.flag_output.as_ref().map(|log_file| &log_file[..])));
+
     let message = String::from("Running ") + &name_and_version;
     let underline = String::from_utf8(vec!['=' as u8; message.len()]).unwrap();
     info!("\n\n{}\n{}", message, underline);
 
-    vault::Vault::run();
+    let replay_path = if args.cmd_replay {
+        Some(PathBuf::from(&args.arg_file))
+    } else {
+        None
+    };
+    let record_path = args.flag_record.clone().map(PathBuf::from);
+    let root_dir_given = args.flag_root_dir.is_some();
+    let mut config = read_config();
+    apply_overrides(&mut config, args);
+    info!("Using {:?}", config);
+
+    if let Some(replay_path) = replay_path {
+        run_replay(&replay_path, config, root_dir_given);
+    }
+    vault::Vault::run(config, record_path.as_ref().map(|path| path.as_path()));
+}
+
+// Reads the config file, exiting if it's invalid.
+fn read_config() -> config_handler::Config {
+    match config_handler::read_config_file() {
+        Ok(config) => config,
+        Err(error) => {
+            let _ = writeln!(io::stderr(), "Failed to read the config file: {:?}", error);
+            process::exit(1);
+        }
+    }
+}
+
+// Command line options take precedence over the values in the config file.
+fn apply_overrides(config: &mut config_handler::Config, args: Args) {
+    if args.flag_root_dir.is_some() {
+        config.root_dir = args.flag_root_dir;
+    }
+    if args.flag_pmid_node_capacity.is_some() {
+        config.pmid_node_capacity = args.flag_pmid_node_capacity;
+    }
+    if args.flag_sd_capacity.is_some() {
+        config.structured_data_manager_capacity = args.flag_sd_capacity;
+    }
+    if args.flag_inbox_capacity.is_some() {
+        config.mpid_manager_inbox_capacity = args.flag_inbox_capacity;
+    }
+    if args.flag_outbox_capacity.is_some() {
+        config.mpid_manager_outbox_capacity = args.flag_outbox_capacity;
+    }
+    if args.flag_tick_interval.is_some() {
+        config.tick_interval_ms = args.flag_tick_interval;
+    }
+    if args.flag_admin_socket.is_some() {
+        config.admin_socket = args.flag_admin_socket;
+    }
+    if args.flag_drain_timeout.is_some() {
+        config.drain_timeout_secs = args.flag_drain_timeout;
+    }
+}
+
+// Sends a command to the running vault's admin socket, prints the result and exits.
+#[cfg(unix)]
+fn run_admin_command(config: &config_handler::Config,
+                     command: String,
+                     argument: Option<String>)
+                     -> ! {
+    let request = admin::AdminRequest {
+        command: command,
+        argument: argument,
+    };
+    match admin::send_request(&config.admin_socket_path(), &request) {
+        Ok(rustc_serialize::json::Json::String(text)) => {
+            print!("{}", text);
+            process::exit(0);
+        }
+        Ok(result) => {
+            println!("{}", result.pretty());
+            process::exit(0);
+        }
+        Err(error) => {
+            let _ = writeln!(io::stderr(), "{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+// The replaying vault gets a fresh root directory unless one was given on the command line, so
+// that the state of any vault using the same config file is left alone.
+fn run_replay(path: &Path, mut config: config_handler::Config, root_dir_given: bool) -> ! {
+    if !root_dir_given {
+        let root_dir = std::env::temp_dir()
+                           .join(format!("safe_vault_replay_{:016x}", rand::random::<u64>()));
+        config.root_dir = Some(root_dir.to_string_lossy().into_owned());
+    }
+    match journal::replay(path, config) {
+        Ok(differences) => {
+            for difference in &differences {
+                println!("After {:?} at {} ms:",
+                         difference.input,
+                         difference.elapsed_ms);
+                for record in &difference.missing {
+                    println!("  missing:    {:?}", record);
+                }
+                for record in &difference.unexpected {
+                    println!("  unexpected: {:?}", record);
+                }
+            }
+            println!("{} inputs were followed by differing messages.",
+                     differences.len());
+            process::exit(if differences.is_empty() {
+                0
+            } else {
+                1
+            });
+        }
+        Err(error) => {
+            let _ = writeln!(io::stderr(), "Failed to replay {}: {:?}", path.display(), error);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn run_admin_command(_config: &config_handler::Config,
+                     _command: String,
+                     _argument: Option<String>)
+                     -> ! {
+    let _ = writeln!(io::stderr(), "Admin commands are only supported on Unix.");
+    process::exit(1);
 }
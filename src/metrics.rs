@@ -0,0 +1,149 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Counters of the messages handled by the personas, rendered along with the personas' stats in
+//! the Prometheus text exposition format.
+
+use routing::{RequestContent, ResponseContent};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const PREFIX: &'static str = "safe_vault_";
+
+pub struct Metrics {
+    // Keyed by metric name, then by the metric's rendered labels.
+    counters: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics { counters: BTreeMap::new() }
+    }
+
+    pub fn increment(&mut self, name: &str, labels: &[(&str, &str)]) {
+        let series = self.counters
+                         .entry(format!("{}{}", PREFIX, name))
+                         .or_insert_with(BTreeMap::new);
+        *series.entry(render_labels(labels)).or_insert(0) += 1;
+    }
+
+    /// Renders the counters, followed by `stats` as `(persona name, stat name, value)` and
+    /// `chunk_stores` as `(persona name, chunk store name, used bytes, maximum bytes)`.  A stat
+    /// whose name ends in `_total` is exported as a counter, and any other as a gauge.
+    pub fn render(&self,
+                  stats: &[(&'static str, &'static str, u64)],
+                  chunk_stores: &[(&'static str, &'static str, u64, u64)])
+                  -> String {
+        let mut gauges = BTreeMap::new();
+        {
+            let mut set = |name: &str, labels: &[(&str, &str)], value: u64| {
+                let _ = gauges.entry(format!("{}{}", PREFIX, name))
+                              .or_insert_with(BTreeMap::new)
+                              .insert(render_labels(labels), value);
+            };
+            for &(persona_name, stat_name, value) in stats {
+                set(stat_name, &[("persona", persona_name)], value);
+            }
+            for &(persona_name, store_name, used_space, max_space) in chunk_stores {
+                let labels = [("persona", persona_name), ("store", store_name)];
+                set("chunk_store_used_bytes", &labels, used_space);
+                set("chunk_store_max_bytes", &labels, max_space);
+            }
+        }
+
+        let mut text = String::new();
+        for (name, series) in self.counters.iter().chain(gauges.iter()) {
+            let metric_type = if name.ends_with("_total") {
+                "counter"
+            } else {
+                "gauge"
+            };
+            let _ = writeln!(text, "# TYPE {} {}", name, metric_type);
+            for (labels, value) in series {
+                let _ = writeln!(text, "{}{} {}", name, labels, value);
+            }
+        }
+        text
+    }
+}
+
+pub fn request_kind(content: &RequestContent) -> &'static str {
+    match *content {
+        RequestContent::Get(..) => "get",
+        RequestContent::Put(..) => "put",
+        RequestContent::Post(..) => "post",
+        RequestContent::Delete(..) => "delete",
+        RequestContent::Refresh(..) => "refresh",
+        _ => "other",
+    }
+}
+
+pub fn response_kind(content: &ResponseContent) -> &'static str {
+    match *content {
+        ResponseContent::GetSuccess(..) => "get_success",
+        ResponseContent::GetFailure { .. } => "get_failure",
+        ResponseContent::PutSuccess(..) => "put_success",
+        ResponseContent::PutFailure { .. } => "put_failure",
+        ResponseContent::PostSuccess(..) => "post_success",
+        ResponseContent::PostFailure { .. } => "post_failure",
+        ResponseContent::DeleteSuccess(..) => "delete_success",
+        ResponseContent::DeleteFailure { .. } => "delete_failure",
+        _ => "other",
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let pairs = labels.iter()
+                      .map(|&(name, value)| {
+                          format!("{}=\"{}\"",
+                                  name,
+                                  value.replace('\\', "\\\\").replace('"', "\\\""))
+                      })
+                      .collect::<Vec<_>>();
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let mut metrics = Metrics::new();
+        metrics.increment("requests_total", &[("persona", "MaidManager"), ("kind", "put")]);
+        metrics.increment("requests_total", &[("persona", "MaidManager"), ("kind", "put")]);
+        metrics.increment("requests_total", &[("persona", "PmidNode"), ("kind", "get")]);
+        let stats = [("ImmutableDataManager", "farming_rate", 7),
+                     ("PmidNode", "chunks_lost_total", 1)];
+        let chunk_stores = [("PmidNode", "chunks", 10, 20)];
+        assert_eq!(metrics.render(&stats, &chunk_stores),
+                   "# TYPE safe_vault_requests_total counter\n\
+                    safe_vault_requests_total{persona=\"MaidManager\",kind=\"put\"} 2\n\
+                    safe_vault_requests_total{persona=\"PmidNode\",kind=\"get\"} 1\n\
+                    # TYPE safe_vault_chunk_store_max_bytes gauge\n\
+                    safe_vault_chunk_store_max_bytes{persona=\"PmidNode\",store=\"chunks\"} 20\n\
+                    # TYPE safe_vault_chunk_store_used_bytes gauge\n\
+                    safe_vault_chunk_store_used_bytes{persona=\"PmidNode\",store=\"chunks\"} 10\n\
+                    # TYPE safe_vault_chunks_lost_total counter\n\
+                    safe_vault_chunks_lost_total{persona=\"PmidNode\"} 1\n\
+                    # TYPE safe_vault_farming_rate gauge\n\
+                    safe_vault_farming_rate{persona=\"ImmutableDataManager\"} 7\n");
+    }
+}
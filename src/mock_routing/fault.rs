@@ -0,0 +1,151 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Faults which a `MockNetwork` can inject into the messages it carries.
+
+use routing::{Authority, Event, RequestContent, ResponseContent};
+use time::Duration;
+
+/// What happens to a message matched by a `FaultPolicy`.
+#[derive(Clone, Copy, Debug)]
+pub enum Fault {
+    /// The message is never delivered.
+    Drop,
+    /// The message is delivered twice.
+    Duplicate,
+    /// The message is delivered this much later than usual.
+    Delay(Duration),
+    /// The message is delayed by a random amount up to this, so later messages may overtake it.
+    Reorder(Duration),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuthorityKind {
+    Client,
+    ClientManager,
+    NaeManager,
+    NodeManager,
+    ManagedNode,
+}
+
+impl<'a> From<&'a Authority> for AuthorityKind {
+    fn from(authority: &Authority) -> AuthorityKind {
+        match *authority {
+            Authority::Client { .. } => AuthorityKind::Client,
+            Authority::ClientManager(_) => AuthorityKind::ClientManager,
+            Authority::NaeManager(_) => AuthorityKind::NaeManager,
+            Authority::NodeManager(_) => AuthorityKind::NodeManager,
+            Authority::ManagedNode(_) => AuthorityKind::ManagedNode,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ContentKind {
+    Get,
+    Put,
+    Post,
+    Delete,
+    Refresh,
+    GetSuccess,
+    GetFailure,
+    PutSuccess,
+    PutFailure,
+    PostSuccess,
+    PostFailure,
+    DeleteSuccess,
+    DeleteFailure,
+}
+
+fn request_kind(content: &RequestContent) -> Option<ContentKind> {
+    match *content {
+        RequestContent::Get(..) => Some(ContentKind::Get),
+        RequestContent::Put(..) => Some(ContentKind::Put),
+        RequestContent::Post(..) => Some(ContentKind::Post),
+        RequestContent::Delete(..) => Some(ContentKind::Delete),
+        RequestContent::Refresh(..) => Some(ContentKind::Refresh),
+        _ => None,
+    }
+}
+
+fn response_kind(content: &ResponseContent) -> Option<ContentKind> {
+    match *content {
+        ResponseContent::GetSuccess(..) => Some(ContentKind::GetSuccess),
+        ResponseContent::GetFailure { .. } => Some(ContentKind::GetFailure),
+        ResponseContent::PutSuccess(..) => Some(ContentKind::PutSuccess),
+        ResponseContent::PutFailure { .. } => Some(ContentKind::PutFailure),
+        ResponseContent::PostSuccess(..) => Some(ContentKind::PostSuccess),
+        ResponseContent::PostFailure { .. } => Some(ContentKind::PostFailure),
+        ResponseContent::DeleteSuccess(..) => Some(ContentKind::DeleteSuccess),
+        ResponseContent::DeleteFailure { .. } => Some(ContentKind::DeleteFailure),
+        _ => None,
+    }
+}
+
+/// Selects messages by source and destination authority and by content.  Unset criteria match
+/// every message.
+#[derive(Clone, Debug, Default)]
+pub struct MessageFilter {
+    src: Option<AuthorityKind>,
+    dst: Option<AuthorityKind>,
+    content: Option<ContentKind>,
+}
+
+impl MessageFilter {
+    /// A filter matching every message.
+    pub fn any() -> MessageFilter {
+        MessageFilter::default()
+    }
+
+    pub fn from(mut self, src: AuthorityKind) -> MessageFilter {
+        self.src = Some(src);
+        self
+    }
+
+    pub fn to(mut self, dst: AuthorityKind) -> MessageFilter {
+        self.dst = Some(dst);
+        self
+    }
+
+    pub fn content(mut self, content: ContentKind) -> MessageFilter {
+        self.content = Some(content);
+        self
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        let (src, dst, content) = match *event {
+            Event::Request(ref request) => {
+                (&request.src, &request.dst, request_kind(&request.content))
+            }
+            Event::Response(ref response) => {
+                (&response.src, &response.dst, response_kind(&response.content))
+            }
+            _ => return false,
+        };
+        self.src.map_or(true, |kind| kind == AuthorityKind::from(src)) &&
+        self.dst.map_or(true, |kind| kind == AuthorityKind::from(dst)) &&
+        self.content.map_or(true, |kind| content == Some(kind))
+    }
+}
+
+/// Applies `fault` to each message matching `filter`, with the given probability.
+#[derive(Clone, Debug)]
+pub struct FaultPolicy {
+    pub filter: MessageFilter,
+    pub fault: Fault,
+    pub probability: f64,
+}
@@ -0,0 +1,534 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! An in-process network of vaults.
+//!
+//! Every vault gets a `MockRoutingNode` which queues the messages it sends on the shared network.
+//! `MockNetwork::poll` then delivers each queued message to the vaults which are actually in the
+//! destination authority, on the calling thread, until there are none left.
+//!
+//! Messages are delivered after a simulated latency measured on the virtual `clock`, which `poll`
+//! advances as it goes rather than sleeping.  Vault names and the personas' message IDs, nonces
+//! and audit choices come from RNGs seeded with the same seed, so a run can be replayed exactly by
+//! passing the seed it logged to `MockNetwork::with_seed`.
+//!
+//! Tests can add `FaultPolicy`s to drop, duplicate, delay or reorder selected messages.
+
+use clock;
+use kademlia_routing_table::GROUP_SIZE;
+use maidsafe_utilities::serialisation;
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+use rng;
+use routing::{Authority, Event, RequestContent, RequestMessage, ResponseMessage};
+use sodiumoxide::crypto::hash::sha512;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, mpsc};
+use super::MockRoutingNode;
+use super::fault::{Fault, FaultPolicy, MessageFilter};
+use time::{Duration, SteadyTime};
+use utils;
+use vault::Vault;
+use xor_name::{self, XorName};
+
+// Guards against messages endlessly triggering further messages.
+const MAX_DELIVERIES_PER_POLL: usize = 1_000_000;
+// Time taken for a message to reach its recipients, unless delayed by a fault.
+const LATENCY_MS: i64 = 200;
+
+enum Envelope {
+    // A request or response, to be delivered to the vaults in its destination authority.
+    Message(Event),
+    // A copy of a message added by a `Fault::Duplicate`, delivered even if the original has been.
+    Duplicate(Event),
+    // An event for a single vault.
+    Direct(XorName, Event),
+}
+
+/// The state shared between a `MockNetwork` and the routing nodes of its vaults.
+pub struct NetworkState {
+    names: Vec<XorName>,
+    // Envelopes paired with the virtual time they're due to be delivered and the round they were
+    // sent in, in delivery order.
+    queue: VecDeque<(SteadyTime, u64, Envelope)>,
+    client_responses: Vec<ResponseMessage>,
+    // Each member of a group sends its own copy of a group message, but routing only delivers it
+    // once to each recipient.  The members send their copies while handling the same delivery,
+    // client request, tick or churn - the same round - so these are the rounds and hashes of the
+    // messages delivered to each vault.  An identical message sent in a later round, such as a
+    // repeated refresh, is delivered again.
+    delivered: HashSet<(XorName, u64, Vec<u8>)>,
+    // The round which messages are currently being sent in, and the next unused round.
+    round: u64,
+    next_round: u64,
+    faults: Vec<FaultPolicy>,
+    rng: XorShiftRng,
+}
+
+impl NetworkState {
+    pub fn send(&mut self, event: Event) {
+        let mut delay = Duration::zero();
+        let mut duplicate = false;
+        for policy in &self.faults {
+            if !policy.filter.matches(&event) || self.rng.gen::<f64>() >= policy.probability {
+                continue;
+            }
+            match policy.fault {
+                Fault::Drop => {
+                    trace!("Dropping {:?}", event);
+                    return;
+                }
+                Fault::Duplicate => duplicate = true,
+                Fault::Delay(extra) => delay = delay + extra,
+                Fault::Reorder(max) => {
+                    let extra = self.rng.gen_range(0, max.num_milliseconds() + 1);
+                    delay = delay + Duration::milliseconds(extra);
+                }
+            }
+        }
+        if duplicate {
+            self.push(Envelope::Duplicate(event.clone()), delay);
+        }
+        self.push(Envelope::Message(event), delay);
+    }
+
+    /// Returns the close group of `target`, if `our_name` is a member of it.
+    pub fn close_group(&self, our_name: &XorName, target: &XorName) -> Option<Vec<XorName>> {
+        let group = self.group(target);
+        if group.contains(our_name) {
+            Some(group)
+        } else {
+            None
+        }
+    }
+
+    // Queues `envelope` in the current round, after any others due for delivery at the same time
+    // or earlier.
+    fn push(&mut self, envelope: Envelope, delay: Duration) {
+        let deliver_at = clock::now() + Duration::milliseconds(LATENCY_MS) + delay;
+        let index = self.queue
+                        .iter()
+                        .position(|&(queued_at, _, _)| queued_at > deliver_at)
+                        .unwrap_or(self.queue.len());
+        self.queue.insert(index, (deliver_at, self.round, envelope));
+    }
+
+    // Starts a new round for the messages sent from now on.
+    fn start_round(&mut self) {
+        self.round = self.next_round;
+        self.next_round += 1;
+    }
+
+    fn group(&self, target: &XorName) -> Vec<XorName> {
+        let mut names = self.names.clone();
+        names.sort_by(|lhs, rhs| {
+            if xor_name::closer_to_target(lhs, rhs, target) {
+                ::std::cmp::Ordering::Less
+            } else {
+                ::std::cmp::Ordering::Greater
+            }
+        });
+        names.truncate(GROUP_SIZE);
+        names
+    }
+}
+
+/// A network of vaults, all run on the thread which calls `poll`.
+pub struct MockNetwork {
+    state: Arc<Mutex<NetworkState>>,
+    vaults: HashMap<XorName, (Vault, MockRoutingNode)>,
+}
+
+impl MockNetwork {
+    /// Creates a network of `vault_count` vaults with a random seed, and delivers the resulting
+    /// churn events.
+    pub fn new(vault_count: usize) -> MockNetwork {
+        let seed = [rand::random(), rand::random(), rand::random(), rand::random()];
+        info!("Creating mock network with seed {:?}", seed);
+        MockNetwork::with_seed(vault_count, seed)
+    }
+
+    /// Creates a network of `vault_count` vaults, naming them using an RNG seeded with `seed`.  The
+    /// personas' RNG on this thread is seeded with `seed` too.
+    pub fn with_seed(vault_count: usize, seed: [u32; 4]) -> MockNetwork {
+        rng::seed(seed);
+        let mut network = MockNetwork {
+            state: Arc::new(Mutex::new(NetworkState {
+                names: vec![],
+                queue: VecDeque::new(),
+                client_responses: vec![],
+                delivered: HashSet::new(),
+                round: 0,
+                next_round: 1,
+                faults: vec![],
+                rng: XorShiftRng::from_seed(seed),
+            })),
+            vaults: HashMap::new(),
+        };
+        for _ in 0..vault_count {
+            let _ = network.add_vault();
+        }
+        let _ = network.poll();
+        network
+    }
+
+    pub fn vault_names(&self) -> Vec<XorName> {
+        unwrap_result!(self.state.lock()).names.clone()
+    }
+
+    /// Returns the names of the vaults in the close group of `target`.
+    pub fn close_group(&self, target: &XorName) -> Vec<XorName> {
+        unwrap_result!(self.state.lock()).group(target)
+    }
+
+    /// Adds a new vault, and queues a `NodeAdded` event for each of the existing ones.
+    pub fn add_vault(&mut self) -> XorName {
+        let name = {
+            let mut state = unwrap_result!(self.state.lock());
+            let name = state.rng.gen();
+            // Every vault sees the churn in the same round, so their refreshes are deduplicated.
+            state.start_round();
+            for existing in state.names.clone() {
+                state.push(Envelope::Direct(existing, Event::NodeAdded(name)), Duration::zero());
+            }
+            state.names.push(name);
+            name
+        };
+        let routing_node = MockRoutingNode::new_in_network(name, self.state.clone());
+        let vault = unwrap_result!(Vault::new(None, mpsc::channel().1, utils::test_config()));
+        let _ = self.vaults.insert(name, (vault, routing_node));
+        name
+    }
+
+    /// Removes the vault called `name`, and queues a `NodeLost` event for each of the others.
+    pub fn remove_vault(&mut self, name: &XorName) {
+        let _ = self.vaults.remove(name);
+        let mut state = unwrap_result!(self.state.lock());
+        state.names = state.names.iter().filter(|existing| *existing != name).cloned().collect();
+        state.start_round();
+        for remaining in state.names.clone() {
+            state.push(Envelope::Direct(remaining, Event::NodeLost(*name)), Duration::zero());
+        }
+    }
+
+    /// Applies `fault` to every message subsequently sent which matches `filter`.
+    pub fn add_fault(&self, filter: MessageFilter, fault: Fault) {
+        self.add_fault_with_probability(filter, fault, 1.0)
+    }
+
+    /// Applies `fault` to each matching message with the given probability, using the network's
+    /// seeded RNG.
+    pub fn add_fault_with_probability(&self,
+                                      filter: MessageFilter,
+                                      fault: Fault,
+                                      probability: f64) {
+        unwrap_result!(self.state.lock()).faults.push(FaultPolicy {
+            filter: filter,
+            fault: fault,
+            probability: probability,
+        });
+    }
+
+    /// Removes all fault policies, so messages are delivered reliably again.
+    pub fn clear_faults(&self) {
+        unwrap_result!(self.state.lock()).faults.clear();
+    }
+
+    /// Queues a request from a client.
+    pub fn send_client_request(&self, src: Authority, dst: Authority, content: RequestContent) {
+        let mut state = unwrap_result!(self.state.lock());
+        state.start_round();
+        state.send(Event::Request(RequestMessage {
+            src: src,
+            dst: dst,
+            content: content,
+        }));
+    }
+
+    /// Returns and clears the responses sent to clients so far.
+    pub fn take_client_responses(&self) -> Vec<ResponseMessage> {
+        let mut state = unwrap_result!(self.state.lock());
+        ::std::mem::replace(&mut state.client_responses, vec![])
+    }
+
+    /// Moves the virtual clock forward by `duration`, gives every vault a tick so it can act on
+    /// any timeouts, then delivers whatever they sent.  Returns the number of deliveries made.
+    pub fn advance_time(&mut self, duration: Duration) -> usize {
+        clock::advance(duration);
+        unwrap_result!(self.state.lock()).start_round();
+        for name in self.vault_names() {
+            if let Some(&mut (ref mut vault, ref routing_node)) = self.vaults.get_mut(&name) {
+                vault.handle_tick(routing_node);
+            }
+        }
+        self.poll()
+    }
+
+    /// Delivers queued messages and events, including any sent while handling them, until the
+    /// queue is empty, advancing the virtual clock to each delivery time.  Returns the number of
+    /// deliveries made.
+    pub fn poll(&mut self) -> usize {
+        let mut deliveries = 0;
+        loop {
+            let (recipients, event) = match self.next_delivery() {
+                Some(delivery) => delivery,
+                None => return deliveries,
+            };
+            for recipient in recipients {
+                if let Some(&mut (ref mut vault, ref routing_node)) = self.vaults
+                                                                          .get_mut(&recipient) {
+                    vault.handle_event(routing_node, event.clone());
+                    deliveries += 1;
+                }
+            }
+            assert!(deliveries < MAX_DELIVERIES_PER_POLL,
+                    "Network failed to settle after {} deliveries",
+                    deliveries);
+        }
+    }
+
+    // Pops the next envelope, returning the vaults it should be delivered to and starting the round
+    // for whatever they send in response.  Responses to clients are stored instead.  Once the queue
+    // is empty every round is complete, so the record of deliveries is cleared.
+    fn next_delivery(&self) -> Option<(Vec<XorName>, Event)> {
+        let mut state = unwrap_result!(self.state.lock());
+        while let Some((deliver_at, round, envelope)) = state.queue.pop_front() {
+            let now = clock::now();
+            if deliver_at > now {
+                clock::advance(deliver_at - now);
+            }
+            let (event, duplicate) = match envelope {
+                Envelope::Direct(name, event) => {
+                    // Direct events queued together, e.g. a churn seen by every vault, stay in
+                    // their round.
+                    state.round = round;
+                    return Some((vec![name], event));
+                }
+                Envelope::Message(event) => (event, false),
+                Envelope::Duplicate(event) => (event, true),
+            };
+            let (dst, serialised_message) = match event {
+                Event::Request(ref request) => {
+                    (request.dst.clone(), unwrap_result!(serialisation::serialise(request)))
+                }
+                Event::Response(ref response) => {
+                    if let Authority::Client { .. } = response.dst {
+                        state.client_responses.push(response.clone());
+                        continue;
+                    }
+                    (response.dst.clone(), unwrap_result!(serialisation::serialise(response)))
+                }
+                ref event => unreachable!("Unexpected message {:?}", event),
+            };
+
+            let candidates = match dst {
+                Authority::ClientManager(ref name) |
+                Authority::NaeManager(ref name) |
+                Authority::NodeManager(ref name) => state.group(name),
+                Authority::ManagedNode(ref name) => vec![*name],
+                Authority::Client { .. } => vec![],
+            };
+            let hash = sha512::hash(&serialised_message).0.to_vec();
+            let mut recipients = vec![];
+            for candidate in candidates {
+                if state.names.contains(&candidate) &&
+                   (state.delivered.insert((candidate, round, hash.clone())) || duplicate) {
+                    recipients.push(candidate);
+                }
+            }
+            if !recipients.is_empty() {
+                state.start_round();
+                return Some((recipients, event));
+            }
+        }
+        state.delivered.clear();
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kademlia_routing_table::GROUP_SIZE;
+    use maidsafe_utilities::log;
+    use mock_routing::{AuthorityKind, ContentKind, Fault, MessageFilter};
+    use rand::random;
+    use routing::{Authority, Data, DataRequest, Event, ImmutableData, ImmutableDataType,
+                  MessageId, RequestContent, RequestMessage, ResponseContent, ResponseMessage,
+                  StructuredData};
+    use personas::immutable_data_manager::REPLICANTS;
+    use sodiumoxide::crypto::hash::sha512;
+    use sodiumoxide::crypto::sign;
+    use time::Duration;
+    use utils::generate_random_vec_u8;
+    use xor_name::XorName;
+
+    fn response_to(network: &MockNetwork, message_id: &MessageId) -> Option<ResponseMessage> {
+        let responses = network.take_client_responses();
+        responses.into_iter().find(|response| {
+            match response.content {
+                ResponseContent::GetSuccess(_, ref id) |
+                ResponseContent::PutSuccess(_, ref id) |
+                ResponseContent::GetFailure { ref id, .. } |
+                ResponseContent::PutFailure { ref id, .. } => id == message_id,
+                _ => false,
+            }
+        })
+    }
+
+    // Creates a client with an account, returning the client and its `ClientManager`.
+    fn create_client(network: &mut MockNetwork) -> (Authority, Authority) {
+        let client_key = sign::gen_keypair().0;
+        let client = Authority::Client {
+            client_key: client_key,
+            peer_id: random(),
+            proxy_node_name: random(),
+        };
+        let client_manager = Authority::ClientManager(XorName(sha512::hash(&client_key.0).0));
+        let account = unwrap_result!(StructuredData::new(0,
+                                                         random::<XorName>(),
+                                                         0,
+                                                         vec![],
+                                                         vec![],
+                                                         vec![],
+                                                         None));
+        match put(network, &client, &client_manager, Data::Structured(account)) {
+            Some(ResponseContent::PutSuccess(..)) => (),
+            content => panic!("Failed to create account: {:?}", content),
+        }
+        (client, client_manager)
+    }
+
+    fn put(network: &mut MockNetwork,
+           client: &Authority,
+           client_manager: &Authority,
+           data: Data)
+           -> Option<ResponseContent> {
+        let message_id = MessageId::new();
+        network.send_client_request(client.clone(),
+                                    client_manager.clone(),
+                                    RequestContent::Put(data, message_id));
+        let _ = network.poll();
+        response_to(network, &message_id).map(|response| response.content)
+    }
+
+    fn get(network: &mut MockNetwork,
+           client: &Authority,
+           data: &ImmutableData)
+           -> Option<ResponseContent> {
+        let message_id = MessageId::new();
+        let data_request = DataRequest::Immutable(data.name(), ImmutableDataType::Normal);
+        network.send_client_request(client.clone(),
+                                    Authority::NaeManager(data.name()),
+                                    RequestContent::Get(data_request, message_id));
+        let _ = network.poll();
+        response_to(network, &message_id).map(|response| response.content)
+    }
+
+    #[test]
+    fn seeded_networks_match() {
+        let seed = [random(), random(), random(), random()];
+        let first = MockNetwork::with_seed(GROUP_SIZE, seed);
+        let second = MockNetwork::with_seed(GROUP_SIZE, seed);
+        assert_eq!(first.vault_names(), second.vault_names());
+    }
+
+    #[test]
+    fn group_message_delivered_once_per_round() {
+        let mut network = MockNetwork::new(2 * GROUP_SIZE);
+        let name = random::<XorName>();
+        let refresh = Event::Request(RequestMessage {
+            src: Authority::NaeManager(name),
+            dst: Authority::NaeManager(name),
+            content: RequestContent::Refresh(vec![1, 2, 3]),
+        });
+        // Each member of the group sends its own copy in the same round.
+        let send_copies = |network: &MockNetwork| {
+            let mut state = unwrap_result!(network.state.lock());
+            state.start_round();
+            for _ in 0..GROUP_SIZE {
+                state.send(refresh.clone());
+            }
+        };
+        send_copies(&network);
+        assert_eq!(network.poll(), GROUP_SIZE);
+
+        // The same message sent in later rounds is delivered again, even before the earlier round
+        // has been delivered.
+        send_copies(&network);
+        send_copies(&network);
+        assert_eq!(network.poll(), 2 * GROUP_SIZE);
+    }
+
+    #[test]
+    fn immutable_data_churn() {
+        log::init(false);
+        let mut network = MockNetwork::new(2 * GROUP_SIZE);
+        let (client, client_manager) = create_client(&mut network);
+
+        let data = ImmutableData::new(ImmutableDataType::Normal, generate_random_vec_u8(1024));
+        match put(&mut network, &client, &client_manager, Data::Immutable(data.clone())) {
+            Some(ResponseContent::PutSuccess(..)) => (),
+            content => panic!("Failed to Put chunk: {:?}", content),
+        }
+
+        // Lose most of the chunk's holders, then a Get should still succeed.
+        for holder in network.close_group(&data.name()).iter().take(REPLICANTS - 1) {
+            network.remove_vault(holder);
+            let _ = network.add_vault();
+            let _ = network.poll();
+        }
+        match get(&mut network, &client, &data) {
+            Some(ResponseContent::GetSuccess(Data::Immutable(ref got), _)) => {
+                assert_eq!(*got, data)
+            }
+            content => panic!("Failed to Get chunk: {:?}", content),
+        }
+    }
+
+    #[test]
+    fn lossy_network() {
+        log::init(false);
+        let mut network = MockNetwork::new(2 * GROUP_SIZE);
+        let (client, client_manager) = create_client(&mut network);
+
+        // With the Put never reaching the chunk's managers, the client gets no response.
+        network.add_fault(MessageFilter::any()
+                              .from(AuthorityKind::ClientManager)
+                              .to(AuthorityKind::NaeManager)
+                              .content(ContentKind::Put),
+                          Fault::Drop);
+        let data = ImmutableData::new(ImmutableDataType::Normal, generate_random_vec_u8(1024));
+        assert!(put(&mut network, &client, &client_manager, Data::Immutable(data.clone()))
+                    .is_none());
+        network.clear_faults();
+
+        // Duplicated and reordered messages between the managers and the holders are harmless.
+        network.add_fault(MessageFilter::any().to(AuthorityKind::ManagedNode),
+                          Fault::Duplicate);
+        network.add_fault(MessageFilter::any(), Fault::Reorder(Duration::seconds(1)));
+        match put(&mut network, &client, &client_manager, Data::Immutable(data.clone())) {
+            Some(ResponseContent::PutSuccess(..)) => (),
+            content => panic!("Failed to Put chunk: {:?}", content),
+        }
+        match get(&mut network, &client, &data) {
+            Some(ResponseContent::GetSuccess(Data::Immutable(ref got), _)) => {
+                assert_eq!(*got, data)
+            }
+            content => panic!("Failed to Get chunk: {:?}", content),
+        }
+    }
+}
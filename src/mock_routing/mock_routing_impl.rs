@@ -15,16 +15,17 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+use journal::{Journal, Record};
 use kademlia_routing_table::{GROUP_SIZE, ContactInfo, RoutingTable};
-use maidsafe_utilities::thread::RaiiThreadJoiner;
+use super::mock_network::NetworkState;
 use rand::random;
 use routing::{Authority, Data, DataRequest, Event, InterfaceError, MessageId, RequestContent,
               RequestMessage, ResponseContent, ResponseMessage};
 use sodiumoxide::crypto::hash::sha512;
 use std::cmp::{Ordering, min};
-use std::sync::mpsc;
-use std::thread::sleep;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex, mpsc};
 use xor_name::{XorName, closer_to_target};
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -42,7 +43,6 @@ pub struct MockRoutingNodeImpl {
     routing_table: RoutingTable<NodeInfo>,
     sender: mpsc::Sender<Event>,
     client_sender: mpsc::Sender<Event>,
-    simulated_latency: Duration,
     get_requests_given: Vec<RequestMessage>,
     put_requests_given: Vec<RequestMessage>,
     post_requests_given: Vec<RequestMessage>,
@@ -56,24 +56,47 @@ pub struct MockRoutingNodeImpl {
     delete_successes_given: Vec<ResponseMessage>,
     delete_failures_given: Vec<ResponseMessage>,
     refresh_requests_given: Vec<RequestMessage>,
-    thread_joiners: Vec<RaiiThreadJoiner>,
+    // If this node is part of a `MockNetwork`, messages are queued on the network rather than
+    // looped back to `sender`, and close groups are calculated from the network's nodes.
+    network: Option<Arc<Mutex<NetworkState>>>,
+    // If recording, the journal to which the answers to the vault's queries and the messages it
+    // sends are appended, and the name last recorded.
+    journal: Option<Arc<Mutex<Journal>>>,
+    recorded_name: Option<XorName>,
+    // If replaying a journal, the recorded close groups, and the messages sent since last taken.
+    recorded_close_groups: Option<HashMap<XorName, Option<Vec<XorName>>>>,
+    replayed_sent: Vec<Record>,
 }
 
 impl MockRoutingNodeImpl {
     pub fn new(sender: mpsc::Sender<Event>) -> MockRoutingNodeImpl {
-        let (client_sender, _) = mpsc::channel();
         let name: XorName = random();
         let mut routing_table = RoutingTable::new(NodeInfo(name));
         for _ in 0..1000 {
             let _ = routing_table.add(NodeInfo(random()));
         }
 
+        Self::with_routing_table(name, routing_table, sender, None)
+    }
+
+    pub fn new_in_network(name: XorName,
+                          network: Arc<Mutex<NetworkState>>)
+                          -> MockRoutingNodeImpl {
+        let (sender, _) = mpsc::channel();
+        Self::with_routing_table(name, RoutingTable::new(NodeInfo(name)), sender, Some(network))
+    }
+
+    fn with_routing_table(name: XorName,
+                          routing_table: RoutingTable<NodeInfo>,
+                          sender: mpsc::Sender<Event>,
+                          network: Option<Arc<Mutex<NetworkState>>>)
+                          -> MockRoutingNodeImpl {
+        let (client_sender, _) = mpsc::channel();
         MockRoutingNodeImpl {
             name: name,
             routing_table: routing_table,
             sender: sender,
             client_sender: client_sender,
-            simulated_latency: Duration::from_millis(200),
             get_requests_given: vec![],
             put_requests_given: vec![],
             post_requests_given: vec![],
@@ -87,10 +110,44 @@ impl MockRoutingNodeImpl {
             delete_successes_given: vec![],
             delete_failures_given: vec![],
             refresh_requests_given: vec![],
-            thread_joiners: vec![],
+            network: network,
+            journal: None,
+            recorded_name: None,
+            recorded_close_groups: None,
+            replayed_sent: vec![],
+        }
+    }
+
+    pub fn with_journal(sender: mpsc::Sender<Event>,
+                        journal: Arc<Mutex<Journal>>)
+                        -> MockRoutingNodeImpl {
+        let mut routing_node = Self::new(sender);
+        routing_node.journal = Some(journal);
+        routing_node
+    }
+
+    pub fn replay() -> MockRoutingNodeImpl {
+        let mut routing_node = Self::new(mpsc::channel().0);
+        routing_node.recorded_close_groups = Some(HashMap::new());
+        routing_node
+    }
+
+    pub fn apply_answer(&mut self, record: &Record) {
+        if let Some(ref mut recorded_close_groups) = self.recorded_close_groups {
+            match *record {
+                Record::Name(name) => self.name = name,
+                Record::CloseGroup(name, ref close_group) => {
+                    let _ = recorded_close_groups.insert(name, close_group.clone());
+                }
+                _ => (),
+            }
         }
     }
 
+    pub fn take_sent(&mut self) -> Vec<Record> {
+        mem::replace(&mut self.replayed_sent, vec![])
+    }
+
     pub fn get_client_receiver(&mut self) -> mpsc::Receiver<Event> {
         let (client_sender, client_receiver) = mpsc::channel();
         self.client_sender = client_sender;
@@ -101,43 +158,33 @@ impl MockRoutingNodeImpl {
     pub fn client_get(&mut self, src: Authority, data_request: DataRequest) {
         let _ = self.send_request(src,
                                   Authority::NaeManager(data_request.name()),
-                                  RequestContent::Get(data_request, MessageId::new()),
-                                  "Mock Client Get Request");
+                                  RequestContent::Get(data_request, MessageId::new()));
     }
 
     pub fn client_put(&mut self, src: Authority, data: Data) {
         let _ = self.send_request(src,
                                   Authority::ClientManager(data.name()),
-                                  RequestContent::Put(data, MessageId::new()),
-                                  "Mock Client Put Request");
+                                  RequestContent::Put(data, MessageId::new()));
     }
 
     pub fn client_post(&mut self, src: Authority, data: Data) {
         let _ = self.send_request(src,
                                   Authority::NaeManager(data.name()),
-                                  RequestContent::Post(data, MessageId::new()),
-                                  "Mock Client Post Request");
+                                  RequestContent::Post(data, MessageId::new()));
     }
 
     pub fn client_delete(&mut self, src: Authority, data: Data) {
         let _ = self.send_request(src,
                                   Authority::ClientManager(data.name()),
-                                  RequestContent::Delete(data, MessageId::new()),
-                                  "Mock Client Delete Request");
+                                  RequestContent::Delete(data, MessageId::new()));
     }
 
     pub fn node_added_event(&mut self, node_added: XorName) {
-        let cloned_sender = self.sender.clone();
-        self.thread_joiners.push(RaiiThreadJoiner::new(thread!("Mock NodeAdded Event", move || {
-            let _ = cloned_sender.send(Event::NodeAdded(node_added));
-        })));
+        let _ = self.sender.send(Event::NodeAdded(node_added));
     }
 
     pub fn node_lost_event(&mut self, node_lost: XorName) {
-        let cloned_sender = self.sender.clone();
-        self.thread_joiners.push(RaiiThreadJoiner::new(thread!("Mock NodeLost Event", move || {
-            let _ = cloned_sender.send(Event::NodeLost(node_lost));
-        })));
+        let _ = self.sender.send(Event::NodeLost(node_lost));
     }
 
     pub fn get_requests_given(&self) -> Vec<RequestMessage> {
@@ -200,7 +247,7 @@ impl MockRoutingNodeImpl {
                             id: MessageId)
                             -> Result<(), InterfaceError> {
         let content = RequestContent::Get(data_request, id);
-        let message = self.send_request(src, dst, content, "Mock Get Request");
+        let message = self.send_request(src, dst, content);
         Ok(self.get_requests_given.push(message))
     }
 
@@ -211,7 +258,7 @@ impl MockRoutingNodeImpl {
                             id: MessageId)
                             -> Result<(), InterfaceError> {
         let content = RequestContent::Put(data, id);
-        let message = self.send_request(src, dst, content, "Mock Put Request");
+        let message = self.send_request(src, dst, content);
         Ok(self.put_requests_given.push(message))
     }
 
@@ -222,7 +269,7 @@ impl MockRoutingNodeImpl {
                              id: MessageId)
                              -> Result<(), InterfaceError> {
         let content = RequestContent::Post(data, id);
-        let message = self.send_request(src, dst, content, "Mock Post Request");
+        let message = self.send_request(src, dst, content);
         Ok(self.post_requests_given.push(message))
     }
 
@@ -233,7 +280,7 @@ impl MockRoutingNodeImpl {
                                id: MessageId)
                                -> Result<(), InterfaceError> {
         let content = RequestContent::Delete(data, id);
-        let message = self.send_request(src, dst, content, "Mock Delete Request");
+        let message = self.send_request(src, dst, content);
         Ok(self.delete_requests_given.push(message))
     }
 
@@ -244,7 +291,7 @@ impl MockRoutingNodeImpl {
                             id: MessageId)
                             -> Result<(), InterfaceError> {
         let content = ResponseContent::GetSuccess(data, id);
-        let message = self.send_response(src, dst, content, "Mock Get Success");
+        let message = self.send_response(src, dst, content);
         Ok(self.get_successes_given.push(message))
     }
 
@@ -260,7 +307,7 @@ impl MockRoutingNodeImpl {
             request: request,
             external_error_indicator: external_error_indicator,
         };
-        let message = self.send_response(src, dst, content, "Mock Get Failure");
+        let message = self.send_response(src, dst, content);
         Ok(self.get_failures_given.push(message))
     }
 
@@ -271,7 +318,7 @@ impl MockRoutingNodeImpl {
                             id: MessageId)
                             -> Result<(), InterfaceError> {
         let content = ResponseContent::PutSuccess(request_hash, id);
-        let message = self.send_response(src, dst, content, "Mock Put Success");
+        let message = self.send_response(src, dst, content);
         Ok(self.put_successes_given.push(message))
     }
 
@@ -287,7 +334,7 @@ impl MockRoutingNodeImpl {
             request: request,
             external_error_indicator: external_error_indicator,
         };
-        let message = self.send_response(src, dst, content, "Mock Put Failure");
+        let message = self.send_response(src, dst, content);
         Ok(self.put_failures_given.push(message))
     }
 
@@ -298,7 +345,7 @@ impl MockRoutingNodeImpl {
                              id: MessageId)
                              -> Result<(), InterfaceError> {
         let content = ResponseContent::PostSuccess(request_hash, id);
-        let message = self.send_response(src, dst, content, "Mock Post Success");
+        let message = self.send_response(src, dst, content);
         Ok(self.post_successes_given.push(message))
     }
 
@@ -314,7 +361,7 @@ impl MockRoutingNodeImpl {
             request: request,
             external_error_indicator: external_error_indicator,
         };
-        let message = self.send_response(src, dst, content, "Mock Post Failure");
+        let message = self.send_response(src, dst, content);
         Ok(self.post_failures_given.push(message))
     }
 
@@ -325,7 +372,7 @@ impl MockRoutingNodeImpl {
                                id: MessageId)
                                -> Result<(), InterfaceError> {
         let content = ResponseContent::DeleteSuccess(request_hash, id);
-        let message = self.send_response(src, dst, content, "Mock Delete Success");
+        let message = self.send_response(src, dst, content);
         Ok(self.delete_successes_given.push(message))
     }
 
@@ -341,7 +388,7 @@ impl MockRoutingNodeImpl {
             request: request,
             external_error_indicator: external_error_indicator,
         };
-        let message = self.send_response(src, dst, content, "Mock Delete Failure");
+        let message = self.send_response(src, dst, content);
         Ok(self.delete_failures_given.push(message))
     }
 
@@ -350,46 +397,68 @@ impl MockRoutingNodeImpl {
                                 content: Vec<u8>)
                                 -> Result<(), InterfaceError> {
         let content = RequestContent::Refresh(content);
-        let message = self.send_request(src.clone(), src, content, "Mock Refresh Request");
+        let message = self.send_request(src.clone(), src, content);
         Ok(self.refresh_requests_given.push(message))
     }
 
     pub fn close_group(&self, name: XorName) -> Result<Option<Vec<XorName>>, InterfaceError> {
-        Ok(self.routing_table
-               .close_nodes(&name)
-               .map(|infos| infos.iter().map(|info| &info.0).cloned().collect()))
+        if let Some(ref recorded_close_groups) = self.recorded_close_groups {
+            return Ok(recorded_close_groups.get(&name).cloned().unwrap_or(None));
+        }
+        let close_group = if let Some(ref network) = self.network {
+            unwrap_result!(network.lock()).close_group(&self.name, &name)
+        } else {
+            self.routing_table
+                .close_nodes(&name)
+                .map(|infos| infos.iter().map(|info| &info.0).cloned().collect())
+        };
+        if let Some(ref journal) = self.journal {
+            unwrap_result!(journal.lock()).append(Record::CloseGroup(name, close_group.clone()));
+        }
+        Ok(close_group)
     }
 
-    pub fn name(&self) -> Result<XorName, InterfaceError> {
+    pub fn name(&mut self) -> Result<XorName, InterfaceError> {
+        if let Some(ref journal) = self.journal {
+            if self.recorded_name != Some(self.name) {
+                unwrap_result!(journal.lock()).append(Record::Name(self.name));
+                self.recorded_name = Some(self.name);
+            }
+        }
         Ok(self.name.clone())
     }
 
+    fn record_sent(&mut self, record: Record) {
+        if let Some(ref journal) = self.journal {
+            unwrap_result!(journal.lock()).append(record);
+        } else if self.recorded_close_groups.is_some() {
+            self.replayed_sent.push(record);
+        }
+    }
+
     fn send_request(&mut self,
                     src: Authority,
                     dst: Authority,
-                    content: RequestContent,
-                    thread_name: &str)
+                    content: RequestContent)
                     -> RequestMessage {
         let message = RequestMessage {
             src: src,
             dst: dst,
             content: content,
         };
-        let cloned_message = message.clone();
-        let simulated_latency = self.simulated_latency.clone();
-        let sender = self.sender.clone();
-        self.thread_joiners.push(RaiiThreadJoiner::new(thread!(thread_name, move || {
-            sleep(simulated_latency);
-            let _ = sender.send(Event::Request(cloned_message));
-        })));
+        self.record_sent(Record::SentRequest(message.clone()));
+        if let Some(ref network) = self.network {
+            unwrap_result!(network.lock()).send(Event::Request(message.clone()));
+            return message;
+        }
+        let _ = self.sender.send(Event::Request(message.clone()));
         message
     }
 
     fn send_response(&mut self,
                      src: Authority,
                      dst: Authority,
-                     content: ResponseContent,
-                     thread_name: &str)
+                     content: ResponseContent)
                      -> ResponseMessage {
         let sender = match &dst {
             &Authority::Client{ .. } => self.client_sender.clone(),
@@ -400,12 +469,12 @@ impl MockRoutingNodeImpl {
             dst: dst,
             content: content,
         };
-        let cloned_message = message.clone();
-        let simulated_latency = self.simulated_latency.clone();
-        self.thread_joiners.push(RaiiThreadJoiner::new(thread!(thread_name, move || {
-            sleep(simulated_latency);
-            let _ = sender.send(Event::Response(cloned_message));
-        })));
+        self.record_sent(Record::SentResponse(message.clone()));
+        if let Some(ref network) = self.network {
+            unwrap_result!(network.lock()).send(Event::Response(message.clone()));
+            return message;
+        }
+        let _ = sender.send(Event::Response(message.clone()));
         message
     }
 }
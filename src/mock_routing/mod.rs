@@ -19,8 +19,14 @@
 
 #![allow(unused)]
 
+mod fault;
+mod mock_network;
 mod mock_routing_impl;
 
+pub use self::fault::{AuthorityKind, ContentKind, Fault, MessageFilter};
+pub use self::mock_network::MockNetwork;
+use journal::{Journal, Record};
+use self::mock_network::NetworkState;
 use self::mock_routing_impl::MockRoutingNodeImpl;
 use rand::random;
 use routing::{Authority, Data, DataRequest, Event, ImmutableData, ImmutableDataType,
@@ -40,6 +46,38 @@ impl MockRoutingNode {
         Ok(MockRoutingNode { pimpl: Arc::new(Mutex::new(MockRoutingNodeImpl::new(event_sender))) })
     }
 
+    /// Creates a node which records the answers to the vault's queries and the messages it sends
+    /// to `journal`.
+    pub fn with_journal(event_sender: mpsc::Sender<Event>,
+                        journal: Arc<Mutex<Journal>>)
+                        -> Result<MockRoutingNode, RoutingError> {
+        Ok(MockRoutingNode {
+            pimpl: Arc::new(Mutex::new(MockRoutingNodeImpl::with_journal(event_sender, journal))),
+        })
+    }
+
+    /// Creates a stand-in node for replaying a journal, as `journal::Node::replay` does.
+    pub fn replay() -> MockRoutingNode {
+        MockRoutingNode { pimpl: Arc::new(Mutex::new(MockRoutingNodeImpl::replay())) }
+    }
+
+    /// When replaying, makes the node answer queries as recorded by `record`.
+    pub fn apply_answer(&self, record: &Record) {
+        unwrap_result!(self.pimpl.lock()).apply_answer(record)
+    }
+
+    /// When replaying, returns the messages sent since this was last called.
+    pub fn take_sent(&self) -> Vec<Record> {
+        unwrap_result!(self.pimpl.lock()).take_sent()
+    }
+
+    /// Creates a node called `name` which sends its messages via `network`.
+    fn new_in_network(name: XorName, network: Arc<Mutex<NetworkState>>) -> MockRoutingNode {
+        MockRoutingNode {
+            pimpl: Arc::new(Mutex::new(MockRoutingNodeImpl::new_in_network(name, network))),
+        }
+    }
+
     pub fn get_client_receiver(&self) -> mpsc::Receiver<Event> {
         unwrap_result!(self.pimpl.lock()).get_client_receiver()
     }
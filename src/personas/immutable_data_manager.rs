@@ -18,23 +18,48 @@
 // TODO remove this
 #![allow(unused)]
 
+use clock;
+use config_handler::Config;
 use error::{ClientError, InternalError};
-use lru_time_cache::LruCache;
+use expiring_map::ExpiringMap;
 use maidsafe_utilities::serialisation;
+use personas::Persona;
+use rng;
 use routing::{Authority, Data, DataRequest, ImmutableData, ImmutableDataType, MessageId,
-              RequestContent, RequestMessage, ResponseContent, ResponseMessage};
+              PlainData, RequestContent, RequestMessage, ResponseContent, ResponseMessage};
 use sodiumoxide::crypto::hash::sha512;
+use state_store::StateStore;
 use std::cmp::{self, Ordering};
 use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Iter;
 use std::mem;
 use time::{Duration, SteadyTime};
-use types::{Refresh, RefreshValue};
+use types::{Audit, DataLost, Refresh};
+use utils;
 use vault::RoutingNode;
 use xor_name::{self, XorName};
 
+pub const PERSONA_NAME: &'static str = "ImmutableDataManager";
 pub const REPLICANTS: usize = 4;
 pub const MIN_REPLICANTS: usize = 4;
 
+// See docs/safecoin_farming_rate.md
+const INITIAL_FARMING_RATE: u64 = 1;
+// Number of farming rates received from peers which are kept to calculate the median.
+const FARMING_RATE_SAMPLES: usize = 8;
+
+// Proof-of-storage audits: every `AUDIT_INTERVAL_SECS` the good holders of `AUDIT_BATCH_SIZE`
+// randomly-chosen chunks are challenged, and given `AUDIT_TIMEOUT_SECS` to answer.
+const AUDIT_INTERVAL_SECS: i64 = 60;
+const AUDIT_BATCH_SIZE: usize = 10;
+const AUDIT_TIMEOUT_SECS: i64 = 60;
+const AUDIT_NONCE_SIZE: usize = 32;
+// Once this time has passed since a Put was received, it's forgotten even if some of the chosen
+// holders haven't answered.
+const PUT_TIMEOUT_SECS: i64 = 300;
+// Answers can only be checked against each other if there are at least this many.
+const MIN_AUDITED_HOLDERS: usize = 2;
+
 // This is the name of a PmidNode which has been chosen to store the data on.  It is assumed to be
 // `Good` (can return the data) until it fails a Get request, at which time it is deemed `Failed`.
 #[derive(Clone, PartialEq, Eq, Hash, Debug, RustcEncodable, RustcDecodable)]
@@ -52,28 +77,48 @@ impl DataHolder {
             DataHolder::Pending(ref name) => name,
         }
     }
+
+    pub fn is_good(&self) -> bool {
+        match *self {
+            DataHolder::Good(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        match *self {
+            DataHolder::Pending(_) => true,
+            _ => false,
+        }
+    }
 }
 
+// `backup_ok` and `sacrificial_ok` are `None` until the corresponding copy has been requested from
+// its own ImmutableDataManager group, then `Some(false)` until that copy has been retrieved.
 #[derive(Clone, PartialEq, Eq, Debug)]
 struct MetadataForGetRequest {
     pub requests: Vec<(MessageId, RequestMessage)>,
+    pub data_type: ImmutableDataType,
     pub pmid_nodes: Vec<DataHolder>,
     pub creation_timestamp: SteadyTime,
     pub data: Option<ImmutableData>,
     pub backup_ok: Option<bool>,
     pub sacrificial_ok: Option<bool>,
+    // Holders which are handing off the data before leaving the network.  They're asked for the
+    // data, but aren't counted as holders or replicated to.
+    pub leaving_holders: Vec<XorName>,
 }
 
 impl MetadataForGetRequest {
-    pub fn new(pmid_nodes: &Account) -> MetadataForGetRequest {
-        Self::construct(vec![], pmid_nodes)
+    pub fn new(account: &Account) -> MetadataForGetRequest {
+        Self::construct(vec![], account)
     }
 
     pub fn with_message(message_id: &MessageId,
                         request: &RequestMessage,
-                        pmid_nodes: &Account)
+                        account: &Account)
                         -> MetadataForGetRequest {
-        Self::construct(vec![(message_id.clone(), request.clone()); 1], pmid_nodes)
+        Self::construct(vec![(message_id.clone(), request.clone()); 1], account)
     }
 
     pub fn send_get_requests(&self,
@@ -83,7 +128,7 @@ impl MetadataForGetRequest {
         for good_node in self.pmid_nodes.iter() {
             let src = Authority::NaeManager(data_name.clone());
             let dst = Authority::ManagedNode(good_node.name().clone());
-            let data_request = DataRequest::Immutable(data_name.clone(), ImmutableDataType::Normal);
+            let data_request = DataRequest::Immutable(data_name.clone(), self.data_type.clone());
             debug!("ImmutableDataManager {} sending get {} to {:?}",
                    unwrap_result!(routing_node.name()),
                    data_name,
@@ -92,54 +137,245 @@ impl MetadataForGetRequest {
         }
     }
 
+    // Returns the type and name of the next copy to try to recover the data from, marking it as
+    // requested.  A Normal chunk can be recovered from its Backup then its Sacrificial copy, and a
+    // Backup chunk from its Sacrificial copy.
+    pub fn next_copy_to_request(&mut self,
+                                data_name: &XorName)
+                                -> Option<(ImmutableDataType, XorName)> {
+        let next_name = XorName(sha512::hash(&data_name.0).0);
+        match self.data_type {
+            ImmutableDataType::Normal if self.backup_ok.is_none() => {
+                self.backup_ok = Some(false);
+                Some((ImmutableDataType::Backup, next_name))
+            }
+            ImmutableDataType::Normal if self.sacrificial_ok.is_none() => {
+                self.sacrificial_ok = Some(false);
+                Some((ImmutableDataType::Sacrificial, XorName(sha512::hash(&next_name.0).0)))
+            }
+            ImmutableDataType::Backup if self.sacrificial_ok.is_none() => {
+                self.sacrificial_ok = Some(false);
+                Some((ImmutableDataType::Sacrificial, next_name))
+            }
+            _ => None,
+        }
+    }
+
     fn construct(requests: Vec<(MessageId, RequestMessage)>,
-                 pmid_nodes: &Account)
+                 account: &Account)
                  -> MetadataForGetRequest {
         // We only want to try and get data from "good" holders
-        let good_nodes = pmid_nodes.iter()
-                                   .filter_map(|data_holder| {
-                                       match *data_holder {
-                                           DataHolder::Good(pmid_node) => {
-                                               Some(DataHolder::Pending(pmid_node))
-                                           }
-                                           DataHolder::Failed(_) | DataHolder::Pending(_) => None,
-                                       }
-                                   })
-                                   .collect();
+        let good_nodes = account.pmid_nodes
+                                .iter()
+                                .filter_map(|data_holder| {
+                                    match *data_holder {
+                                        DataHolder::Good(pmid_node) => {
+                                            Some(DataHolder::Pending(pmid_node))
+                                        }
+                                        DataHolder::Failed(_) | DataHolder::Pending(_) => None,
+                                    }
+                                })
+                                .collect();
 
         MetadataForGetRequest {
             requests: requests,
+            data_type: account.data_type.clone(),
             pmid_nodes: good_nodes,
-            creation_timestamp: SteadyTime::now(),
+            creation_timestamp: clock::now(),
             data: None,
             backup_ok: None,
             sacrificial_ok: None,
+            leaving_holders: vec![],
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, RustcEncodable, RustcDecodable)]
+pub struct Account {
+    data_type: ImmutableDataType,
+    // PmidNodes holding a copy of the chunk
+    pmid_nodes: HashSet<DataHolder>,
+}
+
+// What's sent to our peers for each chunk during churn.
+#[derive(RustcEncodable, RustcDecodable)]
+struct RefreshValue {
+    account: Account,
+    farming_rate: u64,
+}
+
+// An audit of a chunk's holders which is awaiting their answers.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct OngoingAudit {
+    pub nonce: Vec<u8>,
+    // Each audited holder's answer, or `None` until it has answered.
+    pub digests: HashMap<XorName, Option<Vec<u8>>>,
+    pub creation_timestamp: SteadyTime,
+}
+
+impl OngoingAudit {
+    pub fn new(nonce: Vec<u8>, holders: Vec<XorName>) -> OngoingAudit {
+        OngoingAudit {
+            nonce: nonce,
+            digests: holders.into_iter().map(|holder| (holder, None)).collect(),
+            creation_timestamp: clock::now(),
         }
     }
 }
 
-pub type Account = HashSet<DataHolder>;  // Collection of PmidNodes holding a copy of the chunk
+// The accounts, additionally indexed by holder so that the chunks held by a lost node can be found
+// without scanning every account.
+struct Accounts {
+    store: StateStore<Account>,
+    // <PmidNode name, names of the chunks it's a holder of>
+    holders: HashMap<XorName, HashSet<XorName>>,
+}
+
+impl Accounts {
+    fn open(config: &Config) -> Result<Accounts, InternalError> {
+        let mut accounts = Accounts {
+            store: try!(StateStore::open(config, PERSONA_NAME)),
+            holders: HashMap::new(),
+        };
+        let data_names = accounts.store.iter().map(|(data_name, _)| *data_name).collect::<Vec<_>>();
+        for data_name in data_names {
+            let holders = accounts.holder_names(&data_name);
+            accounts.index(&data_name, &holders);
+        }
+        Ok(accounts)
+    }
+
+    fn get(&self, data_name: &XorName) -> Option<&Account> {
+        self.store.get(data_name)
+    }
+
+    fn contains_key(&self, data_name: &XorName) -> bool {
+        self.store.contains_key(data_name)
+    }
 
-const LRU_CACHE_SIZE: usize = 1000;
+    fn iter(&self) -> Iter<XorName, Account> {
+        self.store.iter()
+    }
+
+    fn len(&self) -> usize {
+        self.store.iter().len()
+    }
+
+    fn clear_in_memory(&mut self) {
+        self.store.clear_in_memory();
+        self.holders.clear();
+    }
+
+    fn held_by(&self, pmid_node: &XorName) -> HashSet<XorName> {
+        self.holders.get(pmid_node).cloned().unwrap_or_else(HashSet::new)
+    }
+
+    fn insert(&mut self, data_name: XorName, account: Account) -> Option<Account> {
+        let old_holders = self.holder_names(&data_name);
+        self.unindex(&data_name, &old_holders);
+        let new_holders = account.pmid_nodes.iter().map(|holder| *holder.name()).collect();
+        self.index(&data_name, &new_holders);
+        self.store.insert(data_name, account)
+    }
+
+    fn remove(&mut self, data_name: &XorName) -> Option<Account> {
+        let old_holders = self.holder_names(data_name);
+        self.unindex(data_name, &old_holders);
+        self.store.remove(data_name)
+    }
+
+    fn update<F, R>(&mut self, data_name: &XorName, f: F) -> Option<R>
+        where F: FnOnce(&mut Account) -> R
+    {
+        let old_holders = self.holder_names(data_name);
+        let result = self.store.update(data_name, f);
+        let new_holders = self.holder_names(data_name);
+        if old_holders != new_holders {
+            self.unindex(data_name, &old_holders);
+            self.index(data_name, &new_holders);
+        }
+        result
+    }
+
+    fn holder_names(&self, data_name: &XorName) -> HashSet<XorName> {
+        self.store
+            .get(data_name)
+            .map(|account| account.pmid_nodes.iter().map(|holder| *holder.name()).collect())
+            .unwrap_or_else(HashSet::new)
+    }
+
+    fn index(&mut self, data_name: &XorName, holders: &HashSet<XorName>) {
+        for holder in holders.iter() {
+            let _ = self.holders.entry(*holder).or_insert_with(HashSet::new).insert(*data_name);
+        }
+    }
+
+    fn unindex(&mut self, data_name: &XorName, holders: &HashSet<XorName>) {
+        for holder in holders.iter() {
+            let now_empty = match self.holders.get_mut(holder) {
+                Some(data_names) => {
+                    let _ = data_names.remove(data_name);
+                    data_names.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                let _ = self.holders.remove(holder);
+            }
+        }
+    }
+}
+
+const MAX_ONGOING_GETS: usize = 1000;
 
 pub struct ImmutableDataManager {
     // <Data name, PmidNodes holding a copy of the data>
-    accounts: HashMap<XorName, Account>,
+    accounts: Accounts,
+    // key is chunk_name
+    ongoing_gets: ExpiringMap<XorName, MetadataForGetRequest>,
+    // <Message ID, data being stored and when the Put was received>
+    ongoing_puts: HashMap<MessageId, (ImmutableData, SteadyTime)>,
+    // <Data name, key of its entry in ongoing_puts>
+    ongoing_put_ids: HashMap<XorName, MessageId>,
+    // <Data name, member of the data's close group furthest from it>
+    furthest_group_members: HashMap<XorName, XorName>,
+    // Rises for each Sacrificial chunk stored and falls for each one which couldn't be stored or
+    // was evicted by all of its holders.
+    farming_rate: u64,
+    // Most recent farming rates received in refreshes from our peers, one per batch of refreshes
+    peer_farming_rates: Vec<u64>,
+    // Farming rates carried by the refreshes received since the last tick
+    received_farming_rates: Vec<u64>,
     // key is chunk_name
-    ongoing_gets: LruCache<XorName, MetadataForGetRequest>,
-    ongoing_puts: HashMap<MessageId, ImmutableData>,
+    ongoing_audits: HashMap<XorName, OngoingAudit>,
+    last_audit: SteadyTime,
+    // Number of chunks for which no valid holder was left after churn
+    chunks_lost: u64,
+    refreshes_sent: u64,
+    // Errors sent to clients since the registry last took them
+    client_errors: Vec<ClientError>,
 }
 
 impl ImmutableDataManager {
-    pub fn new() -> ImmutableDataManager {
-        ImmutableDataManager {
-            accounts: HashMap::new(),
-            ongoing_gets: LruCache::with_expiry_duration_and_capacity(Duration::minutes(5),
-                                                                      LRU_CACHE_SIZE),
+    pub fn new(config: &Config) -> Result<ImmutableDataManager, InternalError> {
+        Ok(ImmutableDataManager {
+            accounts: try!(Accounts::open(config)),
+            ongoing_gets: ExpiringMap::new(Duration::minutes(5), MAX_ONGOING_GETS),
             ongoing_puts: HashMap::new(),
-        }
+            ongoing_put_ids: HashMap::new(),
+            furthest_group_members: HashMap::new(),
+            farming_rate: INITIAL_FARMING_RATE,
+            peer_farming_rates: Vec::with_capacity(FARMING_RATE_SAMPLES),
+            received_farming_rates: Vec::new(),
+            ongoing_audits: HashMap::new(),
+            last_audit: clock::now(),
+            chunks_lost: 0,
+            refreshes_sent: 0,
+            client_errors: Vec::new(),
+        })
     }
 
+
     pub fn handle_get(&mut self,
                       routing_node: &RoutingNode,
                       request: &RequestMessage)
@@ -152,7 +388,7 @@ impl ImmutableDataManager {
         };
 
         // If the data doesn't exist, respond with GetFailure
-        let pmid_nodes = match self.accounts.get(&data_name) {
+        let account = match self.accounts.get(&data_name) {
             Some(account) => account,
             None => {
                 let src = request.dst.clone();
@@ -169,9 +405,10 @@ impl ImmutableDataManager {
         };
 
         // If there's an ongoing Put operation, get the data from the cached copy there and return
-        if let Some(immutable_data) = self.ongoing_puts
-                                          .values()
-                                          .find(|&value| value.name() == data_name) {
+        let ongoing_put = self.ongoing_put_ids
+                              .get(&data_name)
+                              .and_then(|message_id| self.ongoing_puts.get(message_id));
+        if let Some(&(ref immutable_data, _)) = ongoing_put {
             let src = request.dst.clone();
             let dst = request.src.clone();
             let _ = routing_node.send_get_success(src,
@@ -184,17 +421,22 @@ impl ImmutableDataManager {
         {
             // If there's already a cached get request, handle it here and return
             if let Some(metadata) = self.ongoing_gets.get_mut(&data_name) {
-                return Ok(Self::reply_with_data_else_cache_request(routing_node,
-                                                                   request,
-                                                                   &message_id,
-                                                                   metadata));
+                Self::reply_with_data_else_cache_request(routing_node,
+                                                         request,
+                                                         &message_id,
+                                                         metadata);
+                return Ok(());
             }
         }
 
         // This is new cache entry
-        let entry = MetadataForGetRequest::with_message(&message_id, request, pmid_nodes);
+        let entry = MetadataForGetRequest::with_message(&message_id, request, account);
         entry.send_get_requests(routing_node, &data_name, &message_id);
-        let _ = self.ongoing_gets.insert(data_name, entry);
+        insert_ongoing_get(routing_node,
+                           &mut self.ongoing_gets,
+                           &mut self.client_errors,
+                           data_name,
+                           entry);
         Ok(())
     }
 
@@ -209,11 +451,14 @@ impl ImmutableDataManager {
             _ => unreachable!("Error in vault demuxing"),
         };
 
-        // Send success on receipt.
-        let src = request.dst.clone();
-        let dst = request.src.clone();
-        let message_hash = sha512::hash(&try!(serialisation::serialise(&request))[..]);
-        let _ = routing_node.send_put_success(src, dst, message_hash, message_id);
+        // Send success on receipt.  Backup and Sacrificial copies are sent to us by the Normal
+        // copy's managers, which don't expect a response.
+        if let Authority::ClientManager(_) = request.src {
+            let src = request.dst.clone();
+            let dst = request.src.clone();
+            let message_hash = sha512::hash(&try!(serialisation::serialise(&request))[..]);
+            let _ = routing_node.send_put_success(src, dst, message_hash, message_id);
+        }
 
         // If the data already exists, there's no more to do.
         let data_name = data.name();
@@ -228,8 +473,13 @@ impl ImmutableDataManager {
         debug!("ImmutableDataManager chosen {:?} as pmid_nodes for chunk {:?}",
                target_pmid_nodes,
                data_name);
-        let _ = self.accounts.insert(data_name, target_pmid_nodes.clone());
-        let _ = self.ongoing_puts.insert(message_id, data.clone());
+        let account = Account {
+            data_type: data.get_type_tag().clone(),
+            pmid_nodes: target_pmid_nodes.clone(),
+        };
+        let _ = self.accounts.insert(data_name, account);
+        let _ = self.ongoing_puts.insert(message_id, (data.clone(), clock::now()));
+        let _ = self.ongoing_put_ids.insert(data_name, message_id);
 
         // Send the message on to the PmidNodes' managers.
         for pmid_node in target_pmid_nodes {
@@ -241,6 +491,21 @@ impl ImmutableDataManager {
                                                   message_id);
         }
 
+        // For a Normal chunk, have the Backup and Sacrificial copies stored by their own managers.
+        // These get new message IDs, since `ongoing_puts` is keyed by message ID and this vault
+        // may also be one of the copies' managers.
+        if *data.get_type_tag() == ImmutableDataType::Normal {
+            for data_type in vec![ImmutableDataType::Backup, ImmutableDataType::Sacrificial] {
+                let copy = ImmutableData::new(data_type, data.value().clone());
+                let src = Authority::NaeManager(data_name);
+                let dst = Authority::NaeManager(copy.name());
+                let _ = routing_node.send_put_request(src,
+                                                      dst,
+                                                      Data::Immutable(copy),
+                                                      rng::new_message_id());
+            }
+        }
+
         Ok(())
     }
 
@@ -337,12 +602,12 @@ impl ImmutableDataManager {
         }
 
         // Mark the responder as "failed" in the account if it was previously marked "good"
-        if let Some(pmid_nodes) = self.accounts.get_mut(&data_name) {
-            if pmid_nodes.remove(&DataHolder::Good(pmid_node.clone())) {
-                pmid_nodes.insert(DataHolder::Failed(pmid_node.clone()));
+        let _ = self.accounts.update(&data_name, |account| {
+            if account.pmid_nodes.remove(&DataHolder::Good(pmid_node.clone())) {
+                account.pmid_nodes.insert(DataHolder::Failed(pmid_node.clone()));
             }
-            trace!("Account for {} updated to {:?}", data_name, pmid_nodes);
-        }
+            trace!("Account for {} updated to {:?}", data_name, account);
+        });
 
         if result.is_ok() {
             try!(self.check_and_replicate(routing_node, &data_name));
@@ -350,37 +615,101 @@ impl ImmutableDataManager {
         result
     }
 
+    // Handles a Backup or Sacrificial copy of the chunk being returned by that copy's managers
+    // after all our own holders failed to return it.
+    pub fn handle_copy_get_success(&mut self,
+                                   routing_node: &RoutingNode,
+                                   response: &ResponseMessage)
+                                   -> Result<(), InternalError> {
+        let data = match response.content {
+            ResponseContent::GetSuccess(Data::Immutable(ref data), _) => data,
+            _ => unreachable!("Error in vault demuxing"),
+        };
+        let data_name = response.dst.name().clone();
+
+        if let Some(metadata) = self.ongoing_gets.get_mut(&data_name) {
+            if metadata.data.is_some() {
+                return Ok(());
+            }
+
+            // Convert the copy back to the type we manage, checking it's really the same chunk
+            let recovered_data = ImmutableData::new(metadata.data_type.clone(),
+                                                    data.value().clone());
+            if recovered_data.name() != data_name {
+                warn!("Copy {} returned for {} holds different data", data.name(), data_name);
+                return Err(InternalError::InvalidResponse);
+            }
+            match *data.get_type_tag() {
+                ImmutableDataType::Backup => metadata.backup_ok = Some(true),
+                ImmutableDataType::Sacrificial => metadata.sacrificial_ok = Some(true),
+                ImmutableDataType::Normal => (),
+            }
+
+            // Reply to any unanswered requests
+            while let Some((original_message_id, request)) = metadata.requests.pop() {
+                let src = request.dst.clone();
+                let dst = request.src;
+                trace!("Sending GetSuccess back to {:?}", dst);
+                let _ = routing_node.send_get_success(src,
+                                                      dst,
+                                                      Data::Immutable(recovered_data.clone()),
+                                                      original_message_id);
+            }
+            metadata.data = Some(recovered_data);
+            trace!("Metadata for Get {} updated to {:?}", data_name, metadata);
+        } else {
+            warn!("Failed to find metadata for recovered copy of {}", data_name);
+            return Err(InternalError::InvalidResponse);
+        }
+
+        self.check_and_replicate(routing_node, &data_name)
+    }
+
+    pub fn handle_copy_get_failure(&mut self,
+                                   routing_node: &RoutingNode,
+                                   response: &ResponseMessage)
+                                   -> Result<(), InternalError> {
+        let data_name = response.dst.name().clone();
+        trace!("Failed to recover {} from copy {}", data_name, response.src.name());
+        // Move on to the next copy, or give up if there are none left
+        self.check_and_replicate(routing_node, &data_name)
+    }
+
     pub fn handle_put_success(&mut self,
                               pmid_node: &XorName,
                               message_id: &MessageId)
                               -> Result<(), InternalError> {
-        let mut replicants_stored = 0;
+        let mut sacrificial_stored = false;
 
         {
-            if let Some(immutable_data) = self.ongoing_puts.get(message_id) {
-                if let Some(pmid_nodes) = self.accounts.get_mut(&immutable_data.name()) {
-                    if pmid_nodes.remove(&DataHolder::Pending(pmid_node.clone())) {
-                        pmid_nodes.insert(DataHolder::Good(*pmid_node));
-                        for node in pmid_nodes.iter() {
-                            if let DataHolder::Good(_) = *node {
-                                replicants_stored += 1;
-                            }
-                        }
-                    } else {
-                        return Err(InternalError::InvalidResponse);
+            if let Some(&(ref immutable_data, _)) = self.ongoing_puts.get(message_id) {
+                // Whether this is the chunk's first holder to store it.
+                let updated = self.accounts.update(&immutable_data.name(), |account| {
+                    if !account.pmid_nodes.remove(&DataHolder::Pending(pmid_node.clone())) {
+                        return None;
                     }
-                } else {
-                    return Err(InternalError::InvalidResponse);
-                }
+                    let first_stored = !account.pmid_nodes.iter().any(DataHolder::is_good);
+                    account.pmid_nodes.insert(DataHolder::Good(*pmid_node));
+                    Some(first_stored)
+                });
+                let first_stored = match updated {
+                    Some(Some(first_stored)) => first_stored,
+                    _ => return Err(InternalError::InvalidResponse),
+                };
+                sacrificial_stored = first_stored &&
+                                     *immutable_data.get_type_tag() ==
+                                     ImmutableDataType::Sacrificial;
             } else {
                 return Err(InternalError::FailedToFindCachedRequest(*message_id));
             }
         }
 
-        if replicants_stored == REPLICANTS {
-            let _ = self.ongoing_puts.remove(message_id);
+        if sacrificial_stored {
+            self.farming_rate += 1;
+            trace!("Farming rate increased to {}", self.farming_rate);
         }
 
+        self.conclude_put_if_answered(message_id);
         Ok(())
     }
 
@@ -389,20 +718,46 @@ impl ImmutableDataManager {
                               pmid_node: &XorName,
                               message_id: &MessageId)
                               -> Result<(), InternalError> {
-        if let Some(immutable_data) = self.ongoing_puts.get(message_id) {
-            if let Some(pmid_nodes) = self.accounts.get_mut(&immutable_data.name()) {
+        let result = self.replace_failed_holder(routing_node, pmid_node, message_id);
+        self.conclude_put_if_answered(message_id);
+        result
+    }
+
+    // Marks the holder as Failed and, unless it was to store a Sacrificial copy, asks the next
+    // closest member of the chunk's close group not yet tried to store it instead.
+    fn replace_failed_holder(&mut self,
+                             routing_node: &RoutingNode,
+                             pmid_node: &XorName,
+                             message_id: &MessageId)
+                             -> Result<(), InternalError> {
+        if let Some(&(ref immutable_data, _)) = self.ongoing_puts.get(message_id) {
+            if let Some(mut account) = self.accounts.get(&immutable_data.name()).cloned() {
                 // Mark the holder as Failed
-                if !pmid_nodes.remove(&DataHolder::Pending(pmid_node.clone())) {
+                if !account.pmid_nodes.remove(&DataHolder::Pending(pmid_node.clone())) {
                     return Err(InternalError::InvalidResponse);
                 }
-                pmid_nodes.insert(DataHolder::Failed(pmid_node.clone()));
+                account.pmid_nodes.insert(DataHolder::Failed(pmid_node.clone()));
+                let data_name = immutable_data.name();
+
+                // Sacrificial copies are only attempted to be stored, so there's no replacement.
+                // The chunk only counts as unable to be stored once every holder has failed.
+                if *immutable_data.get_type_tag() == ImmutableDataType::Sacrificial {
+                    let unstored = account.pmid_nodes.iter().all(|holder| {
+                        !holder.is_good() && !holder.is_pending()
+                    });
+                    let _ = self.accounts.insert(data_name, account);
+                    if unstored {
+                        self.farming_rate = self.farming_rate.saturating_sub(1);
+                        trace!("Farming rate decreased to {}", self.farming_rate);
+                    }
+                    return Ok(());
+                }
 
                 // Find a replacement - first node in close_group not already tried
-                let data_name = immutable_data.name();
                 match try!(routing_node.close_group(data_name)) {
                     Some(mut target_pmid_nodes) => {
                         target_pmid_nodes.retain(|elt| {
-                            !pmid_nodes.iter().any(|exclude| elt == exclude.name())
+                            !account.pmid_nodes.iter().any(|exclude| elt == exclude.name())
                         });
                         Self::sort_from_target(&mut target_pmid_nodes, &data_name);
                         if let Some(new_holder) = target_pmid_nodes.iter().next() {
@@ -412,9 +767,11 @@ impl ImmutableDataManager {
                                                                   dst,
                                                                   Data::Immutable(immutable_data.clone()),
                                                                   *message_id);
-                            pmid_nodes.insert(DataHolder::Pending(*new_holder));
+                            account.pmid_nodes.insert(DataHolder::Pending(*new_holder));
+                            let _ = self.accounts.insert(data_name, account);
                         } else {
                             warn!("Failed to find a new storage node for {}.", data_name);
+                            let _ = self.accounts.insert(data_name, account);
                             return Err(InternalError::UnableToAllocateNewPmidNode);
                         }
                     }
@@ -430,109 +787,497 @@ impl ImmutableDataManager {
         Ok(())
     }
 
-    pub fn handle_refresh(&mut self, data_name: XorName, account: Account) {
+    // Forgets the ongoing Put once none of the chunk's holders are still to answer.
+    fn conclude_put_if_answered(&mut self, message_id: &MessageId) {
+        let data_name = match self.ongoing_puts.get(message_id) {
+            Some(&(ref immutable_data, _)) => immutable_data.name(),
+            None => return,
+        };
+        let answered = self.accounts.get(&data_name).map_or(true, |account| {
+            !account.pmid_nodes.iter().any(DataHolder::is_pending)
+        });
+        if answered {
+            self.remove_ongoing_put(message_id);
+        }
+    }
+
+    fn remove_ongoing_put(&mut self, message_id: &MessageId) {
+        if let Some((immutable_data, _)) = self.ongoing_puts.remove(message_id) {
+            let _ = self.ongoing_put_ids.remove(&immutable_data.name());
+        }
+    }
+
+    // Forgets the Puts which have been waiting on their holders for too long.
+    fn expire_ongoing_puts(&mut self) {
+        let expiry = clock::now() - Duration::seconds(PUT_TIMEOUT_SECS);
+        let expired = self.ongoing_puts
+                          .iter()
+                          .filter(|&(_, &(_, received))| received <= expiry)
+                          .map(|(message_id, _)| *message_id)
+                          .collect::<Vec<_>>();
+        for message_id in expired {
+            warn!("Ongoing put {:?} expired", message_id);
+            self.remove_ongoing_put(&message_id);
+        }
+    }
+
+    // A holder has lost its copy of the chunk (notified via its PmidManagers), so remove it from
+    // the account.  A Sacrificial copy isn't replaced, but a lost one lowers the farming rate.
+    // Otherwise the chunk is retrieved from the remaining holders and replicated.
+    pub fn handle_data_lost(&mut self,
+                            routing_node: &RoutingNode,
+                            request: &RequestMessage)
+                            -> Result<(), InternalError> {
+        let data = match request.content {
+            RequestContent::Post(Data::Plain(ref data), _) => data,
+            _ => unreachable!("Error in vault demuxing"),
+        };
+        let data_lost = try!(serialisation::deserialise::<DataLost>(&data.value()));
+        let data_name = data_lost.data_name;
+        let pmid_node = request.src.name().clone();
+        if data_name != *request.dst.name() {
+            warn!("{:?} sent lost data notification for {} to {:?}",
+                  request.src,
+                  data_name,
+                  request.dst);
+            return Ok(());
+        }
+
+        // The chunk's type, and whether the holder had the last good copy.
+        let (data_type, last_copy_lost) = match self.accounts.update(&data_name, |account| {
+            let holder = account.pmid_nodes
+                                .iter()
+                                .find(|holder| *holder.name() == pmid_node)
+                                .cloned();
+            holder.map(|holder| {
+                let _ = account.pmid_nodes.remove(&holder);
+                let last_copy_lost = holder.is_good() &&
+                                     !account.pmid_nodes.iter().any(DataHolder::is_good);
+                (account.data_type.clone(), last_copy_lost)
+            })
+        }) {
+            Some(Some(lost)) => lost,
+            _ => {
+                warn!("{} isn't a holder of {}", pmid_node, data_name);
+                return Ok(());
+            }
+        };
+        trace!("{} lost {:?} data {}", pmid_node, data_type, data_name);
+
+        if data_type == ImmutableDataType::Sacrificial {
+            if last_copy_lost {
+                self.farming_rate = self.farming_rate.saturating_sub(1);
+                trace!("Farming rate decreased to {}", self.farming_rate);
+            }
+            return Ok(());
+        }
+
+        let leaving_holder = if data_lost.handing_off {
+            Some(pmid_node)
+        } else {
+            None
+        };
+        self.retrieve_and_replicate(routing_node, &data_name, &[pmid_node], leaving_holder)
+    }
+
+    // Starts a proof-of-storage audit of the chunk's good holders, if there are enough of them to
+    // compare their answers.
+    pub fn start_audit(&mut self, routing_node: &RoutingNode, data_name: &XorName) {
+        if self.ongoing_audits.contains_key(data_name) {
+            return;
+        }
+        let holders = match self.accounts.get(data_name) {
+            Some(account) => {
+                account.pmid_nodes
+                       .iter()
+                       .filter_map(|holder| {
+                           match *holder {
+                               DataHolder::Good(ref name) => Some(name.clone()),
+                               DataHolder::Failed(_) | DataHolder::Pending(_) => None,
+                           }
+                       })
+                       .collect::<Vec<_>>()
+            }
+            None => return,
+        };
+        if holders.len() < MIN_AUDITED_HOLDERS {
+            return;
+        }
+
+        let nonce = (0..AUDIT_NONCE_SIZE).map(|_| rng::gen::<u8>()).collect::<Vec<_>>();
+        let challenge = Audit::Challenge { nonce: nonce.clone() };
+        let serialised_challenge = match serialisation::serialise(&challenge) {
+            Ok(serialised_challenge) => serialised_challenge,
+            Err(error) => {
+                error!("Failed to serialise audit challenge: {:?}", error);
+                return;
+            }
+        };
+        let message_id = rng::new_message_id();
+        for holder in holders.iter() {
+            let src = Authority::NaeManager(data_name.clone());
+            let dst = Authority::ManagedNode(holder.clone());
+            let data = Data::Plain(PlainData::new(data_name.clone(),
+                                                  serialised_challenge.clone()));
+            trace!("Sending audit challenge for {} to {:?}", data_name, dst);
+            let _ = routing_node.send_post_request(src, dst, data, message_id);
+        }
+        let _ = self.ongoing_audits.insert(*data_name, OngoingAudit::new(nonce, holders));
+    }
+
+    pub fn handle_audit_response(&mut self,
+                                 routing_node: &RoutingNode,
+                                 request: &RequestMessage)
+                                 -> Result<(), InternalError> {
+        let data = match request.content {
+            RequestContent::Post(Data::Plain(ref data), _) => data,
+            _ => unreachable!("Error in vault demuxing"),
+        };
+        let (nonce, digest) = match try!(serialisation::deserialise::<Audit>(&data.value())) {
+            Audit::Response { nonce, digest } => (nonce, digest),
+            Audit::Challenge { .. } => {
+                warn!("Received an audit challenge from {:?}", request.src);
+                return Ok(());
+            }
+        };
+        let data_name = data.name();
+        let complete = match self.ongoing_audits.get_mut(&data_name) {
+            Some(audit) => {
+                if audit.nonce != nonce {
+                    warn!("Received audit response for {} with the wrong nonce", data_name);
+                    return Ok(());
+                }
+                if let Some(answer) = audit.digests.get_mut(request.src.name()) {
+                    *answer = Some(digest);
+                }
+                audit.digests.values().all(Option::is_some)
+            }
+            None => return Ok(()),
+        };
+        if complete {
+            self.conclude_audit(routing_node, &data_name)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Concludes audits which have been waiting too long for answers.
+    pub fn check_audit_timeouts(&mut self, routing_node: &RoutingNode) {
+        let timed_out = self.ongoing_audits
+                            .iter()
+                            .filter(|&(_, audit)| {
+                                audit.creation_timestamp + Duration::seconds(AUDIT_TIMEOUT_SECS) <
+                                clock::now()
+                            })
+                            .map(|(data_name, _)| *data_name)
+                            .collect::<Vec<_>>();
+        for data_name in timed_out {
+            if let Err(error) = self.conclude_audit(routing_node, &data_name) {
+                warn!("Failed to conclude audit of {}: {:?}", data_name, error);
+            }
+        }
+    }
+
+    // Audits a batch of randomly-chosen chunks, at most once per `AUDIT_INTERVAL_SECS`.
+    fn audit_chunks(&mut self, routing_node: &RoutingNode) {
+        if self.last_audit + Duration::seconds(AUDIT_INTERVAL_SECS) > clock::now() {
+            return;
+        }
+        self.last_audit = clock::now();
+        let data_names = rng::sample(self.accounts.iter().map(|(data_name, _)| *data_name),
+                                     AUDIT_BATCH_SIZE);
+        for data_name in data_names.iter() {
+            self.start_audit(routing_node, data_name);
+        }
+    }
+
+    // The answer given by a majority of the audited holders is taken to be the correct one.  Any
+    // holder which gave a different answer or none at all is marked as `Failed` and the chunk is
+    // replicated.  Without a majority we can't tell which holders are faulty, so only the ones
+    // which didn't answer are marked.
+    fn conclude_audit(&mut self,
+                      routing_node: &RoutingNode,
+                      data_name: &XorName)
+                      -> Result<(), InternalError> {
+        let audit = match self.ongoing_audits.remove(data_name) {
+            Some(audit) => audit,
+            None => return Ok(()),
+        };
+        let mut votes = HashMap::<&Vec<u8>, usize>::new();
+        for digest in audit.digests.values().filter_map(Option::as_ref) {
+            *votes.entry(digest).or_insert(0) += 1;
+        }
+        let majority_digest = votes.iter()
+                                   .find(|&(_, count)| 2 * *count > audit.digests.len())
+                                   .map(|(digest, _)| *digest);
+        if majority_digest.is_none() {
+            warn!("No majority answer to audit of {} - {:?}", data_name, audit.digests);
+        }
+        let failed_holders = audit.digests
+                                  .iter()
+                                  .filter(|&(_, answer)| {
+                                      match (answer.as_ref(), majority_digest) {
+                                          (None, _) => true,
+                                          (Some(digest), Some(majority_digest)) => {
+                                              digest != majority_digest
+                                          }
+                                          (Some(_), None) => false,
+                                      }
+                                  })
+                                  .map(|(holder, _)| *holder)
+                                  .collect::<Vec<_>>();
+        if failed_holders.is_empty() {
+            trace!("All holders of {} passed audit", data_name);
+            return Ok(());
+        }
+
+        warn!("Holders of {} failed audit: {:?}", data_name, failed_holders);
+        let _ = self.accounts.update(data_name, |account| {
+            for holder in failed_holders.iter() {
+                if account.pmid_nodes.remove(&DataHolder::Good(*holder)) {
+                    let _ = account.pmid_nodes.insert(DataHolder::Failed(*holder));
+                }
+            }
+        });
+        self.retrieve_and_replicate(routing_node, data_name, &failed_holders, None)
+    }
+
+    // Gets the chunk from its good holders, ignoring `lost_holders`, so that it can be replicated
+    // to replace them.  A `leaving_holder` is asked for the chunk too, if it isn't already being
+    // retrieved.
+    fn retrieve_and_replicate(&mut self,
+                              routing_node: &RoutingNode,
+                              data_name: &XorName,
+                              lost_holders: &[XorName],
+                              leaving_holder: Option<XorName>)
+                              -> Result<(), InternalError> {
+        let already_getting = match self.ongoing_gets.get_mut(data_name) {
+            Some(metadata) => {
+                // Just stop waiting for the lost holders.
+                metadata.pmid_nodes.retain(|holder| !lost_holders.contains(holder.name()));
+                true
+            }
+            None => false,
+        };
+        if !already_getting {
+            let mut entry = match self.accounts.get(data_name) {
+                Some(account) => MetadataForGetRequest::new(account),
+                None => return Ok(()),
+            };
+            if let Some(leaving_holder) = leaving_holder {
+                entry.pmid_nodes.push(DataHolder::Pending(leaving_holder));
+                entry.leaving_holders.push(leaving_holder);
+            }
+            trace!("Created ongoing get entry for {} - {:?}", data_name, entry);
+            entry.send_get_requests(routing_node, data_name, &rng::new_message_id());
+            insert_ongoing_get(routing_node,
+                               &mut self.ongoing_gets,
+                               &mut self.client_errors,
+                               *data_name,
+                               entry);
+        }
+        self.check_and_replicate(routing_node, data_name)
+    }
+
+    // Each chunk's refresh carries the sender's farming rate, so the rates are only merged once
+    // per batch of refreshes, on the next tick.
+    pub fn handle_refresh(&mut self, data_name: XorName, account: Account, farming_rate: u64) {
         let _ = self.accounts.insert(data_name, account);
+        self.received_farming_rates.push(farming_rate);
     }
 
-    pub fn handle_node_added(&mut self, routing_node: &RoutingNode, _node_added: XorName) {
-        self.handle_churn(routing_node)
+    // Moves our farming rate to the median of our own and the most recent ones from our peers,
+    // taking the median of the rates received since the last tick as a single new sample.
+    fn merge_farming_rates(&mut self) {
+        if self.received_farming_rates.is_empty() {
+            return;
+        }
+        let peer_farming_rate = utils::median(&self.received_farming_rates);
+        self.received_farming_rates.clear();
+        if self.peer_farming_rates.len() == FARMING_RATE_SAMPLES {
+            let _ = self.peer_farming_rates.remove(0);
+        }
+        self.peer_farming_rates.push(peer_farming_rate);
+        let mut farming_rates = self.peer_farming_rates.clone();
+        farming_rates.push(self.farming_rate);
+        self.farming_rate = utils::median(&farming_rates);
     }
 
-    pub fn handle_node_lost(&mut self, routing_node: &RoutingNode, _node_lost: XorName) {
-        self.handle_churn(routing_node)
+    pub fn handle_node_added(&mut self, routing_node: &RoutingNode, node_added: XorName) {
+        self.handle_churn(routing_node, &node_added, false)
     }
 
-    pub fn handle_churn(&mut self, routing_node: &RoutingNode) {
-        {
-            let accounts = mem::replace(&mut self.accounts, HashMap::new());
-            self.accounts =
-                accounts.into_iter()
-                        .filter_map(|(data_name, mut pmid_nodes)| {
-                            trace!("Churning for {} - holders before: {:?}", data_name, pmid_nodes);
-                            let close_group: HashSet<_> = match routing_node.close_group(data_name) {
-                                Ok(None) => {
-                                    trace!("No longer a DM for {}", data_name);
-                                    // Remove entry, as we're not part of the NaeManager any more
-                                    let mut message_id = None;
-
-                                    for (id, immutable_data) in self.ongoing_puts.iter() {
-                                        if immutable_data.name() == data_name {
-                                            message_id = Some(id.clone());
-                                            break;
-                                        }
-                                    }
+    pub fn handle_node_lost(&mut self, routing_node: &RoutingNode, node_lost: XorName) {
+        self.handle_churn(routing_node, &node_lost, true)
+    }
 
-                                    if let Some(message_id) = message_id {
-                                        let _ = self.ongoing_puts.remove(&message_id);
-                                    }
+    // Only the chunks held by a lost node, or whose close group includes the churned node, are
+    // affected.  Each of our chunks is checked against the furthest member of its close group,
+    // which is cached so that `close_group` is only called for the chunks affected.  The churned
+    // node's own close group can't be used to find them, as we needn't be part of it.
+    pub fn handle_churn(&mut self, routing_node: &RoutingNode, churn_node: &XorName, lost: bool) {
+        let mut affected: HashSet<XorName> = if lost {
+            self.accounts.held_by(churn_node)
+        } else {
+            HashSet::new()
+        };
+        let data_names = self.accounts.iter().map(|(data_name, _)| *data_name).collect::<Vec<_>>();
+        for data_name in data_names {
+            let cached_furthest = self.furthest_group_members.get(&data_name).cloned();
+            let furthest = match cached_furthest {
+                Some(furthest) => Some(furthest),
+                None => self.look_up_furthest_group_member(routing_node, &data_name),
+            };
+            let in_close_group = match furthest {
+                Some(furthest) => !xor_name::closer_to_target(&furthest, churn_node, &data_name),
+                None => true,
+            };
+            if in_close_group {
+                let _ = affected.insert(data_name);
+            }
+        }
+        trace!("Churn of {} affects {} of {} chunks",
+               churn_node,
+               affected.len(),
+               self.accounts.len());
+
+        for data_name in affected {
+            let lost_node = if lost {
+                Some(churn_node)
+            } else {
+                None
+            };
+            self.churn_chunk(routing_node, &data_name, lost_node);
+        }
+    }
 
-                                    return None;
-                                }
-                                Ok(Some(close_group)) => close_group.into_iter().collect(),
-                                Err(error) => {
-                                    error!("Failed to get close group: {:?}", error);
-                                    return None;
-                                }
-                            };
-                            pmid_nodes = pmid_nodes.into_iter().filter_map(|pmid_node| {
-                                if close_group.contains(pmid_node.name()) {
-                                    Some(pmid_node)
-                                } else {
-                                    None
-                                }
-                            }).collect();
-                            trace!("Churning for {} - holders after:  {:?}", data_name, pmid_nodes);
-                            if pmid_nodes.is_empty() {
-                                error!("Chunk lost - No valid nodes left to retrieve chunk");
-                                return None;
-                            }
-                            Some((data_name, pmid_nodes))
-                        })
-                        .collect();
-        }
-
-        for (data_name, pmid_nodes) in self.accounts.iter() {
-            if pmid_nodes.len() < MIN_REPLICANTS {
-                trace!("Need to replicate {} as only {} holders left",
-                       data_name,
-                       pmid_nodes.len());
-                {
-                    if let Some(mut metadata) = self.ongoing_gets.get_mut(&data_name) {
-                        trace!("Already getting {} - {:?}", data_name, metadata);
-                        // Remove any holders which no longer belong in the cache entry
-                        let close_group: HashSet<_> =
-                            match routing_node.close_group(data_name.clone()) {
-                                Ok(Some(close_group)) => close_group.into_iter().collect(),
-                                _ => {
-                                    panic!("This is a logic error - we filtered out all invalid \
-                                            accounts previously.")
-                                }
-                            };
-                        metadata.pmid_nodes
-                                .retain(|pmid_node| close_group.contains(pmid_node.name()));
-                        trace!("Updated ongoing get for {} to {:?}", data_name, metadata);
-                        continue;
-                    }
+    fn churn_chunk(&mut self,
+                   routing_node: &RoutingNode,
+                   data_name: &XorName,
+                   lost_node: Option<&XorName>) {
+        let mut account = match self.accounts.get(data_name) {
+            Some(account) => account.clone(),
+            None => return,
+        };
+        trace!("Churning for {} - holders before: {:?}", data_name, account.pmid_nodes);
+        let close_group: HashSet<_> = match routing_node.close_group(*data_name) {
+            Ok(None) => {
+                trace!("No longer a DM for {}", data_name);
+                // Remove entry, as we're not part of the NaeManager any more
+                if let Some(message_id) = self.ongoing_put_ids.remove(data_name) {
+                    let _ = self.ongoing_puts.remove(&message_id);
                 }
+                let _ = self.accounts.remove(data_name);
+                let _ = self.furthest_group_members.remove(data_name);
+                return;
+            }
+            Ok(Some(close_group)) => close_group.into_iter().collect(),
+            Err(error) => {
+                error!("Failed to get close group: {:?}", error);
+                let _ = self.accounts.remove(data_name);
+                let _ = self.furthest_group_members.remove(data_name);
+                return;
+            }
+        };
+        if let Some(furthest) = Self::furthest_from_target(&close_group, data_name) {
+            let _ = self.furthest_group_members.insert(*data_name, furthest);
+        }
+
+        let is_valid_holder = |pmid_node: &DataHolder| {
+            close_group.contains(pmid_node.name()) && Some(pmid_node.name()) != lost_node
+        };
+        let holder_count = account.pmid_nodes.len();
+        account.pmid_nodes = account.pmid_nodes
+                                    .into_iter()
+                                    .filter(|pmid_node| is_valid_holder(pmid_node))
+                                    .collect();
+        trace!("Churning for {} - holders after:  {:?}", data_name, account.pmid_nodes);
+        if account.pmid_nodes.is_empty() {
+            error!("Chunk lost - No valid nodes left to retrieve chunk");
+            self.chunks_lost += 1;
+            let _ = self.accounts.remove(data_name);
+            let _ = self.furthest_group_members.remove(data_name);
+            return;
+        } else if account.pmid_nodes.len() != holder_count {
+            let _ = self.accounts.insert(*data_name, account.clone());
+        }
+
+        if account.pmid_nodes.len() < MIN_REPLICANTS {
+            trace!("Need to replicate {} as only {} holders left",
+                   data_name,
+                   account.pmid_nodes.len());
+            let already_getting = match self.ongoing_gets.get_mut(data_name) {
+                Some(metadata) => {
+                    trace!("Already getting {} - {:?}", data_name, metadata);
+                    // Remove any holders which no longer belong in the cache entry
+                    metadata.pmid_nodes.retain(|pmid_node| is_valid_holder(pmid_node));
+                    trace!("Updated ongoing get for {} to {:?}", data_name, metadata);
+                    true
+                }
+                None => false,
+            };
+            if !already_getting {
                 // Create a new entry and send Get requests to each of the current holders
-                let entry = MetadataForGetRequest::new(pmid_nodes);
+                let entry = MetadataForGetRequest::new(&account);
                 trace!("Created ongoing get entry for {} - {:?}", data_name, entry);
-                let message_id = MessageId::new();
+                let message_id = rng::new_message_id();
                 entry.send_get_requests(routing_node, data_name, &message_id);
-                let _ = self.ongoing_gets.insert(data_name.clone(), entry);
+                insert_ongoing_get(routing_node,
+                                   &mut self.ongoing_gets,
+                                   &mut self.client_errors,
+                                   *data_name,
+                                   entry);
             }
-            self.send_refresh(routing_node, data_name, pmid_nodes);
         }
+        self.send_refresh(routing_node, data_name, &account);
+    }
+
+    // Caches and returns the member of the chunk's current close group furthest from it.  A node
+    // lost from the group was no further from the chunk than the member which replaced it, so the
+    // current group serves to check lost nodes too.
+    fn look_up_furthest_group_member(&mut self,
+                                     routing_node: &RoutingNode,
+                                     data_name: &XorName)
+                                     -> Option<XorName> {
+        let close_group: HashSet<_> = match routing_node.close_group(*data_name) {
+            Ok(Some(close_group)) => close_group.into_iter().collect(),
+            _ => return None,
+        };
+        let furthest = Self::furthest_from_target(&close_group, data_name);
+        if let Some(furthest) = furthest {
+            let _ = self.furthest_group_members.insert(*data_name, furthest);
+        }
+        furthest
     }
 
-    fn send_refresh(&self, routing_node: &RoutingNode, data_name: &XorName, pmid_nodes: &Account) {
+    fn furthest_from_target(names: &HashSet<XorName>, target: &XorName) -> Option<XorName> {
+        names.iter().fold(None, |furthest, name| {
+            match furthest {
+                Some(furthest) if xor_name::closer_to_target(name, &furthest, target) => {
+                    Some(furthest)
+                }
+                _ => Some(*name),
+            }
+        })
+    }
+
+    fn send_refresh(&mut self,
+                    routing_node: &RoutingNode,
+                    data_name: &XorName,
+                    account: &Account) {
         let src = Authority::NaeManager(data_name.clone());
-        let refresh = Refresh::new(data_name,
-                                   RefreshValue::ImmutableDataManagerAccount(pmid_nodes.clone()));
-        if let Ok(serialised_refresh) = serialisation::serialise(&refresh) {
+        let refresh_value = RefreshValue {
+            account: account.clone(),
+            farming_rate: self.farming_rate,
+        };
+        let refresh = Refresh::new(PERSONA_NAME, data_name, &refresh_value);
+        if let Ok(serialised_refresh) = refresh.and_then(|refresh| {
+            serialisation::serialise(&refresh)
+        }) {
             debug!("ImmutableDataManager sending refresh for account {:?}",
                    src.name());
             let _ = routing_node.send_refresh_request(src, serialised_refresh);
+            self.refreshes_sent += 1;
         }
     }
 
@@ -570,6 +1315,7 @@ impl ImmutableDataManager {
             for queried_data_holder in metadata.pmid_nodes.iter() {
                 match queried_data_holder {
                     &DataHolder::Pending(_) => return Ok(()),
+                    &DataHolder::Good(ref name) if metadata.leaving_holders.contains(name) => (),
                     &DataHolder::Good(_) => good_holder_count += 1,
                     &DataHolder::Failed(_) => (),
                 }
@@ -585,6 +1331,9 @@ impl ImmutableDataManager {
                 let mut nodes_to_exclude = vec![];
                 for queried_data_holder in metadata.pmid_nodes.iter() {
                     match queried_data_holder {
+                        &DataHolder::Good(ref name) if metadata.leaving_holders.contains(name) => {
+                            nodes_to_exclude.push(name);
+                        }
                         &DataHolder::Good(ref name) => {
                             let _ = good_nodes.insert(DataHolder::Good(name.clone()));
                         }
@@ -604,7 +1353,7 @@ impl ImmutableDataManager {
                 trace!("Replicating {} - target nodes: {:?}",
                        data_name,
                        target_pmid_nodes);
-                let message_id = MessageId::new();
+                let message_id = rng::new_message_id();
                 for new_pmid_node in target_pmid_nodes.difference(&good_nodes).into_iter() {
                     trace!("Replicating {} - sending Put to {}",
                            data_name,
@@ -618,23 +1367,27 @@ impl ImmutableDataManager {
                                                           message_id.clone());
                 }
                 finished = true;
+            } else if let Some((copy_type, copy_name)) = metadata.next_copy_to_request(data_name) {
+                // Recover the data from the backup or sacrificial copy's managers.  The failed
+                // holders are kept so that they're excluded when the data is replicated.
+                trace!("Recovering {} from {:?} copy {}", data_name, copy_type, copy_name);
+                let src = Authority::NaeManager(data_name.clone());
+                let dst = Authority::NaeManager(copy_name);
+                let data_request = DataRequest::Immutable(copy_name, copy_type);
+                let _ = routing_node.send_get_request(src,
+                                                      dst,
+                                                      data_request,
+                                                      rng::new_message_id());
             } else {
-                // Recover the data from backup and/or sacrificial locations
+                // All copies have been tried, so return failure to the clients waiting for
+                // responses, and they'll have to retry.
                 metadata.pmid_nodes.clear();
-                // TODO - actually retrieve the data.  For now we'll just return failure to the
-                // clients waiting for responses, and they'll have to retry.
-                while let Some((original_message_id, request)) = metadata.requests.pop() {
-                    let src = request.dst.clone();
-                    let dst = request.src.clone();
-                    trace!("Sending GetFailure back to {:?}", dst);
-                    let error = ClientError::NoSuchData;
-                    let external_error_indicator = try!(serialisation::serialise(&error));
-                    let _ = routing_node.send_get_failure(src,
-                                                          dst,
-                                                          request,
-                                                          external_error_indicator,
-                                                          original_message_id);
-                }
+                finished = true;
+                let requests = mem::replace(&mut metadata.requests, vec![]);
+                try!(reply_with_get_failures(routing_node,
+                                             requests,
+                                             &ClientError::NoSuchData,
+                                             &mut self.client_errors));
             }
         } else {
             warn!("Failed to find metadata for check_and_replicate of {}",
@@ -649,17 +1402,17 @@ impl ImmutableDataManager {
             trace!("Replicating {} - new holders: {:?}",
                    data_name,
                    new_pmid_nodes);
-            if let Some(pmid_nodes) = self.accounts.get_mut(data_name) {
+            let _ = self.accounts.update(data_name, |account| {
                 trace!("Replicating {} - account before: {:?}",
                        data_name,
-                       pmid_nodes);
-                *pmid_nodes = pmid_nodes.union(&new_pmid_nodes).cloned().collect();
+                       account);
+                account.pmid_nodes = account.pmid_nodes.union(&new_pmid_nodes).cloned().collect();
                 trace!("Replicating {} - account after:  {:?}",
                        data_name,
-                       pmid_nodes);
-            }
-            if let Some(pmid_nodes) = self.accounts.get(data_name) {
-                self.send_refresh(routing_node, data_name, pmid_nodes);
+                       account);
+            });
+            if let Some(account) = self.accounts.get(data_name).cloned() {
+                self.send_refresh(routing_node, data_name, &account);
             }
         }
 
@@ -695,18 +1448,263 @@ impl ImmutableDataManager {
     }
 }
 
+impl Persona for ImmutableDataManager {
+    fn name(&self) -> &'static str {
+        PERSONA_NAME
+    }
+
+    fn accepts_request(&self, src: &Authority, dst: &Authority, content: &RequestContent) -> bool {
+        match (src, dst, content) {
+            (&Authority::Client{ .. },
+             &Authority::NaeManager(_),
+             &RequestContent::Get(DataRequest::Immutable(_, _), _)) |
+            (&Authority::NaeManager(_),
+             &Authority::NaeManager(_),
+             &RequestContent::Get(DataRequest::Immutable(_, _), _)) |
+            (&Authority::ClientManager(_),
+             &Authority::NaeManager(_),
+             &RequestContent::Put(Data::Immutable(_), _)) |
+            (&Authority::NaeManager(_),
+             &Authority::NaeManager(_),
+             &RequestContent::Put(Data::Immutable(_), _)) |
+            (&Authority::NodeManager(_),
+             &Authority::NaeManager(_),
+             &RequestContent::Post(Data::Plain(_), _)) |
+            (&Authority::ManagedNode(_),
+             &Authority::NaeManager(_),
+             &RequestContent::Post(Data::Plain(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    fn accepts_response(&self,
+                        src: &Authority,
+                        dst: &Authority,
+                        content: &ResponseContent)
+                        -> bool {
+        match (src, dst, content) {
+            (&Authority::ManagedNode(_),
+             &Authority::NaeManager(_),
+             &ResponseContent::GetSuccess(Data::Immutable(_), _)) |
+            (&Authority::ManagedNode(_),
+             &Authority::NaeManager(_),
+             &ResponseContent::GetFailure{ .. }) |
+            (&Authority::NaeManager(_),
+             &Authority::NaeManager(_),
+             &ResponseContent::GetSuccess(Data::Immutable(_), _)) |
+            (&Authority::NaeManager(_),
+             &Authority::NaeManager(_),
+             &ResponseContent::GetFailure{ .. }) |
+            (&Authority::NodeManager(_),
+             &Authority::NaeManager(_),
+             &ResponseContent::PutSuccess(..)) |
+            (&Authority::NodeManager(_),
+             &Authority::NaeManager(_),
+             &ResponseContent::PutFailure{ .. }) => true,
+            _ => false,
+        }
+    }
+
+    fn on_request(&mut self,
+                  routing_node: &RoutingNode,
+                  request: &RequestMessage)
+                  -> Result<(), InternalError> {
+        match request.content {
+            RequestContent::Get(..) => self.handle_get(routing_node, request),
+            RequestContent::Put(..) => self.handle_put(routing_node, request),
+            RequestContent::Post(..) => {
+                match request.src {
+                    Authority::ManagedNode(_) => self.handle_audit_response(routing_node, request),
+                    _ => self.handle_data_lost(routing_node, request),
+                }
+            }
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_response(&mut self,
+                   routing_node: &RoutingNode,
+                   response: &ResponseMessage)
+                   -> Result<(), InternalError> {
+        if let Authority::NaeManager(_) = response.src {
+            return match response.content {
+                ResponseContent::GetSuccess(..) => {
+                    self.handle_copy_get_success(routing_node, response)
+                }
+                ResponseContent::GetFailure{ .. } => {
+                    self.handle_copy_get_failure(routing_node, response)
+                }
+                _ => unreachable!("Error in vault demuxing"),
+            };
+        }
+
+        match response.content {
+            ResponseContent::GetSuccess(..) => self.handle_get_success(routing_node, response),
+            ResponseContent::GetFailure{ ref id, ref request, ref external_error_indicator } => {
+                self.handle_get_failure(routing_node,
+                                        response.src.name(),
+                                        id,
+                                        request,
+                                        external_error_indicator)
+            }
+            ResponseContent::PutSuccess(_, ref message_id) => {
+                self.handle_put_success(response.src.name(), message_id)
+            }
+            ResponseContent::PutFailure{ ref id, .. } => {
+                self.handle_put_failure(routing_node, response.src.name(), id)
+            }
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_refresh(&mut self,
+                  _routing_node: &RoutingNode,
+                  src: &Authority,
+                  dst: &Authority,
+                  refresh: &Refresh)
+                  -> Result<(), InternalError> {
+        match (src, dst) {
+            (&Authority::NaeManager(_), &Authority::NaeManager(_)) => {
+                let refresh_value = try!(refresh.value::<RefreshValue>());
+                self.handle_refresh(refresh.name,
+                                    refresh_value.account,
+                                    refresh_value.farming_rate);
+                Ok(())
+            }
+            _ => Err(InternalError::UnknownRefreshType(src.clone(), dst.clone(), refresh.clone())),
+        }
+    }
+
+    fn on_node_added(&mut self, routing_node: &RoutingNode, node_added: &XorName) {
+        self.handle_node_added(routing_node, *node_added)
+    }
+
+    fn on_node_lost(&mut self, routing_node: &RoutingNode, node_lost: &XorName) {
+        self.handle_node_lost(routing_node, *node_lost)
+    }
+
+    fn on_tick(&mut self, routing_node: &RoutingNode) {
+        for (data_name, metadata) in self.ongoing_gets.pop_expired() {
+            warn!("Ongoing get for {} expired - {:?}", data_name, metadata);
+            let _ = reply_with_get_failures(routing_node,
+                                            metadata.requests,
+                                            &ClientError::Timeout,
+                                            &mut self.client_errors);
+        }
+        self.merge_farming_rates();
+        self.expire_ongoing_puts();
+        self.check_audit_timeouts(routing_node);
+        self.audit_chunks(routing_node);
+    }
+
+    fn on_drain(&mut self, routing_node: &RoutingNode) {
+        let accounts = self.accounts
+                           .iter()
+                           .map(|(data_name, account)| (*data_name, account.clone()))
+                           .collect::<Vec<_>>();
+        for (data_name, account) in accounts {
+            self.send_refresh(routing_node, &data_name, &account);
+        }
+    }
+
+    // Chunk accounts belong to our old close groups; our new groups will refresh us with theirs.
+    // The farming rate is network-wide, so it's kept.
+    fn on_disconnected(&mut self) {
+        self.accounts.clear_in_memory();
+        self.ongoing_gets.clear();
+        self.ongoing_puts.clear();
+        self.ongoing_put_ids.clear();
+        self.furthest_group_members.clear();
+        self.ongoing_audits.clear();
+    }
+
+    fn account_names(&self) -> Vec<XorName> {
+        self.accounts.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn ongoing_requests(&self) -> Vec<String> {
+        let gets = self.ongoing_gets.keys().into_iter().map(|name| format!("Get {}", name));
+        let puts = self.ongoing_puts.iter().map(|(message_id, &(ref data, _))| {
+            format!("Put {} {:?}", data.name(), message_id)
+        });
+        let audits = self.ongoing_audits.keys().map(|name| format!("Audit {}", name));
+        gets.chain(puts).chain(audits).collect()
+    }
+
+    fn take_client_errors(&mut self) -> Vec<ClientError> {
+        mem::replace(&mut self.client_errors, Vec::new())
+    }
+
+    fn stats(&self) -> Vec<(&'static str, u64)> {
+        vec![("farming_rate", self.farming_rate),
+             ("accounts", self.accounts.len() as u64),
+             ("ongoing_gets", self.ongoing_gets.keys().len() as u64),
+             ("ongoing_puts", self.ongoing_puts.len() as u64),
+             ("ongoing_audits", self.ongoing_audits.len() as u64),
+             ("chunks_lost_total", self.chunks_lost),
+             ("refreshes_sent_total", self.refreshes_sent)]
+    }
+}
+
+// Clients waiting on an ongoing get displaced by the new `entry` are sent a `Timeout`, as they
+// would have been had it expired.
+fn insert_ongoing_get(routing_node: &RoutingNode,
+                      ongoing_gets: &mut ExpiringMap<XorName, MetadataForGetRequest>,
+                      client_errors: &mut Vec<ClientError>,
+                      data_name: XorName,
+                      entry: MetadataForGetRequest) {
+    if let Some((data_name, metadata)) = ongoing_gets.insert(data_name, entry) {
+        warn!("Ongoing get for {} evicted - {:?}", data_name, metadata);
+        let _ = reply_with_get_failures(routing_node,
+                                        metadata.requests,
+                                        &ClientError::Timeout,
+                                        client_errors);
+    }
+}
+
+// Sends a `GetFailure` carrying `error` for each of the clients' Get requests.  Each error sent is
+// added to `client_errors`.
+fn reply_with_get_failures(routing_node: &RoutingNode,
+                           requests: Vec<(MessageId, RequestMessage)>,
+                           error: &ClientError,
+                           client_errors: &mut Vec<ClientError>)
+                           -> Result<(), InternalError> {
+    let external_error_indicator = try!(serialisation::serialise(error));
+    for (message_id, request) in requests {
+        client_errors.push(error.clone());
+        let src = request.dst.clone();
+        let dst = request.src.clone();
+        trace!("Sending GetFailure back to {:?}", dst);
+        let _ = routing_node.send_get_failure(src,
+                                              dst,
+                                              request,
+                                              external_error_indicator.clone(),
+                                              message_id);
+    }
+    Ok(())
+}
+
 
 
 #[cfg(all(test, feature = "use-mock-routing"))]
 mod test {
     use super::*;
-    use maidsafe_utilities::log;
+    use super::{FARMING_RATE_SAMPLES, INITIAL_FARMING_RATE, PUT_TIMEOUT_SECS};
+    use clock;
+    use error::{ClientError, InternalError};
+    use journal::Record;
+    use maidsafe_utilities::{log, serialisation};
+    use personas::Persona;
     use rand::random;
     use routing::{Authority, Data, DataRequest, ImmutableData, ImmutableDataType, MessageId,
-                  RequestContent, RequestMessage};
+                  PlainData, RequestContent, RequestMessage, ResponseContent, ResponseMessage};
     use sodiumoxide::crypto::sign;
+    use std::collections::HashSet;
     use std::sync::mpsc;
-    use utils::generate_random_vec_u8;
+    use time::Duration;
+    use types::{Audit, DataLost};
+    use xor_name::{XOR_NAME_LEN, XorName};
+    use utils::{self, generate_random_vec_u8};
     use vault::RoutingNode;
 
     struct Environment {
@@ -719,7 +1717,8 @@ mod test {
     fn environment_setup() -> Environment {
         log::init(false);
         let routing = unwrap_result!(RoutingNode::new(mpsc::channel().0));
-        let immutable_data_manager = ImmutableDataManager::new();
+        let immutable_data_manager =
+            unwrap_result!(ImmutableDataManager::new(&utils::test_config()));
         loop {
             // Create random ImmutableData until we get one we're close to.
             let value = generate_random_vec_u8(1024);
@@ -735,6 +1734,82 @@ mod test {
         }
     }
 
+    impl Environment {
+        // Adds an account for `data` held by the first `REPLICANTS` of its close group, returning
+        // the holders.
+        fn add_account(&mut self) -> Vec<XorName> {
+            let data_name = self.data.name();
+            let holders = unwrap_option!(unwrap_result!(self.routing.close_group(data_name)),
+                                         "We are in the data's close group");
+            let holders = holders.into_iter().take(REPLICANTS).collect::<Vec<_>>();
+            let account = Account {
+                data_type: ImmutableDataType::Normal,
+                pmid_nodes: holders.iter().map(|holder| DataHolder::Good(*holder)).collect(),
+            };
+            let _ = self.immutable_data_manager.accounts.insert(data_name, account);
+            holders
+        }
+
+        // Has the client's managers Put `data`, returning the Put's message ID and the holders
+        // chosen for it.
+        fn put_data(&mut self) -> (MessageId, Vec<XorName>) {
+            let message_id = MessageId::new();
+            let request = RequestMessage {
+                src: Authority::ClientManager(random()),
+                dst: self.our_authority.clone(),
+                content: RequestContent::Put(Data::Immutable(self.data.clone()), message_id),
+            };
+            unwrap_result!(self.immutable_data_manager.handle_put(&self.routing, &request));
+            let holders = self.routing
+                              .put_requests_given()
+                              .into_iter()
+                              .filter_map(|put_request| {
+                                  match put_request.dst {
+                                      Authority::NodeManager(holder) => Some(holder),
+                                      _ => None,
+                                  }
+                              })
+                              .collect::<Vec<_>>();
+            assert_eq!(holders.len(), REPLICANTS);
+            (message_id, holders)
+        }
+
+        fn get_request(&self, client: &Authority, message_id: MessageId) -> RequestMessage {
+            RequestMessage {
+                src: client.clone(),
+                dst: self.our_authority.clone(),
+                content: RequestContent::Get(DataRequest::Immutable(self.data.name(),
+                                                                    ImmutableDataType::Normal),
+                                             message_id),
+            }
+        }
+
+        // A holder's notification that it's lost `data`, as passed on by its managers.
+        fn data_lost_request(&self, holder: XorName, handing_off: bool) -> RequestMessage {
+            let data_lost = DataLost {
+                data_name: self.data.name(),
+                size: self.data.payload_size() as u64,
+                handing_off: handing_off,
+            };
+            let value = unwrap_result!(serialisation::serialise(&data_lost));
+            RequestMessage {
+                src: Authority::NodeManager(holder),
+                dst: self.our_authority.clone(),
+                content: RequestContent::Post(Data::Plain(PlainData::new(self.data.name(),
+                                                                         value)),
+                                              MessageId::new()),
+            }
+        }
+    }
+
+    fn client() -> Authority {
+        Authority::Client {
+            client_key: sign::gen_keypair().0,
+            peer_id: random(),
+            proxy_node_name: random(),
+        }
+    }
+
     #[test]
     fn handle_put_get() {
         let mut env = environment_setup();
@@ -749,69 +1824,414 @@ mod test {
             unwrap_result!(env.immutable_data_manager
                               .handle_put(&env.routing, &request));
             let put_requests = env.routing.put_requests_given();
-            assert_eq!(put_requests.len(), REPLICANTS);
-            for i in 0..put_requests.len() {
-                assert_eq!(put_requests[i].src, env.our_authority);
-                assert_eq!(put_requests[i].content,
+            assert_eq!(put_requests.len(), REPLICANTS + 2);
+            let (holder_puts, copy_puts): (Vec<_>, Vec<_>) =
+                put_requests.into_iter().partition(|put_request| {
+                    match put_request.dst {
+                        Authority::NodeManager(_) => true,
+                        _ => false,
+                    }
+                });
+            assert_eq!(holder_puts.len(), REPLICANTS);
+            for put_request in holder_puts.iter() {
+                assert_eq!(put_request.src, env.our_authority);
+                assert_eq!(put_request.content,
                            RequestContent::Put(Data::Immutable(env.data.clone()),
                                                message_id.clone()));
             }
+            for (put_request, data_type) in copy_puts.iter()
+                                                     .zip(vec![ImmutableDataType::Backup,
+                                                               ImmutableDataType::Sacrificial]) {
+                let copy = ImmutableData::new(data_type, env.data.value().clone());
+                assert_eq!(put_request.src, env.our_authority);
+                assert_eq!(put_request.dst, Authority::NaeManager(copy.name()));
+                match put_request.content {
+                    RequestContent::Put(Data::Immutable(ref data), _) => assert_eq!(*data, copy),
+                    _ => unreachable!(),
+                }
+            }
         }
         {
-            let keys = sign::gen_keypair();
-            let from = random();
-            let client = Authority::Client {
-                client_key: keys.0,
-                peer_id: random(),
-                proxy_node_name: from,
-            };
+            let request = env.get_request(&client(), MessageId::new());
+            env.immutable_data_manager.handle_get(&env.routing, &request);
+            let get_requests = env.routing.get_requests_given();
+            assert_eq!(get_requests.len(), 0);
+        }
+    }
 
-            let message_id = MessageId::new();
-            let content = RequestContent::Get(DataRequest::Immutable(env.data.name().clone(),
-                                                                     ImmutableDataType::Normal),
-                                              message_id);
+    #[test]
+    fn recover_from_backup_copy() {
+        let mut env = environment_setup();
+        let holders = env.add_account();
+
+        // A client's Get goes to all the holders...
+        let client = client();
+        let message_id = MessageId::new();
+        let request = env.get_request(&client, message_id);
+        unwrap_result!(env.immutable_data_manager.handle_get(&env.routing, &request));
+        let get_requests = env.routing.get_requests_given();
+        assert_eq!(get_requests.len(), REPLICANTS);
+
+        // ...which all fail, so the Backup copy is requested from its managers.
+        let error_indicator = unwrap_result!(serialisation::serialise(&ClientError::NoSuchData));
+        for get_request in get_requests.iter() {
+            let _ = env.immutable_data_manager.handle_get_failure(&env.routing,
+                                                                  get_request.dst.name(),
+                                                                  &message_id,
+                                                                  get_request,
+                                                                  &error_indicator);
+        }
+        let backup = ImmutableData::new(ImmutableDataType::Backup, env.data.value().clone());
+        let get_requests = env.routing.get_requests_given();
+        assert_eq!(get_requests.len(), REPLICANTS + 1);
+        let copy_request = unwrap_option!(get_requests.last(), "");
+        assert_eq!(copy_request.src, env.our_authority);
+        assert_eq!(copy_request.dst, Authority::NaeManager(backup.name()));
+
+        // The recovered copy is returned to the client as Normal data and re-replicated.
+        let response = ResponseMessage {
+            src: copy_request.dst.clone(),
+            dst: copy_request.src.clone(),
+            content: ResponseContent::GetSuccess(Data::Immutable(backup), MessageId::new()),
+        };
+        unwrap_result!(env.immutable_data_manager.on_response(&env.routing, &response));
+        let get_successes = env.routing.get_successes_given();
+        assert_eq!(get_successes.len(), 1);
+        assert_eq!(get_successes[0].dst, client);
+        assert_eq!(get_successes[0].content,
+                   ResponseContent::GetSuccess(Data::Immutable(env.data.clone()), message_id));
+        let new_holders = env.routing
+                             .put_requests_given()
+                             .iter()
+                             .map(|put_request| put_request.dst.name().clone())
+                             .collect::<HashSet<_>>();
+        assert_eq!(new_holders.len(), REPLICANTS);
+        assert!(holders.iter().all(|holder| !new_holders.contains(holder)));
+    }
+
+    #[test]
+    fn holder_lost_data() {
+        let mut env = environment_setup();
+        let data_name = env.data.name();
+        let holders = env.add_account();
+
+        // The first holder's managers pass on its notification that the chunk has been lost.
+        let request = env.data_lost_request(holders[0], false);
+        unwrap_result!(env.immutable_data_manager.on_request(&env.routing, &request));
+
+        // The holder is dropped and the data is retrieved from the others to be replicated.
+        let account = unwrap_option!(env.immutable_data_manager.accounts.get(&data_name), "");
+        assert_eq!(account.pmid_nodes.len(), REPLICANTS - 1);
+        assert!(account.pmid_nodes.iter().all(|holder| *holder.name() != holders[0]));
+        let get_requests = env.routing.get_requests_given();
+        assert_eq!(get_requests.len(), REPLICANTS - 1);
+        assert!(get_requests.iter().all(|get_request| *get_request.dst.name() != holders[0]));
+        assert!(env.immutable_data_manager.ongoing_gets.get_mut(&data_name).is_some());
+    }
+
+    #[test]
+    fn holder_handing_off_data() {
+        let mut env = environment_setup();
+        let data_name = env.data.name();
+        let holders = env.add_account();
+
+        // The first holder is leaving the network, but still has the chunk.
+        let request = env.data_lost_request(holders[0], true);
+        unwrap_result!(env.immutable_data_manager.on_request(&env.routing, &request));
+
+        // The holder is dropped, but the data is retrieved from it as well as from the others.
+        let account = unwrap_option!(env.immutable_data_manager.accounts.get(&data_name), "");
+        assert_eq!(account.pmid_nodes.len(), REPLICANTS - 1);
+        assert!(account.pmid_nodes.iter().all(|holder| *holder.name() != holders[0]));
+        let get_requests = env.routing.get_requests_given();
+        assert_eq!(get_requests.len(), REPLICANTS);
+        assert!(get_requests.iter().any(|get_request| *get_request.dst.name() == holders[0]));
+    }
+
+    #[test]
+    fn expired_get_replies_with_timeout() {
+        let mut env = environment_setup();
+        let data_name = env.data.name();
+        let _ = env.add_account();
+
+        // None of the holders answer the client's Get.
+        let client = client();
+        let message_id = MessageId::new();
+        let request = env.get_request(&client, message_id);
+        unwrap_result!(env.immutable_data_manager.on_request(&env.routing, &request));
+        env.immutable_data_manager.on_tick(&env.routing);
+        assert!(env.routing.get_failures_given().is_empty());
+
+        clock::advance(Duration::minutes(5));
+        env.immutable_data_manager.on_tick(&env.routing);
+        let get_failures = env.routing.get_failures_given();
+        assert_eq!(get_failures.len(), 1);
+        assert_eq!(get_failures[0].dst, client);
+        match get_failures[0].content {
+            ResponseContent::GetFailure { ref id, ref external_error_indicator, .. } => {
+                assert_eq!(*id, message_id);
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::Timeout => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
+            }
+            _ => unreachable!(),
+        }
+        let client_errors = env.immutable_data_manager.take_client_errors();
+        assert_eq!(client_errors.len(), 1);
+        assert_eq!(client_errors[0].kind(), ClientError::Timeout.kind());
+        assert!(env.immutable_data_manager.ongoing_gets.get_mut(&data_name).is_none());
+    }
+
+    #[test]
+    fn displaced_get_replies_with_timeout() {
+        let mut env = environment_setup();
+        let _ = env.add_account();
+        let first_client = client();
+        let first_message_id = MessageId::new();
+        let request = env.get_request(&first_client, first_message_id);
+        unwrap_result!(env.immutable_data_manager.on_request(&env.routing, &request));
+
+        // Another client's Get replaces the expired entry before a tick has timed it out.
+        clock::advance(Duration::minutes(5));
+        let request = env.get_request(&client(), MessageId::new());
+        unwrap_result!(env.immutable_data_manager.on_request(&env.routing, &request));
+        let get_failures = env.routing.get_failures_given();
+        assert_eq!(get_failures.len(), 1);
+        assert_eq!(get_failures[0].dst, first_client);
+        match get_failures[0].content {
+            ResponseContent::GetFailure { ref id, ref external_error_indicator, .. } => {
+                assert_eq!(*id, first_message_id);
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::Timeout => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn audit_holders() {
+        let mut env = environment_setup();
+        let data_name = env.data.name();
+        let holders = env.add_account();
+
+        // Each holder is sent the same challenge.
+        env.immutable_data_manager.start_audit(&env.routing, &data_name);
+        let challenges = env.routing.post_requests_given();
+        assert_eq!(challenges.len(), REPLICANTS);
+        let nonce = match challenges[0].content {
+            RequestContent::Post(Data::Plain(ref data), _) => {
+                match unwrap_result!(serialisation::deserialise::<Audit>(&data.value())) {
+                    Audit::Challenge { nonce } => nonce,
+                    Audit::Response { .. } => panic!("Expected an audit challenge"),
+                }
+            }
+            _ => panic!("Expected a Post request"),
+        };
+
+        // All but the last holder answer correctly.
+        let serialised_chunk = unwrap_result!(serialisation::serialise(&env.data));
+        let digest = utils::audit_digest(&nonce, &serialised_chunk);
+        for (index, holder) in holders.iter().enumerate() {
+            let response = Audit::Response {
+                nonce: nonce.clone(),
+                digest: if index + 1 < holders.len() {
+                    digest.clone()
+                } else {
+                    vec![0; digest.len()]
+                },
+            };
+            let value = unwrap_result!(serialisation::serialise(&response));
             let request = RequestMessage {
-                src: client.clone(),
+                src: Authority::ManagedNode(*holder),
                 dst: env.our_authority.clone(),
-                content: content.clone(),
+                content: RequestContent::Post(Data::Plain(PlainData::new(data_name, value)),
+                                              MessageId::new()),
             };
-            env.immutable_data_manager.handle_get(&env.routing, &request);
-            let get_requests = env.routing.get_requests_given();
-            assert_eq!(get_requests.len(), 0);
+            unwrap_result!(env.immutable_data_manager.on_request(&env.routing, &request));
         }
+
+        // The last holder is marked as failed and the data is retrieved from the others.
+        let failed_holder = holders[REPLICANTS - 1];
+        let account = unwrap_option!(env.immutable_data_manager.accounts.get(&data_name), "");
+        assert!(account.pmid_nodes.contains(&DataHolder::Failed(failed_holder)));
+        assert!(!account.pmid_nodes.contains(&DataHolder::Good(failed_holder)));
+        let get_requests = env.routing.get_requests_given();
+        assert_eq!(get_requests.len(), REPLICANTS - 1);
+        assert!(get_requests.iter().all(|get_request| *get_request.dst.name() != failed_holder));
+        assert!(env.immutable_data_manager.ongoing_audits.is_empty());
+    }
+
+    #[test]
+    fn farming_rate() {
+        let mut env = environment_setup();
+        assert_eq!(env.immutable_data_manager.farming_rate, INITIAL_FARMING_RATE);
+
+        // Have a Sacrificial copy stored.
+        let sacrificial_data;
+        loop {
+            let data = ImmutableData::new(ImmutableDataType::Sacrificial,
+                                          generate_random_vec_u8(1024));
+            if unwrap_result!(env.routing.close_group(data.name())).is_some() {
+                sacrificial_data = data;
+                break;
+            }
+        }
+        let message_id = MessageId::new();
+        let request = RequestMessage {
+            src: Authority::NaeManager(random()),
+            dst: Authority::NaeManager(sacrificial_data.name()),
+            content: RequestContent::Put(Data::Immutable(sacrificial_data), message_id),
+        };
+        unwrap_result!(env.immutable_data_manager.handle_put(&env.routing, &request));
+        assert!(env.routing.put_successes_given().is_empty());
+        let put_requests = env.routing.put_requests_given();
+        assert_eq!(put_requests.len(), REPLICANTS);
+
+        // The rate rises once for the chunk, however many holders store it, and doesn't fall
+        // while any of them has.
+        unwrap_result!(env.immutable_data_manager
+                          .handle_put_success(put_requests[0].dst.name(), &message_id));
+        unwrap_result!(env.immutable_data_manager
+                          .handle_put_success(put_requests[1].dst.name(), &message_id));
+        assert_eq!(env.immutable_data_manager.farming_rate, INITIAL_FARMING_RATE + 1);
+        unwrap_result!(env.immutable_data_manager.handle_put_failure(&env.routing,
+                                                                     put_requests[2].dst.name(),
+                                                                     &message_id));
+        assert_eq!(env.immutable_data_manager.farming_rate, INITIAL_FARMING_RATE + 1);
+        // No replacement holder is sought for a Sacrificial copy.
+        assert_eq!(env.routing.put_requests_given().len(), REPLICANTS);
+    }
+
+    #[test]
+    fn farming_rate_merged_once_per_refresh_batch() {
+        let mut env = environment_setup();
+        let peer_farming_rate = INITIAL_FARMING_RATE + 10;
+
+        // Each chunk's refresh carries the peer's rate, but the whole batch counts as one sample.
+        for _ in 0..(FARMING_RATE_SAMPLES * 2) {
+            let account = Account {
+                data_type: ImmutableDataType::Normal,
+                pmid_nodes: HashSet::new(),
+            };
+            env.immutable_data_manager.handle_refresh(random(), account, peer_farming_rate);
+        }
+        assert_eq!(env.immutable_data_manager.accounts.len(), FARMING_RATE_SAMPLES * 2);
+        assert_eq!(env.immutable_data_manager.farming_rate, INITIAL_FARMING_RATE);
+        env.immutable_data_manager.on_tick(&env.routing);
+        assert_eq!(env.immutable_data_manager.peer_farming_rates,
+                   vec![peer_farming_rate]);
+        assert_eq!(env.immutable_data_manager.farming_rate,
+                   (INITIAL_FARMING_RATE + peer_farming_rate) / 2);
+
+        // A second batch moves our rate to the peers'.
+        for _ in 0..3 {
+            let account = Account {
+                data_type: ImmutableDataType::Normal,
+                pmid_nodes: HashSet::new(),
+            };
+            env.immutable_data_manager.handle_refresh(random(), account, peer_farming_rate);
+        }
+        env.immutable_data_manager.on_tick(&env.routing);
+        assert_eq!(env.immutable_data_manager.peer_farming_rates,
+                   vec![peer_farming_rate, peer_farming_rate]);
+        assert_eq!(env.immutable_data_manager.farming_rate, peer_farming_rate);
+    }
+
+    #[test]
+    fn failed_put_is_forgotten() {
+        let mut env = environment_setup();
+        let (message_id, holders) = env.put_data();
+        for holder in &holders[1..] {
+            unwrap_result!(env.immutable_data_manager.handle_put_success(holder, &message_id));
+        }
+        assert_eq!(env.immutable_data_manager.ongoing_puts.len(), 1);
+
+        // Each failed holder is replaced until the close group runs out of candidates, at which
+        // point none of the holders are still to answer.
+        let mut failed_holder = holders[0];
+        loop {
+            match env.immutable_data_manager
+                     .handle_put_failure(&env.routing, &failed_holder, &message_id) {
+                Ok(()) => {
+                    assert_eq!(env.immutable_data_manager.ongoing_puts.len(), 1);
+                    let put_requests = env.routing.put_requests_given();
+                    let replacement = unwrap_option!(put_requests.last(), "Replacement Put");
+                    failed_holder = *replacement.dst.name();
+                }
+                Err(InternalError::UnableToAllocateNewPmidNode) => break,
+                Err(error) => panic!("Unexpected error {:?}", error),
+            }
+        }
+        assert!(env.immutable_data_manager.ongoing_puts.is_empty());
+        assert!(env.immutable_data_manager.ongoing_put_ids.is_empty());
+    }
+
+    #[test]
+    fn unanswered_put_expires() {
+        let mut env = environment_setup();
+        let (message_id, holders) = env.put_data();
+        unwrap_result!(env.immutable_data_manager.handle_put_success(&holders[0], &message_id));
+
+        clock::advance(Duration::seconds(PUT_TIMEOUT_SECS - 1));
+        env.immutable_data_manager.on_tick(&env.routing);
+        assert_eq!(env.immutable_data_manager.ongoing_puts.len(), 1);
+        clock::advance(Duration::seconds(1));
+        env.immutable_data_manager.on_tick(&env.routing);
+        assert!(env.immutable_data_manager.ongoing_puts.is_empty());
+        assert!(env.immutable_data_manager.ongoing_put_ids.is_empty());
     }
 
     #[test]
     fn handle_churn() {
-        // let mut env = environment_setup();
-        // env.immutable_data_manager.handle_put(&env.routing, &env.data);
-        // let close_group = vec![env.our_authority.name().clone()]
-        //                       .into_iter()
-        //                       .chain(env.routing.close_group_including_self().into_iter())
-        //                       .collect();
-        // let churn_node = random();
-        // env.immutable_data_manager.handle_churn(&env.routing, close_group, &churn_node);
-        // let refresh_requests = env.routing.refresh_requests_given();
-        // assert_eq!(refresh_requests.len(), 2);
-        // {
-        //     // Account refresh
-        //     assert_eq!(refresh_requests[0].src.name().clone(), env.data.name());
-        //     let (type_tag, cause) = match refresh_requests[0].content {
-        //         RequestContent::Refresh{ type_tag, cause, .. } => (type_tag, cause),
-        //         _ => panic!("Invalid content type"),
-        //     };
-        //     assert_eq!(type_tag, ACCOUNT_TAG);
-        //     assert_eq!(cause, churn_node);
-        // }
-        // {
-        //     // Stats refresh
-        //     assert_eq!(refresh_requests[1].src.name().clone(), churn_node);
-        //     let (type_tag, cause) = match refresh_requests[1].content {
-        //         RequestContent::Refresh{ type_tag, cause, .. } => (type_tag, cause),
-        //         _ => panic!("Invalid content type"),
-        //     };
-        //     assert_eq!(type_tag, STATS_TAG);
-        //     assert_eq!(cause, churn_node);
-        // }
+        let mut env = environment_setup();
+        let data_name = env.data.name();
+        let holders = env.add_account();
+        assert_eq!(env.immutable_data_manager.accounts.held_by(&holders[0]).len(), 1);
+
+        // A node as far as possible from the data neither holds it nor is close to its holders,
+        // so the chunk isn't churned.
+        let mut far_name = data_name.0;
+        far_name[0] ^= 0x80;
+        let far_node = XorName(far_name);
+        env.immutable_data_manager.handle_node_lost(&env.routing, far_node);
+        env.immutable_data_manager.handle_node_added(&env.routing, far_node);
+        assert!(env.routing.refresh_requests_given().is_empty());
+        assert!(env.routing.get_requests_given().is_empty());
+
+        // Losing a holder removes it and triggers replication from the others.
+        env.immutable_data_manager.handle_node_lost(&env.routing, holders[0]);
+        assert_eq!(env.routing.refresh_requests_given().len(), 1);
+        let account = unwrap_option!(env.immutable_data_manager.accounts.get(&data_name), "");
+        assert!(account.pmid_nodes.iter().all(|holder| *holder.name() != holders[0]));
+        assert!(env.immutable_data_manager.accounts.held_by(&holders[0]).is_empty());
+        let get_requests = env.routing.get_requests_given();
+        assert_eq!(get_requests.len(), REPLICANTS - 1);
+        assert!(get_requests.iter().all(|get_request| *get_request.dst.name() != holders[0]));
+    }
+
+    #[test]
+    fn churn_outside_our_close_group() {
+        let mut env = environment_setup();
+        let data_name = env.data.name();
+        let holders = env.add_account();
+
+        // A node joins right next to the chunk, but so far from us that we aren't in its close
+        // group.  The chunk is still churned, so that its account is refreshed to the new node.
+        let mut near_name = data_name.0;
+        near_name[XOR_NAME_LEN - 1] ^= 1;
+        let near_node = XorName(near_name);
+        let routing = RoutingNode::replay();
+        let mut close_group = holders.clone();
+        close_group.push(near_node);
+        routing.apply_answer(&Record::CloseGroup(data_name, Some(close_group)));
+        assert!(unwrap_result!(routing.close_group(near_node)).is_none());
+
+        env.immutable_data_manager.handle_node_added(&routing, near_node);
+        assert_eq!(routing.refresh_requests_given().len(), 1);
+        let account = unwrap_option!(env.immutable_data_manager.accounts.get(&data_name), "");
+        assert_eq!(account.pmid_nodes.len(), holders.len());
     }
 }
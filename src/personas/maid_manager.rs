@@ -15,20 +15,27 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+use config_handler::Config;
 use error::{ClientError, InternalError};
-use lru_time_cache::LruCache;
+use expiring_map::ExpiringMap;
 use maidsafe_utilities::serialisation;
-use routing::{Authority, Data, MessageId, RequestContent, RequestMessage};
+use personas::Persona;
+use routing::{Authority, Data, DataRequest, MessageId, PlainData, RequestContent, RequestMessage,
+              ResponseContent, ResponseMessage};
 use sodiumoxide::crypto::hash::sha512;
+use state_store::StateStore;
 use std::collections::HashMap;
 use time::Duration;
-use types::{Refresh, RefreshValue};
+use types::Refresh;
 use utils;
 use vault::RoutingNode;
 use xor_name::XorName;
 
+pub const PERSONA_NAME: &'static str = "MaidManager";
+const STRUCTURED_DATA_SIZES: &'static str = "MaidManagerStructuredDataSizes";
+
 const DEFAULT_ACCOUNT_SIZE: u64 = 1_073_741_824;  // 1 GB
-const DEFAULT_PAYMENT: u64 = 1_048_576;  // 1 MB
+const MAX_CACHED_REQUESTS: usize = 1000;
 
 #[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
 pub struct Account {
@@ -55,6 +62,12 @@ impl Account {
         Ok(())
     }
 
+    // Charges for data which has already been stored, even if that leaves no space available.
+    fn charge_stored_data(&mut self, size: u64) {
+        self.data_stored += size;
+        self.space_available = self.space_available.saturating_sub(size);
+    }
+
     fn delete_data(&mut self, size: u64) {
         if self.data_stored < size {
             self.space_available += self.data_stored;
@@ -66,18 +79,90 @@ impl Account {
     }
 }
 
+// The size of a StructuredData as last Put or Posted by its owner, so that Posts can be charged
+// for growth and Deletes credited.  Sizes are stored apart from the accounts, one per data, so
+// that the `Account` encoding seen by clients and in older snapshots is unchanged and an update
+// only rewrites a single small record.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
+struct StructuredDataSize {
+    client_name: XorName,
+    data_name: XorName,
+    size: u64,
+}
+
+// Refresh value: account, and the size of each of the client's StructuredData
+type RefreshValue = (Account, Vec<(XorName, u64)>);
+
+// The name under which the size of the client's StructuredData is stored.
+fn size_key(client_name: &XorName, data_name: &XorName) -> XorName {
+    let mut input = client_name.0.to_vec();
+    input.extend(data_name.0.iter().cloned());
+    XorName(sha512::hash(&input).0)
+}
 
+// A client request forwarded to the NaeManager, with the amount charged to the client for it.
+struct CachedRequest {
+    request: RequestMessage,
+    charge: u64,
+}
 
 pub struct MaidManager {
-    accounts: HashMap<XorName, Account>,
-    request_cache: LruCache<MessageId, RequestMessage>,
+    accounts: StateStore<Account>,
+    structured_data_sizes: StateStore<StructuredDataSize>,
+    request_cache: ExpiringMap<MessageId, CachedRequest>,
+    // Requests which the client has been refunded for, kept in case they succeed late.
+    timed_out_requests: ExpiringMap<MessageId, CachedRequest>,
+    refreshes_sent: u64,
 }
 
 impl MaidManager {
-    pub fn new() -> MaidManager {
-        MaidManager {
-            accounts: HashMap::new(),
-            request_cache: LruCache::with_expiry_duration_and_capacity(Duration::minutes(5), 1000),
+    pub fn new(config: &Config) -> Result<MaidManager, InternalError> {
+        Ok(MaidManager {
+            accounts: try!(StateStore::open(config, PERSONA_NAME)),
+            structured_data_sizes: try!(StateStore::open(config, STRUCTURED_DATA_SIZES)),
+            request_cache: ExpiringMap::new(Duration::minutes(5), MAX_CACHED_REQUESTS),
+            timed_out_requests: ExpiringMap::new(Duration::hours(1), MAX_CACHED_REQUESTS),
+            refreshes_sent: 0,
+        })
+    }
+
+    // The account is returned as `PlainData` named after the client, holding the serialised
+    // `Account`.  Only the account's owner gets an answer.
+    pub fn handle_get(&mut self,
+                      routing_node: &RoutingNode,
+                      request: &RequestMessage)
+                      -> Result<(), InternalError> {
+        let (account_name, message_id) = match request.content {
+            RequestContent::Get(DataRequest::Plain(ref name), ref message_id) => {
+                (name.clone(), message_id.clone())
+            }
+            _ => unreachable!("Error in vault demuxing"),
+        };
+
+        let src = request.dst.clone();
+        let dst = request.src.clone();
+        let account = if utils::client_name(&request.src) == account_name {
+            self.accounts.get(&account_name)
+        } else {
+            None
+        };
+        match account {
+            Some(account) => {
+                let data = Data::Plain(PlainData::new(account_name,
+                                                      try!(serialisation::serialise(account))));
+                let _ = routing_node.send_get_success(src, dst, data, message_id);
+                Ok(())
+            }
+            None => {
+                let error = ClientError::NoSuchAccount;
+                let external_error_indicator = try!(serialisation::serialise(&error));
+                let _ = routing_node.send_get_failure(src,
+                                                      dst,
+                                                      request.clone(),
+                                                      external_error_indicator,
+                                                      message_id);
+                Err(InternalError::Client(error))
+            }
         }
     }
 
@@ -96,63 +181,178 @@ impl MaidManager {
         }
     }
 
-    pub fn handle_put_success(&mut self,
-                              routing_node: &RoutingNode,
-                              message_id: &MessageId)
-                              -> Result<(), InternalError> {
-        match self.request_cache.remove(message_id) {
-            Some(client_request) => {
-                // Send success response back to client
-                let message_hash =
-                    sha512::hash(&try!(serialisation::serialise(&client_request))[..]);
-                let src = client_request.dst;
-                let dst = client_request.src;
-                let _ = routing_node.send_put_success(src, dst, message_hash, message_id.clone());
-                Ok(())
+    // The client has nothing to pay for a Post which doesn't grow the StructuredData, and any
+    // shrinkage is credited once the Post has succeeded.
+    pub fn handle_post(&mut self,
+                       routing_node: &RoutingNode,
+                       request: &RequestMessage)
+                       -> Result<(), InternalError> {
+        let (data, message_id) = match request.content {
+            RequestContent::Post(Data::Structured(ref data), ref message_id) => {
+                (data, message_id.clone())
+            }
+            _ => unreachable!("Error in vault demuxing"),
+        };
+
+        // Data without a recorded size is charged in full.
+        let client_name = utils::client_name(&request.src);
+        let data_name = data.name();
+        let recorded_size = self.structured_data_sizes
+                                .get(&size_key(&client_name, &data_name))
+                                .map_or(0, |record| record.size);
+        let growth = (data.payload_size() as u64).saturating_sub(recorded_size);
+        let result = self.accounts
+                         .update(&client_name,
+                                 |account| account.put_data(growth).map(|()| growth))
+                         .unwrap_or(Err(ClientError::NoSuchAccount));
+        let charge = match result {
+            Ok(charge) => charge,
+            Err(error) => {
+                try!(self.reply_with_failure(routing_node, request.clone(), message_id, &error));
+                return Err(InternalError::Client(error));
+            }
+        };
+
+        let src = request.dst.clone();
+        let dst = Authority::NaeManager(data_name);
+        let _ = routing_node.send_post_request(src,
+                                               dst,
+                                               Data::Structured(data.clone()),
+                                               message_id.clone());
+        self.cache_request(routing_node, message_id, request.clone(), charge);
+        Ok(())
+    }
+
+    // Deletes are free, and the StructuredData's recorded size is credited once the Delete has
+    // succeeded.
+    pub fn handle_delete(&mut self,
+                         routing_node: &RoutingNode,
+                         request: &RequestMessage)
+                         -> Result<(), InternalError> {
+        let (data, message_id) = match request.content {
+            RequestContent::Delete(Data::Structured(ref data), ref message_id) => {
+                (data, message_id.clone())
             }
-            None => Err(InternalError::FailedToFindCachedRequest(message_id.clone())),
+            _ => unreachable!("Error in vault demuxing"),
+        };
+
+        if !self.accounts.contains_key(&utils::client_name(&request.src)) {
+            let error = ClientError::NoSuchAccount;
+            try!(self.reply_with_failure(routing_node, request.clone(), message_id, &error));
+            return Err(InternalError::Client(error));
         }
+
+        let src = request.dst.clone();
+        let dst = Authority::NaeManager(data.name());
+        let _ = routing_node.send_delete_request(src,
+                                                 dst,
+                                                 Data::Structured(data.clone()),
+                                                 message_id.clone());
+        self.cache_request(routing_node, message_id, request.clone(), 0);
+        Ok(())
     }
 
-    pub fn handle_put_failure(&mut self,
-                              routing_node: &RoutingNode,
-                              message_id: &MessageId,
-                              external_error_indicator: &Vec<u8>)
-                              -> Result<(), InternalError> {
-        match self.request_cache.remove(message_id) {
-            Some(client_request) => {
-                // Refund account
-                match self.accounts.get_mut(client_request.dst.name()) {
-                    Some(account) => {
-                        account.delete_data(DEFAULT_PAYMENT /* data.payload_size() as u64 */)
+    // Updates the client's account for the forwarded request which has succeeded, and passes the
+    // success on to the client.  If the request had timed out, the client was refunded, so it's
+    // charged again now that the data has been stored after all.
+    pub fn handle_success(&mut self,
+                          routing_node: &RoutingNode,
+                          message_id: &MessageId)
+                          -> Result<(), InternalError> {
+        let client_request = match self.request_cache.remove(message_id) {
+            Some(cached_request) => cached_request.request,
+            None => {
+                let cached_request = match self.timed_out_requests.remove(message_id) {
+                    Some(cached_request) => cached_request,
+                    None => {
+                        return Err(InternalError::FailedToFindCachedRequest(message_id.clone()))
                     }
-                    None => return Ok(()),
-                }
+                };
+                let charge = cached_request.charge;
+                let _ = self.accounts.update(&utils::client_name(&cached_request.request.src),
+                                             |account| account.charge_stored_data(charge));
+                cached_request.request
+            }
+        };
 
-                // Send failure response back to client
-                let error =
-                    try!(serialisation::deserialise::<ClientError>(external_error_indicator));
-                self.reply_with_put_failure(routing_node,
-                                            client_request,
-                                            message_id.clone(),
-                                            &error)
+        let client_name = utils::client_name(&client_request.src);
+        let message_hash = sha512::hash(&try!(serialisation::serialise(&client_request))[..]);
+        let src = client_request.dst.clone();
+        let dst = client_request.src.clone();
+        let id = message_id.clone();
+        match client_request.content {
+            RequestContent::Put(ref data, _) => {
+                if let Data::Structured(_) = *data {
+                    self.record_structured_data(client_name, data.name(), data.payload_size());
+                }
+                let _ = routing_node.send_put_success(src, dst, message_hash, id);
+            }
+            RequestContent::Post(ref data, _) => {
+                self.record_structured_data(client_name, data.name(), data.payload_size());
+                let _ = routing_node.send_post_success(src, dst, message_hash, id);
+            }
+            RequestContent::Delete(ref data, _) => {
+                self.delete_structured_data(&client_name, &data.name());
+                let _ = routing_node.send_delete_success(src, dst, message_hash, id);
             }
-            None => Err(InternalError::FailedToFindCachedRequest(message_id.clone())),
+            _ => unreachable!("Only Puts, Posts and Deletes are cached"),
         }
+        Ok(())
+    }
+
+    // Refunds the client for the forwarded request which has failed, and passes the failure on to
+    // the client.  If the cached client request has already expired, the client has been sent a
+    // `Timeout` and refunded, so nothing more is done.
+    pub fn handle_failure(&mut self,
+                          routing_node: &RoutingNode,
+                          message_id: &MessageId,
+                          external_error_indicator: &[u8])
+                          -> Result<(), InternalError> {
+        let cached_request = match self.request_cache.remove(message_id) {
+            Some(cached_request) => cached_request,
+            None => return Err(InternalError::FailedToFindCachedRequest(message_id.clone())),
+        };
+
+        let charge = cached_request.charge;
+        let _ = self.accounts.update(&utils::client_name(&cached_request.request.src),
+                                     |account| account.delete_data(charge));
+        self.send_failure(routing_node,
+                          cached_request.request,
+                          message_id.clone(),
+                          external_error_indicator.to_vec());
+        Ok(())
     }
 
-    pub fn handle_refresh(&mut self, name: XorName, account: Account) {
+    pub fn handle_refresh(&mut self, name: XorName, value: RefreshValue) {
+        let (account, sizes) = value;
         let _ = self.accounts.insert(name, account);
+        for (data_name, size) in sizes {
+            let record = StructuredDataSize {
+                client_name: name,
+                data_name: data_name,
+                size: size,
+            };
+            let _ = self.structured_data_sizes.insert(size_key(&name, &data_name), record);
+        }
     }
 
     pub fn handle_churn(&mut self, routing_node: &RoutingNode) {
+        let mut sizes = HashMap::<XorName, Vec<(XorName, u64)>>::new();
+        for (_, record) in self.structured_data_sizes.iter() {
+            sizes.entry(record.client_name)
+                 .or_insert_with(Vec::new)
+                 .push((record.data_name, record.size));
+        }
         for (maid_name, account) in self.accounts.iter() {
             let src = Authority::ClientManager(maid_name.clone());
-            let refresh = Refresh::new(maid_name,
-                                       RefreshValue::MaidManagerAccount(account.clone()));
-            if let Ok(serialised_refresh) = serialisation::serialise(&refresh) {
+            let value = (account.clone(), sizes.remove(maid_name).unwrap_or_else(Vec::new));
+            let refresh = Refresh::new(PERSONA_NAME, maid_name, &value);
+            if let Ok(serialised_refresh) = refresh.and_then(|refresh| {
+                serialisation::serialise(&refresh)
+            }) {
                 debug!("MaidManager sending refresh for account {:?}", src.name());
                 let _ = routing_node.send_refresh_request(src, serialised_refresh);
+                self.refreshes_sent += 1;
             }
         }
     }
@@ -189,10 +389,7 @@ impl MaidManager {
         if type_tag == 0 {
             if self.accounts.contains_key(&client_name) {
                 let error = ClientError::AccountExists;
-                try!(self.reply_with_put_failure(routing_node,
-                                                 request.clone(),
-                                                 message_id,
-                                                 &error));
+                try!(self.reply_with_failure(routing_node, request.clone(), message_id, &error));
                 return Err(InternalError::Client(error));
             }
 
@@ -210,17 +407,12 @@ impl MaidManager {
                            request: &RequestMessage)
                            -> Result<(), InternalError> {
         // Account must already exist to Put Data.
+        let charge = data.payload_size() as u64;
         let result = self.accounts
-                         .get_mut(&client_name)
-                         .ok_or(ClientError::NoSuchAccount)
-                         .and_then(|account| {
-                             account.put_data(DEFAULT_PAYMENT /* data.payload_size() as u64 */)
-                         });
+                         .update(&client_name, |account| account.put_data(charge))
+                         .unwrap_or(Err(ClientError::NoSuchAccount));
         if let Err(error) = result {
-            try!(self.reply_with_put_failure(routing_node,
-                                             request.clone(),
-                                             message_id,
-                                             &error));
+            try!(self.reply_with_failure(routing_node, request.clone(), message_id, &error));
             return Err(InternalError::Client(error));
         }
 
@@ -231,28 +423,258 @@ impl MaidManager {
             let _ = routing_node.send_put_request(src, dst, data, message_id.clone());
         }
 
-        if let Some(prior_request) = self.request_cache
-                                         .insert(message_id.clone(), request.clone()) {
-            error!("Overwrote existing cached request: {:?}", prior_request);
+        self.cache_request(routing_node, message_id, request.clone(), charge);
+        Ok(())
+    }
+
+    // A cached request displaced by the new one is timed out, as if it had expired.
+    fn cache_request(&mut self,
+                     routing_node: &RoutingNode,
+                     message_id: MessageId,
+                     request: RequestMessage,
+                     charge: u64) {
+        let cached_request = CachedRequest {
+            request: request,
+            charge: charge,
+        };
+        if let Some((message_id, displaced)) = self.request_cache.insert(message_id,
+                                                                         cached_request) {
+            warn!("Evicted cached request {:?} - {:?}", message_id, displaced.request);
+            self.time_out(routing_node, message_id, displaced);
+        }
+    }
+
+    // Refunds the client for a request which has had no response in time and sends it a
+    // `Timeout`.
+    fn time_out(&mut self,
+                routing_node: &RoutingNode,
+                message_id: MessageId,
+                cached_request: CachedRequest) {
+        let charge = cached_request.charge;
+        let _ = self.accounts.update(&utils::client_name(&cached_request.request.src),
+                                     |account| account.delete_data(charge));
+        let _ = self.reply_with_failure(routing_node,
+                                        cached_request.request.clone(),
+                                        message_id.clone(),
+                                        &ClientError::Timeout);
+        let _ = self.timed_out_requests.insert(message_id, cached_request);
+    }
+
+    // Records the size of a StructuredData once stored, crediting its owner for any shrinkage.
+    fn record_structured_data(&mut self, client_name: XorName, data_name: XorName, size: usize) {
+        let record = StructuredDataSize {
+            client_name: client_name,
+            data_name: data_name,
+            size: size as u64,
+        };
+        let recorded_size = self.structured_data_sizes
+                                .insert(size_key(&client_name, &data_name), record)
+                                .map_or(0, |record| record.size);
+        if recorded_size > size as u64 {
+            let shrinkage = recorded_size - size as u64;
+            let _ = self.accounts.update(&client_name, |account| account.delete_data(shrinkage));
+        }
+    }
+
+    // Credits the owner of a deleted StructuredData with its recorded size.
+    fn delete_structured_data(&mut self, client_name: &XorName, data_name: &XorName) {
+        if let Some(record) = self.structured_data_sizes.remove(&size_key(client_name, data_name)) {
+            let _ = self.accounts.update(client_name, |account| account.delete_data(record.size));
         }
+    }
+
+    fn reply_with_failure(&self,
+                          routing_node: &RoutingNode,
+                          request: RequestMessage,
+                          message_id: MessageId,
+                          error: &ClientError)
+                          -> Result<(), InternalError> {
+        let external_error_indicator = try!(serialisation::serialise(error));
+        self.send_failure(routing_node, request, message_id, external_error_indicator);
         Ok(())
     }
 
-    fn reply_with_put_failure(&self,
-                              routing_node: &RoutingNode,
-                              request: RequestMessage,
-                              message_id: MessageId,
-                              error: &ClientError)
-                              -> Result<(), InternalError> {
+    // Sends the failure response matching the kind of the client's `request`.
+    fn send_failure(&self,
+                    routing_node: &RoutingNode,
+                    request: RequestMessage,
+                    message_id: MessageId,
+                    external_error_indicator: Vec<u8>) {
         let src = request.dst.clone();
         let dst = request.src.clone();
-        let external_error_indicator = try!(serialisation::serialise(error));
-        let _ = routing_node.send_put_failure(src,
+        let _ = match request.content {
+            RequestContent::Post(..) => {
+                routing_node.send_post_failure(src,
+                                               dst,
+                                               request,
+                                               external_error_indicator,
+                                               message_id)
+            }
+            RequestContent::Delete(..) => {
+                routing_node.send_delete_failure(src,
+                                                 dst,
+                                                 request,
+                                                 external_error_indicator,
+                                                 message_id)
+            }
+            _ => {
+                routing_node.send_put_failure(src,
                                               dst,
                                               request,
                                               external_error_indicator,
-                                              message_id);
-        Ok(())
+                                              message_id)
+            }
+        };
+    }
+}
+
+impl Persona for MaidManager {
+    fn name(&self) -> &'static str {
+        PERSONA_NAME
+    }
+
+    fn accepts_request(&self, src: &Authority, dst: &Authority, content: &RequestContent) -> bool {
+        match (src, dst, content) {
+            (&Authority::Client{ .. },
+             &Authority::ClientManager(_),
+             &RequestContent::Put(Data::Immutable(_), _)) |
+            (&Authority::Client{ .. },
+             &Authority::ClientManager(_),
+             &RequestContent::Put(Data::Structured(_), _)) |
+            (&Authority::Client{ .. },
+             &Authority::ClientManager(_),
+             &RequestContent::Post(Data::Structured(_), _)) |
+            (&Authority::Client{ .. },
+             &Authority::ClientManager(_),
+             &RequestContent::Delete(Data::Structured(_), _)) |
+            (&Authority::Client{ .. },
+             &Authority::ClientManager(_),
+             &RequestContent::Get(DataRequest::Plain(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    fn accepts_response(&self,
+                        src: &Authority,
+                        dst: &Authority,
+                        content: &ResponseContent)
+                        -> bool {
+        match (src, dst, content) {
+            (&Authority::NaeManager(_),
+             &Authority::ClientManager(_),
+             &ResponseContent::PutSuccess(..)) |
+            (&Authority::NaeManager(_),
+             &Authority::ClientManager(_),
+             &ResponseContent::PutFailure{ .. }) |
+            (&Authority::NaeManager(_),
+             &Authority::ClientManager(_),
+             &ResponseContent::PostSuccess(..)) |
+            (&Authority::NaeManager(_),
+             &Authority::ClientManager(_),
+             &ResponseContent::PostFailure{ .. }) |
+            (&Authority::NaeManager(_),
+             &Authority::ClientManager(_),
+             &ResponseContent::DeleteSuccess(..)) |
+            (&Authority::NaeManager(_),
+             &Authority::ClientManager(_),
+             &ResponseContent::DeleteFailure{ .. }) => true,
+            _ => false,
+        }
+    }
+
+    fn on_request(&mut self,
+                  routing_node: &RoutingNode,
+                  request: &RequestMessage)
+                  -> Result<(), InternalError> {
+        match request.content {
+            RequestContent::Get(..) => self.handle_get(routing_node, request),
+            RequestContent::Put(..) => self.handle_put(routing_node, request),
+            RequestContent::Post(..) => self.handle_post(routing_node, request),
+            RequestContent::Delete(..) => self.handle_delete(routing_node, request),
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_response(&mut self,
+                   routing_node: &RoutingNode,
+                   response: &ResponseMessage)
+                   -> Result<(), InternalError> {
+        match response.content {
+            ResponseContent::PutSuccess(_, ref message_id) |
+            ResponseContent::PostSuccess(_, ref message_id) |
+            ResponseContent::DeleteSuccess(_, ref message_id) => {
+                self.handle_success(routing_node, message_id)
+            }
+            ResponseContent::PutFailure{ ref id, ref external_error_indicator, .. } |
+            ResponseContent::PostFailure{ ref id, ref external_error_indicator, .. } |
+            ResponseContent::DeleteFailure{ ref id, ref external_error_indicator, .. } => {
+                self.handle_failure(routing_node, id, external_error_indicator)
+            }
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_refresh(&mut self,
+                  _routing_node: &RoutingNode,
+                  src: &Authority,
+                  dst: &Authority,
+                  refresh: &Refresh)
+                  -> Result<(), InternalError> {
+        match (src, dst) {
+            (&Authority::ClientManager(_), &Authority::ClientManager(_)) => {
+                let value = try!(refresh.value::<RefreshValue>());
+                self.handle_refresh(refresh.name, value);
+                Ok(())
+            }
+            _ => Err(InternalError::UnknownRefreshType(src.clone(), dst.clone(), refresh.clone())),
+        }
+    }
+
+    fn on_node_added(&mut self, routing_node: &RoutingNode, _node_added: &XorName) {
+        self.handle_churn(routing_node)
+    }
+
+    fn on_node_lost(&mut self, routing_node: &RoutingNode, _node_lost: &XorName) {
+        self.handle_churn(routing_node)
+    }
+
+    fn on_drain(&mut self, routing_node: &RoutingNode) {
+        self.handle_churn(routing_node)
+    }
+
+    // Clients whose requests have had no response in time are refunded and sent a `Timeout`.
+    fn on_tick(&mut self, routing_node: &RoutingNode) {
+        for (message_id, cached_request) in self.request_cache.pop_expired() {
+            warn!("Request {:?} expired - {:?}", message_id, cached_request.request);
+            self.time_out(routing_node, message_id, cached_request);
+        }
+        let _ = self.timed_out_requests.pop_expired();
+    }
+
+    // We rejoin under a new name, so we will no longer be managing the same clients.
+    fn on_disconnected(&mut self) {
+        self.accounts.clear_in_memory();
+        self.structured_data_sizes.clear_in_memory();
+        self.request_cache.clear();
+        self.timed_out_requests.clear();
+    }
+
+    fn stats(&self) -> Vec<(&'static str, u64)> {
+        vec![("accounts", self.accounts.iter().len() as u64),
+             ("ongoing_requests", self.request_cache.keys().len() as u64),
+             ("refreshes_sent_total", self.refreshes_sent)]
+    }
+
+    fn account_names(&self) -> Vec<XorName> {
+        self.accounts.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn ongoing_requests(&self) -> Vec<String> {
+        self.request_cache
+            .keys()
+            .into_iter()
+            .map(|message_id| format!("Request {:?}", message_id))
+            .collect()
     }
 }
 
@@ -260,14 +682,22 @@ impl MaidManager {
 #[cfg(all(test, feature = "use-mock-routing"))]
 mod test {
     use super::*;
+    use super::{MAX_CACHED_REQUESTS, RefreshValue};
+    use clock;
     use error::{ClientError, InternalError};
     use maidsafe_utilities::serialisation;
+    use personas::Persona;
     use rand::random;
-    use routing::{Authority, Data, ImmutableData, ImmutableDataType, MessageId, RequestContent,
-                  RequestMessage, ResponseContent};
+    use routing::{Authority, Data, DataRequest, ImmutableData, ImmutableDataType, MessageId,
+                  RequestContent, RequestMessage, ResponseContent, StructuredData};
     use sodiumoxide::crypto::sign;
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+    use std::io::Write;
     use std::sync::mpsc;
-    use utils::generate_random_vec_u8;
+    use time::Duration;
+    use types::Refresh;
+    use utils::{self, generate_random_vec_u8};
     use vault::RoutingNode;
     use xor_name::XorName;
 
@@ -289,7 +719,7 @@ mod test {
                 proxy_node_name: from.clone(),
             },
             routing: unwrap_result!(RoutingNode::new(mpsc::channel().0)),
-            maid_manager: MaidManager::new(),
+            maid_manager: unwrap_result!(MaidManager::new(&utils::test_config())),
         }
     }
 
@@ -342,6 +772,371 @@ mod test {
         // assert_eq!(put_requests[0].data, Data::Immutable(data));
     }
 
+    #[test]
+    fn charge_and_refund_by_payload_size() {
+        let mut env = environment_setup();
+        let client_name = utils::client_name(&env.client);
+        let _ = env.maid_manager.accounts.insert(client_name, Account::default());
+
+        let immutable_data = ImmutableData::new(ImmutableDataType::Normal,
+                                                generate_random_vec_u8(1024));
+        let payload_size = immutable_data.payload_size() as u64;
+        let message_id = MessageId::new();
+        let valid_request = RequestMessage {
+            src: env.client.clone(),
+            dst: env.our_authority.clone(),
+            content: RequestContent::Put(Data::Immutable(immutable_data), message_id.clone()),
+        };
+        unwrap_result!(env.maid_manager.handle_put(&env.routing, &valid_request));
+        match env.maid_manager.accounts.get(&client_name) {
+            Some(account) => assert_eq!(account.data_stored, payload_size),
+            None => unreachable!(),
+        }
+
+        // Refund the forwarded request as if the NaeManager had rejected it.
+        let put_requests = env.routing.put_requests_given();
+        assert_eq!(put_requests.len(), 1);
+        let external_error_indicator =
+            unwrap_result!(serialisation::serialise(&ClientError::DataExists));
+        unwrap_result!(env.maid_manager.handle_failure(&env.routing,
+                                                       &message_id,
+                                                       &external_error_indicator));
+        assert_eq!(env.maid_manager.accounts.get(&client_name), Some(&Account::default()));
+        assert_eq!(env.routing.put_failures_given().len(), 1);
+    }
+
+    #[test]
+    fn expired_put_is_refunded() {
+        let mut env = environment_setup();
+        let client_name = utils::client_name(&env.client);
+        let _ = env.maid_manager.accounts.insert(client_name, Account::default());
+
+        let immutable_data = ImmutableData::new(ImmutableDataType::Normal,
+                                                generate_random_vec_u8(1024));
+        let message_id = MessageId::new();
+        let valid_request = RequestMessage {
+            src: env.client.clone(),
+            dst: env.our_authority.clone(),
+            content: RequestContent::Put(Data::Immutable(immutable_data), message_id.clone()),
+        };
+        unwrap_result!(env.maid_manager.handle_put(&env.routing, &valid_request));
+
+        // Nothing happens until the request has been outstanding for the full timeout.
+        env.maid_manager.on_tick(&env.routing);
+        assert!(env.routing.put_failures_given().is_empty());
+        clock::advance(Duration::minutes(5));
+        env.maid_manager.on_tick(&env.routing);
+        assert_eq!(env.maid_manager.accounts.get(&client_name), Some(&Account::default()));
+        let put_failures = env.routing.put_failures_given();
+        assert_eq!(put_failures.len(), 1);
+        assert_eq!(put_failures[0].dst, env.client);
+        match put_failures[0].content {
+            ResponseContent::PutFailure { ref id, ref external_error_indicator, .. } => {
+                assert_eq!(*id, message_id);
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::Timeout => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        // A late failure from the NaeManager doesn't refund the client a second time.
+        let external_error_indicator =
+            unwrap_result!(serialisation::serialise(&ClientError::DataExists));
+        assert!(env.maid_manager
+                   .handle_failure(&env.routing, &message_id, &external_error_indicator)
+                   .is_err());
+        assert_eq!(env.maid_manager.accounts.get(&client_name), Some(&Account::default()));
+    }
+
+    #[test]
+    fn late_success_is_charged_again() {
+        let mut env = environment_setup();
+        let client_name = utils::client_name(&env.client);
+        let _ = env.maid_manager.accounts.insert(client_name, Account::default());
+
+        let immutable_data = ImmutableData::new(ImmutableDataType::Normal,
+                                                generate_random_vec_u8(1024));
+        let payload_size = immutable_data.payload_size() as u64;
+        let message_id = MessageId::new();
+        let valid_request = RequestMessage {
+            src: env.client.clone(),
+            dst: env.our_authority.clone(),
+            content: RequestContent::Put(Data::Immutable(immutable_data), message_id.clone()),
+        };
+        unwrap_result!(env.maid_manager.handle_put(&env.routing, &valid_request));
+        clock::advance(Duration::minutes(5));
+        env.maid_manager.on_tick(&env.routing);
+        assert_eq!(env.maid_manager.accounts.get(&client_name), Some(&Account::default()));
+
+        // The data was stored after all, so the refund is taken back and the success passed on.
+        unwrap_result!(env.maid_manager.handle_success(&env.routing, &message_id));
+        match env.maid_manager.accounts.get(&client_name) {
+            Some(account) => assert_eq!(account.data_stored, payload_size),
+            None => unreachable!(),
+        }
+        assert_eq!(env.routing.put_successes_given().len(), 1);
+        assert!(env.maid_manager.handle_success(&env.routing, &message_id).is_err());
+    }
+
+    #[test]
+    fn evicted_request_is_refunded() {
+        let mut env = environment_setup();
+        let client_name = utils::client_name(&env.client);
+        let _ = env.maid_manager.accounts.insert(client_name, Account::default());
+
+        let mut message_ids = Vec::new();
+        let mut payload_sizes = Vec::new();
+        for _ in 0..(MAX_CACHED_REQUESTS + 1) {
+            let message_id = MessageId::new();
+            let immutable_data = ImmutableData::new(ImmutableDataType::Normal,
+                                                    generate_random_vec_u8(1024));
+            payload_sizes.push(immutable_data.payload_size() as u64);
+            let valid_request = RequestMessage {
+                src: env.client.clone(),
+                dst: env.our_authority.clone(),
+                content: RequestContent::Put(Data::Immutable(immutable_data),
+                                             message_id.clone()),
+            };
+            unwrap_result!(env.maid_manager.handle_put(&env.routing, &valid_request));
+            message_ids.push(message_id);
+            clock::advance(Duration::milliseconds(1));
+        }
+
+        // Only the oldest request is evicted, and its client is refunded and sent a `Timeout`.
+        let put_failures = env.routing.put_failures_given();
+        assert_eq!(put_failures.len(), 1);
+        match put_failures[0].content {
+            ResponseContent::PutFailure { ref id, ref external_error_indicator, .. } => {
+                assert_eq!(*id, message_ids[0]);
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::Timeout => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
+            }
+            _ => unreachable!(),
+        }
+        let charged = payload_sizes[1..].iter().fold(0, |total, size| total + size);
+        match env.maid_manager.accounts.get(&client_name) {
+            Some(account) => assert_eq!(account.data_stored, charged),
+            None => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn post_and_delete_structured_data() {
+        let mut env = environment_setup();
+        let client_name = utils::client_name(&env.client);
+        let _ = env.maid_manager.accounts.insert(client_name, Account::default());
+        let identifier = random::<XorName>();
+        let versions = [1000, 3000, 500, 2000]
+                           .iter()
+                           .enumerate()
+                           .map(|(version, size)| {
+                               unwrap_result!(StructuredData::new(100,
+                                                                  identifier,
+                                                                  version as u64,
+                                                                  generate_random_vec_u8(*size),
+                                                                  vec![],
+                                                                  vec![],
+                                                                  None))
+                           })
+                           .collect::<Vec<_>>();
+        let data_name = versions[0].name();
+        let data_stored = |maid_manager: &MaidManager| {
+            unwrap_option!(maid_manager.accounts.get(&client_name), "").data_stored
+        };
+        let (client, our_authority) = (env.client.clone(), env.our_authority.clone());
+        let request = move |content| {
+            RequestMessage {
+                src: client.clone(),
+                dst: our_authority.clone(),
+                content: content,
+            }
+        };
+
+        let message_id = MessageId::new();
+        let put_request = request(RequestContent::Put(Data::Structured(versions[0].clone()),
+                                                      message_id.clone()));
+        unwrap_result!(env.maid_manager.handle_put(&env.routing, &put_request));
+        unwrap_result!(env.maid_manager.handle_success(&env.routing, &message_id));
+        assert_eq!(data_stored(&env.maid_manager),
+                   versions[0].payload_size() as u64);
+
+        // Growth is charged up front, shrinkage is credited on success.
+        for data in &versions[1..3] {
+            let message_id = MessageId::new();
+            let post_request = request(RequestContent::Post(Data::Structured(data.clone()),
+                                                            message_id.clone()));
+            unwrap_result!(env.maid_manager.handle_post(&env.routing, &post_request));
+            unwrap_result!(env.maid_manager.handle_success(&env.routing, &message_id));
+            assert_eq!(data_stored(&env.maid_manager), data.payload_size() as u64);
+        }
+        let post_requests = env.routing.post_requests_given();
+        assert_eq!(post_requests.len(), 2);
+        assert_eq!(post_requests[0].dst, Authority::NaeManager(data_name));
+        assert_eq!(env.routing.post_successes_given().len(), 2);
+
+        // A failed Post is refunded.
+        let message_id = MessageId::new();
+        let post_request = request(RequestContent::Post(Data::Structured(versions[3].clone()),
+                                                        message_id.clone()));
+        unwrap_result!(env.maid_manager.handle_post(&env.routing, &post_request));
+        assert_eq!(data_stored(&env.maid_manager),
+                   versions[3].payload_size() as u64);
+        unwrap_result!(env.maid_manager.handle_failure(&env.routing, &message_id, &[]));
+        assert_eq!(data_stored(&env.maid_manager),
+                   versions[2].payload_size() as u64);
+        let post_failures = env.routing.post_failures_given();
+        assert_eq!(post_failures.len(), 1);
+        assert_eq!(post_failures[0].dst, env.client);
+
+        // Deletion credits the data's recorded size.
+        let message_id = MessageId::new();
+        let delete_request = request(RequestContent::Delete(Data::Structured(versions[3].clone()),
+                                                            message_id.clone()));
+        unwrap_result!(env.maid_manager.handle_delete(&env.routing, &delete_request));
+        assert_eq!(env.routing.delete_requests_given().len(), 1);
+        unwrap_result!(env.maid_manager.handle_success(&env.routing, &message_id));
+        assert_eq!(env.maid_manager.accounts.get(&client_name), Some(&Account::default()));
+        assert_eq!(env.routing.delete_successes_given().len(), 1);
+    }
+
+    #[test]
+    fn refresh_carries_structured_data_sizes() {
+        let mut env = environment_setup();
+        let client_name = utils::client_name(&env.client);
+        let _ = env.maid_manager.accounts.insert(client_name, Account::default());
+        let identifier = random::<XorName>();
+        let versions = [1000, 3000]
+                           .iter()
+                           .enumerate()
+                           .map(|(version, size)| {
+                               unwrap_result!(StructuredData::new(100,
+                                                                  identifier,
+                                                                  version as u64,
+                                                                  generate_random_vec_u8(*size),
+                                                                  vec![],
+                                                                  vec![],
+                                                                  None))
+                           })
+                           .collect::<Vec<_>>();
+        let message_id = MessageId::new();
+        let put_request = RequestMessage {
+            src: env.client.clone(),
+            dst: env.our_authority.clone(),
+            content: RequestContent::Put(Data::Structured(versions[0].clone()),
+                                         message_id.clone()),
+        };
+        unwrap_result!(env.maid_manager.handle_put(&env.routing, &put_request));
+        unwrap_result!(env.maid_manager.handle_success(&env.routing, &message_id));
+
+        env.maid_manager.handle_churn(&env.routing);
+        let refresh_requests = env.routing.refresh_requests_given();
+        assert_eq!(refresh_requests.len(), 1);
+        let value = match refresh_requests[0].content {
+            RequestContent::Refresh(ref content) => {
+                let refresh: Refresh = unwrap_result!(serialisation::deserialise(content));
+                unwrap_result!(refresh.value::<RefreshValue>())
+            }
+            _ => unreachable!(),
+        };
+        let mut other_manager = unwrap_result!(MaidManager::new(&utils::test_config()));
+        other_manager.handle_refresh(client_name, value);
+
+        // The other manager only charges the growth of the data it was told about.
+        let post_request = RequestMessage {
+            src: env.client.clone(),
+            dst: env.our_authority.clone(),
+            content: RequestContent::Post(Data::Structured(versions[1].clone()),
+                                          MessageId::new()),
+        };
+        unwrap_result!(other_manager.handle_post(&env.routing, &post_request));
+        match other_manager.accounts.get(&client_name) {
+            Some(account) => assert_eq!(account.data_stored, versions[1].payload_size() as u64),
+            None => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn load_accounts_from_older_snapshot() {
+        // The accounts' encoding hasn't changed, so snapshots written by earlier versions load.
+        #[derive(RustcEncodable)]
+        struct OldAccount {
+            data_stored: u64,
+            space_available: u64,
+        }
+        let config = utils::test_config();
+        let client_name = random::<XorName>();
+        let mut accounts = HashMap::new();
+        let _ = accounts.insert(client_name,
+                                OldAccount {
+                                    data_stored: 1000,
+                                    space_available: 2000,
+                                });
+        let state_dir = config.root_dir().join("state");
+        unwrap_result!(fs::create_dir_all(&state_dir));
+        {
+            let mut file = unwrap_result!(File::create(state_dir.join("MaidManager.snapshot")));
+            unwrap_result!(file.write_all(&unwrap_result!(serialisation::serialise(&accounts))));
+        }
+
+        let maid_manager = unwrap_result!(MaidManager::new(&config));
+        let expected = Account {
+            data_stored: 1000,
+            space_available: 2000,
+        };
+        assert_eq!(maid_manager.accounts.get(&client_name), Some(&expected));
+    }
+
+    #[test]
+    fn handle_get_account() {
+        let mut env = environment_setup();
+        let client_name = utils::client_name(&env.client);
+        let _ = env.maid_manager.accounts.insert(client_name, Account::default());
+
+        // The owner can read its account.
+        let message_id = MessageId::new();
+        let request = RequestMessage {
+            src: env.client.clone(),
+            dst: env.our_authority.clone(),
+            content: RequestContent::Get(DataRequest::Plain(client_name), message_id.clone()),
+        };
+        unwrap_result!(env.maid_manager.handle_get(&env.routing, &request));
+        let get_successes = env.routing.get_successes_given();
+        assert_eq!(get_successes.len(), 1);
+        assert_eq!(get_successes[0].dst, env.client);
+        match get_successes[0].content {
+            ResponseContent::GetSuccess(Data::Plain(ref plain_data), ref id) => {
+                assert_eq!(*id, message_id);
+                assert_eq!(plain_data.name(), client_name);
+                let account: Account =
+                    unwrap_result!(serialisation::deserialise(&plain_data.value()));
+                assert_eq!(account, Account::default());
+            }
+            _ => unreachable!(),
+        }
+
+        // Anyone else is refused.
+        let other_client = Authority::Client {
+            client_key: sign::gen_keypair().0,
+            peer_id: random(),
+            proxy_node_name: random(),
+        };
+        let request = RequestMessage {
+            src: other_client,
+            dst: env.our_authority.clone(),
+            content: RequestContent::Get(DataRequest::Plain(client_name), MessageId::new()),
+        };
+        match env.maid_manager.handle_get(&env.routing, &request) {
+            Err(InternalError::Client(ClientError::NoSuchAccount)) => (),
+            _ => unreachable!(),
+        }
+        assert_eq!(env.routing.get_successes_given().len(), 1);
+        assert_eq!(env.routing.get_failures_given().len(), 1);
+    }
+
     // #[test]
     // fn handle_churn_and_account_transfer() {
     //     let churn_node = random();
@@ -21,3 +21,298 @@ pub mod mpid_manager;
 pub mod pmid_manager;
 pub mod pmid_node;
 pub mod structured_data_manager;
+
+use error::{ClientError, InternalError};
+use maidsafe_utilities::serialisation;
+use metrics::{self, Metrics};
+use routing::{Authority, RequestContent, RequestMessage, ResponseContent, ResponseMessage,
+              RoutingMessage};
+use std::slice::Iter;
+use types::Refresh;
+use vault::RoutingNode;
+use xor_name::XorName;
+
+/// Common interface to all the personas run by a vault.
+///
+/// Each persona declares which `(src, dst, content)` combinations it accepts, and the `Registry`
+/// routes every message to the first persona which accepts it.
+pub trait Persona {
+    /// Unique name of the persona.  Refresh messages are tagged with this so that the receiving
+    /// vault can pass them to the matching persona.
+    fn name(&self) -> &'static str;
+
+    fn accepts_request(&self, src: &Authority, dst: &Authority, content: &RequestContent) -> bool;
+
+    fn accepts_response(&self,
+                        _src: &Authority,
+                        _dst: &Authority,
+                        _content: &ResponseContent)
+                        -> bool {
+        false
+    }
+
+    fn on_request(&mut self,
+                  routing_node: &RoutingNode,
+                  request: &RequestMessage)
+                  -> Result<(), InternalError>;
+
+    fn on_response(&mut self,
+                   _routing_node: &RoutingNode,
+                   response: &ResponseMessage)
+                   -> Result<(), InternalError> {
+        Err(InternalError::UnknownMessageType(RoutingMessage::Response(response.clone())))
+    }
+
+    fn on_refresh(&mut self,
+                  _routing_node: &RoutingNode,
+                  src: &Authority,
+                  dst: &Authority,
+                  refresh: &Refresh)
+                  -> Result<(), InternalError> {
+        Err(InternalError::UnknownRefreshType(src.clone(), dst.clone(), refresh.clone()))
+    }
+
+    fn on_node_added(&mut self, _routing_node: &RoutingNode, _node_added: &XorName) {}
+
+    fn on_node_lost(&mut self, _routing_node: &RoutingNode, _node_lost: &XorName) {}
+
+    /// Called periodically to allow time-based maintenance (e.g. timeouts) to be carried out.
+    fn on_tick(&mut self, _routing_node: &RoutingNode) {}
+
+    /// Called when the vault has been disconnected, before it rejoins the network under a new
+    /// name.  Personas whose state only applies to their old close groups should drop it here, to
+    /// be refreshed by their new groups.  By default all state is kept.
+    fn on_disconnected(&mut self) {}
+
+    /// Called when the vault is about to leave the network, to hand off the persona's state to
+    /// the groups which will take over from it.
+    fn on_drain(&mut self, _routing_node: &RoutingNode) {}
+
+    /// Whether the persona has finished handing off its state since `on_drain` was called.
+    fn is_drained(&self) -> bool {
+        true
+    }
+
+    /// Named values describing the persona's current state, for operators to monitor.
+    fn stats(&self) -> Vec<(&'static str, u64)> {
+        vec![]
+    }
+
+    /// Names of the accounts the persona holds.
+    fn account_names(&self) -> Vec<XorName> {
+        vec![]
+    }
+
+    /// Name, used space and maximum space in bytes of each of the persona's chunk stores.
+    fn chunk_store_usage(&self) -> Vec<(&'static str, u64, u64)> {
+        vec![]
+    }
+
+    /// Brief descriptions of the requests the persona is still waiting on responses for.
+    fn ongoing_requests(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Takes the client errors the persona has sent in failure responses since this was last
+    /// called, other than those returned from `on_request`, `on_response` or `on_refresh`.
+    fn take_client_errors(&mut self) -> Vec<ClientError> {
+        vec![]
+    }
+}
+
+/// Holds all the personas and dispatches routing events to them.
+pub struct Registry {
+    personas: Vec<Box<Persona>>,
+    metrics: Metrics,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            personas: Vec::new(),
+            metrics: Metrics::new(),
+        }
+    }
+
+    pub fn register(&mut self, persona: Box<Persona>) {
+        if self.personas.iter().any(|existing| existing.name() == persona.name()) {
+            error!("Persona {} already registered.", persona.name());
+            return;
+        }
+        self.personas.push(persona);
+    }
+
+    pub fn on_request(&mut self,
+                      routing_node: &RoutingNode,
+                      request: &RequestMessage)
+                      -> Result<(), InternalError> {
+        if let RequestContent::Refresh(ref serialised_refresh) = request.content {
+            return self.on_refresh(routing_node, &request.src, &request.dst, serialised_refresh);
+        }
+
+        let (persona_name, result) = match self.personas
+                                               .iter_mut()
+                                               .find(|persona| {
+                                                   persona.accepts_request(&request.src,
+                                                                           &request.dst,
+                                                                           &request.content)
+                                               }) {
+            Some(persona) => {
+                let result = persona.on_request(routing_node, request);
+                count_client_errors(&mut self.metrics, &mut **persona);
+                (persona.name(), result)
+            }
+            None => {
+                let message = RoutingMessage::Request(request.clone());
+                return Err(InternalError::UnknownMessageType(message));
+            }
+        };
+        self.record(persona_name, metrics::request_kind(&request.content), &result);
+        result
+    }
+
+    pub fn on_response(&mut self,
+                       routing_node: &RoutingNode,
+                       response: &ResponseMessage)
+                       -> Result<(), InternalError> {
+        let (persona_name, result) = match self.personas
+                                               .iter_mut()
+                                               .find(|persona| {
+                                                   persona.accepts_response(&response.src,
+                                                                            &response.dst,
+                                                                            &response.content)
+                                               }) {
+            Some(persona) => {
+                let result = persona.on_response(routing_node, response);
+                count_client_errors(&mut self.metrics, &mut **persona);
+                (persona.name(), result)
+            }
+            None => {
+                let message = RoutingMessage::Response(response.clone());
+                return Err(InternalError::UnknownMessageType(message));
+            }
+        };
+        self.record(persona_name, metrics::response_kind(&response.content), &result);
+        result
+    }
+
+    pub fn on_node_added(&mut self, routing_node: &RoutingNode, node_added: &XorName) {
+        for persona in self.personas.iter_mut() {
+            persona.on_node_added(routing_node, node_added);
+            count_client_errors(&mut self.metrics, &mut **persona);
+        }
+    }
+
+    pub fn on_node_lost(&mut self, routing_node: &RoutingNode, node_lost: &XorName) {
+        for persona in self.personas.iter_mut() {
+            persona.on_node_lost(routing_node, node_lost);
+            count_client_errors(&mut self.metrics, &mut **persona);
+        }
+    }
+
+    pub fn on_tick(&mut self, routing_node: &RoutingNode) {
+        for persona in self.personas.iter_mut() {
+            persona.on_tick(routing_node);
+            count_client_errors(&mut self.metrics, &mut **persona);
+        }
+    }
+
+    pub fn on_disconnected(&mut self) {
+        for persona in self.personas.iter_mut() {
+            persona.on_disconnected();
+        }
+    }
+
+    pub fn on_drain(&mut self, routing_node: &RoutingNode) {
+        for persona in self.personas.iter_mut() {
+            persona.on_drain(routing_node);
+            count_client_errors(&mut self.metrics, &mut **persona);
+        }
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.personas.iter().all(|persona| persona.is_drained())
+    }
+
+    pub fn iter(&self) -> Iter<Box<Persona>> {
+        self.personas.iter()
+    }
+
+    /// Returns the stats of every persona, tagged with the persona's name.
+    pub fn stats(&self) -> Vec<(&'static str, &'static str, u64)> {
+        self.personas
+            .iter()
+            .flat_map(|persona| {
+                let persona_name = persona.name();
+                persona.stats()
+                       .into_iter()
+                       .map(move |(stat_name, value)| (persona_name, stat_name, value))
+            })
+            .collect()
+    }
+
+    /// Returns the message counters and the stats of every persona in the Prometheus text format.
+    pub fn metrics(&self) -> String {
+        let chunk_stores = self.personas
+                               .iter()
+                               .flat_map(|persona| {
+                                   let persona_name = persona.name();
+                                   persona.chunk_store_usage()
+                                          .into_iter()
+                                          .map(move |(store_name, used_space, max_space)| {
+                                              (persona_name, store_name, used_space, max_space)
+                                          })
+                               })
+                               .collect::<Vec<_>>();
+        self.metrics.render(&self.stats(), &chunk_stores)
+    }
+
+    fn on_refresh(&mut self,
+                  routing_node: &RoutingNode,
+                  src: &Authority,
+                  dst: &Authority,
+                  serialised_refresh: &Vec<u8>)
+                  -> Result<(), InternalError> {
+        let refresh = try!(serialisation::deserialise::<Refresh>(serialised_refresh));
+        let (persona_name, result) = match self.personas
+                                               .iter_mut()
+                                               .find(|persona| persona.name() == refresh.persona) {
+            Some(persona) => {
+                let result = persona.on_refresh(routing_node, src, dst, &refresh);
+                count_client_errors(&mut self.metrics, &mut **persona);
+                (persona.name(), result)
+            }
+            None => {
+                return Err(InternalError::UnknownRefreshType(src.clone(), dst.clone(), refresh))
+            }
+        };
+        self.record(persona_name, "refresh", &result);
+        result
+    }
+
+    fn record(&mut self, persona_name: &str, kind: &str, result: &Result<(), InternalError>) {
+        let outcome = if result.is_ok() {
+            "ok"
+        } else {
+            "error"
+        };
+        self.metrics.increment("messages_handled_total",
+                               &[("persona", persona_name), ("kind", kind), ("outcome", outcome)]);
+        if let Err(InternalError::Client(ref error)) = *result {
+            count_client_error(&mut self.metrics, persona_name, error);
+        }
+    }
+}
+
+// Counts the client errors which `persona` has sent in failure responses without returning them.
+fn count_client_errors(metrics: &mut Metrics, persona: &mut Persona) {
+    let persona_name = persona.name();
+    for error in persona.take_client_errors() {
+        count_client_error(metrics, persona_name, &error);
+    }
+}
+
+fn count_client_error(metrics: &mut Metrics, persona_name: &str, error: &ClientError) {
+    metrics.increment("client_errors_total",
+                      &[("persona", persona_name), ("error", error.kind())]);
+}
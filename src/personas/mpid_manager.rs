@@ -18,18 +18,27 @@
 use std::collections::HashMap;
 
 use chunk_store::ChunkStore;
-use default_chunk_store;
+use config_handler::Config;
+use default_chunk_store::{self, MPID_MANAGER_INBOX, MPID_MANAGER_OUTBOX};
 use error::{ClientError, InternalError};
 use maidsafe_utilities::serialisation::{deserialise, serialise};
 use mpid_messaging::{MAX_INBOX_SIZE, MAX_OUTBOX_SIZE, MpidHeader, MpidMessage, MpidMessageWrapper};
-use routing::{Authority, Data, PlainData, RequestContent, RequestMessage};
+use personas::Persona;
+use routing::{Authority, Data, MessageId, PlainData, RequestContent, RequestMessage,
+              ResponseContent, ResponseMessage};
 use sodiumoxide::crypto::sign::PublicKey;
 use sodiumoxide::crypto::hash::sha512;
-use types::{Refresh, RefreshValue};
+use state_store::StateStore;
+use types::Refresh;
 use utils;
 use vault::RoutingNode;
 use xor_name::XorName;
 
+pub const PERSONA_NAME: &'static str = "MpidManager";
+
+// Refresh value: account, outbox messages, inbox headers
+type RefreshValue = (Account, Vec<PlainData>, Vec<PlainData>);
+
 #[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
 struct MailBox {
     allowance: u64,
@@ -161,18 +170,28 @@ impl Account {
 }
 
 pub struct MpidManager {
-    accounts: HashMap<XorName, Account>,
+    accounts: StateStore<Account>,
     chunk_store_inbox: ChunkStore,
     chunk_store_outbox: ChunkStore,
+    refreshes_sent: u64,
 }
 
 impl MpidManager {
-    pub fn new() -> MpidManager {
-        MpidManager {
-            accounts: HashMap::new(),
-            chunk_store_inbox: default_chunk_store::new().unwrap(),
-            chunk_store_outbox: default_chunk_store::new().unwrap(),
-        }
+    pub fn new(config: &Config) -> Result<MpidManager, InternalError> {
+        let chunk_store_inbox =
+            try!(default_chunk_store::new(config,
+                                          MPID_MANAGER_INBOX,
+                                          config.mpid_manager_inbox_capacity()));
+        let chunk_store_outbox =
+            try!(default_chunk_store::new(config,
+                                          MPID_MANAGER_OUTBOX,
+                                          config.mpid_manager_outbox_capacity()));
+        Ok(MpidManager {
+            accounts: try!(StateStore::open(config, PERSONA_NAME)),
+            chunk_store_inbox: chunk_store_inbox,
+            chunk_store_outbox: chunk_store_outbox,
+            refreshes_sent: 0,
+        })
     }
 
     // The name of the PlainData is expected to be the mpidheader or mpidmessage name
@@ -192,15 +211,21 @@ impl MpidManager {
         match mpid_message_wrapper {
             MpidMessageWrapper::PutHeader(mpid_header) => {
                 if self.chunk_store_inbox.has_chunk(&data.name()) {
-                    return Err(InternalError::Client(ClientError::DataExists));
+                    return Self::reply_with_put_failure(routing_node,
+                                                        request,
+                                                        message_id,
+                                                        ClientError::DataExists);
                 }
 
                 let serialised_header = try!(serialise(&mpid_header));
-                if let Some(ref mut account) = self.accounts.get_mut(&request.dst.name().clone()) {
-                    // Client is online.
-                    // TODO: how the sender's public key get retained?
-                    if account.put_into_inbox(serialised_header.len() as u64, &data.name(), &None) {
-                        try!(self.chunk_store_inbox.put(&data.name(), &serialised_header[..]));
+                let header_size = serialised_header.len() as u64;
+                let receiver_online = self.accounts.contains_key(request.dst.name());
+                // TODO: how the sender's public key get retained?
+                if self.accounts.update_or_default(request.dst.name().clone(), |account| {
+                    account.put_into_inbox(header_size, &data.name(), &None)
+                }) {
+                    try!(self.chunk_store_inbox.put(&data.name(), &serialised_header[..]));
+                    if receiver_online {
                         let dst = Authority::ClientManager(mpid_header.sender().clone());
                         let wrapper = MpidMessageWrapper::GetMessage(mpid_header.clone());
                         let value = try!(serialise(&wrapper));
@@ -210,46 +235,33 @@ impl MpidManager {
                                                             dst,
                                                             data,
                                                             message_id.clone()));
-                        return Ok(());
-                    } else {
-                        try!(routing_node.send_put_failure(request.dst.clone(),
-                                                           request.src.clone(),
-                                                           request.clone(),
-                                                           Vec::new(),
-                                                           message_id));
-                        return Ok(());
                     }
-                }
-
-                if self.accounts
-                       .entry(request.dst.name().clone())
-                       .or_insert(Account::default())
-                       .put_into_inbox(serialised_header.len() as u64, &data.name(), &None) {
-                    try!(self.chunk_store_inbox.put(&data.name(), &serialised_header[..]));
                 } else {
-                    try!(routing_node.send_put_failure(request.dst.clone(),
-                                                       request.src.clone(),
-                                                       request.clone(),
-                                                       Vec::new(),
-                                                       message_id));
+                    return Self::reply_with_put_failure(routing_node,
+                                                        request,
+                                                        message_id,
+                                                        ClientError::InboxFull);
                 }
             }
             MpidMessageWrapper::PutMessage(mpid_message) => {
-                if let Some(ref mut account) = self.accounts.get_mut(&request.dst.name().clone()) {
+                if self.accounts.contains_key(request.dst.name()) {
                     if self.chunk_store_outbox.has_chunk(&data.name()) {
-                        return Err(InternalError::Client(ClientError::DataExists));
+                        return Self::reply_with_put_failure(routing_node,
+                                                            request,
+                                                            message_id,
+                                                            ClientError::DataExists);
                     }
                     let serialised_message = try!(serialise(&mpid_message));
                     if let Authority::Client { client_key, .. } = request.src {
-                        if !account.put_into_outbox(serialised_message.len() as u64,
-                                                    &data.name(),
-                                                    &Some(client_key)) {
-                            try!(routing_node.send_put_failure(request.dst.clone(),
-                                                               request.src.clone(),
-                                                               request.clone(),
-                                                               Vec::new(),
-                                                               message_id));
-                            return Ok(());
+                        let message_size = serialised_message.len() as u64;
+                        let stored = self.accounts.update(request.dst.name(), |account| {
+                            account.put_into_outbox(message_size, &data.name(), &Some(client_key))
+                        });
+                        if stored != Some(true) {
+                            return Self::reply_with_put_failure(routing_node,
+                                                                request,
+                                                                message_id,
+                                                                ClientError::OutboxFull);
                         }
                     };
                     try!(self.chunk_store_outbox.put(&data.name(), &serialised_message[..]));
@@ -268,11 +280,10 @@ impl MpidManager {
                     let _ = routing_node.send_put_success(src, dst, digest, message_id);
                 } else {
                     // Client not registered online.
-                    try!(routing_node.send_put_failure(request.dst.clone(),
-                                                       request.src.clone(),
-                                                       request.clone(),
-                                                       Vec::new(),
-                                                       message_id));
+                    return Self::reply_with_put_failure(routing_node,
+                                                        request,
+                                                        message_id,
+                                                        ClientError::NoSuchAccount);
                 }
             }
             _ => unreachable!("Error in vault demuxing"),
@@ -302,12 +313,15 @@ impl MpidManager {
                     if let Some(ref account) = self.accounts.get(&request.src.name().clone()) {
                         let ori_msg_name = try!(mpid_header.name());
                         if account.has_in_outbox(&ori_msg_name) {
+                            let external_error_indicator =
+                                try!(serialise(&ClientError::InboxFull));
                             let clients = account.registered_clients();
                             for client in clients.iter() {
+                                let indicator = external_error_indicator.clone();
                                 let _ = routing_node.send_put_failure(request.src.clone(),
                                                                       client.clone(),
                                                                       request.clone(),
-                                                                      Vec::new(),
+                                                                      indicator,
                                                                       message_id.clone());
                             }
                         }
@@ -332,17 +346,17 @@ impl MpidManager {
         let mpid_message_wrapper: MpidMessageWrapper = try!(deserialise(&data.value()));
         match mpid_message_wrapper {
             MpidMessageWrapper::Online => {
-                let account = self.accounts
-                                  .entry(request.dst.name().clone())
-                                  .or_insert(Account::default());
-                account.register_online(&request.src);
+                let received_headers =
+                    self.accounts.update_or_default(request.dst.name().clone(), |account| {
+                        account.register_online(&request.src);
+                        account.received_headers()
+                    });
                 // Send post success to client.
                 let src = request.dst.clone();
                 let dst = request.src.clone();
                 let digest = sha512::hash(&try!(serialise(request))[..]);
                 let _ = routing_node.send_post_success(src, dst, digest, message_id.clone());
                 // For each received header in the inbox, fetch the full message from the sender
-                let received_headers = account.received_headers();
                 for header in received_headers.iter() {
                     match self.chunk_store_inbox.get(&header) {
                         Ok(serialised_header) => {
@@ -375,29 +389,31 @@ impl MpidManager {
                     Ok(serialised_message) => {
                         let mpid_message: MpidMessage = try!(deserialise(&serialised_message));
                         let message_name = try!(mpid_message.header().name());
-                        if (message_name == header_name) &&
-                           (mpid_message.recipient() == request.src.name()) {
-                            let wrapper = MpidMessageWrapper::PutMessage(mpid_message);
-                            let serialised_wrapper = try!(serialise(&wrapper));
-                            let data = Data::Plain(PlainData::new(message_name,
-                                                                  serialised_wrapper));
-                            try!(routing_node.send_post_request(request.dst.clone(),
-                                                                request.src.clone(),
-                                                                data,
-                                                                message_id.clone()));
+                        if (message_name != header_name) ||
+                           (mpid_message.recipient() != request.src.name()) {
+                            return Self::reply_with_post_failure(routing_node,
+                                                                 request,
+                                                                 message_id,
+                                                                 ClientError::InvalidOperation);
                         }
+                        let wrapper = MpidMessageWrapper::PutMessage(mpid_message);
+                        let serialised_wrapper = try!(serialise(&wrapper));
+                        let data = Data::Plain(PlainData::new(message_name, serialised_wrapper));
+                        try!(routing_node.send_post_request(request.dst.clone(),
+                                                            request.src.clone(),
+                                                            data,
+                                                            message_id.clone()));
                     }
                     _ => {
-                        try!(routing_node.send_post_failure(request.dst.clone(),
-                                                            request.src.clone(),
-                                                            request.clone(),
-                                                            Vec::new(),
-                                                            message_id))
+                        return Self::reply_with_post_failure(routing_node,
+                                                             request,
+                                                             message_id,
+                                                             ClientError::NoSuchData);
                     }
                 }
             }
             MpidMessageWrapper::PutMessage(mpid_message) => {
-                match self.accounts.get(request.dst.name()) {
+                let error = match self.accounts.get(request.dst.name()) {
                     Some(receiver) => {
                         if mpid_message.recipient() == request.dst.name() {
                             let clients = receiver.registered_clients();
@@ -407,64 +423,77 @@ impl MpidManager {
                                                                        Data::Plain(data.clone()),
                                                                        message_id.clone());
                             }
+                            None
+                        } else {
+                            Some(ClientError::InvalidOperation)
                         }
                     }
-                    None => warn!("can not find the account {:?}", request.dst.name().clone()),
+                    None => {
+                        warn!("can not find the account {:?}", request.dst.name().clone());
+                        Some(ClientError::NoSuchAccount)
+                    }
+                };
+                if let Some(error) = error {
+                    return Self::reply_with_post_failure(routing_node, request, message_id, error);
                 }
             }
             MpidMessageWrapper::OutboxHas(header_names) => {
-                if let Some(ref account) = self.accounts.get(&request.dst.name().clone()) {
-                    if account.registered_clients()
-                              .iter()
-                              .any(|authority| *authority == request.src) {
-                        let names_in_outbox = header_names.iter()
-                                                          .filter(|name| {
-                                                              account.has_in_outbox(name)
-                                                          })
-                                                          .cloned()
-                                                          .collect::<Vec<XorName>>();
-                        let mut mpid_headers = vec![];
-
-                        for name in names_in_outbox.iter() {
-                            if let Ok(data) = self.chunk_store_outbox.get(name) {
-                                let mpid_message: MpidMessage = try!(deserialise(&data));
-                                mpid_headers.push(mpid_message.header().clone());
-                            }
-                        }
-
-                        let src = request.dst.clone();
-                        let dst = request.src.clone();
-                        let wrapper = MpidMessageWrapper::OutboxHasResponse(mpid_headers);
-                        let serialised_wrapper = try!(serialise(&wrapper));
-                        let data = Data::Plain(PlainData::new(request.dst.name().clone(),
-                                                              serialised_wrapper));
-                        try!(routing_node.send_post_request(src, dst, data, message_id.clone()));
+                let account = match self.registered_account(&request.src, request.dst.name()) {
+                    Ok(account) => account,
+                    Err(error) => {
+                        return Self::reply_with_post_failure(routing_node,
+                                                             request,
+                                                             message_id,
+                                                             error);
+                    }
+                };
+                let names_in_outbox = header_names.iter()
+                                                  .filter(|name| account.has_in_outbox(name))
+                                                  .cloned()
+                                                  .collect::<Vec<XorName>>();
+                let mut mpid_headers = vec![];
+
+                for name in names_in_outbox.iter() {
+                    if let Ok(data) = self.chunk_store_outbox.get(name) {
+                        let mpid_message: MpidMessage = try!(deserialise(&data));
+                        mpid_headers.push(mpid_message.header().clone());
                     }
                 }
+
+                let src = request.dst.clone();
+                let dst = request.src.clone();
+                let wrapper = MpidMessageWrapper::OutboxHasResponse(mpid_headers);
+                let serialised_wrapper = try!(serialise(&wrapper));
+                let data = Data::Plain(PlainData::new(request.dst.name().clone(),
+                                                      serialised_wrapper));
+                try!(routing_node.send_post_request(src, dst, data, message_id.clone()));
             }
             MpidMessageWrapper::GetOutboxHeaders => {
-                if let Some(ref account) = self.accounts.get(&request.dst.name().clone()) {
-                    if account.registered_clients()
-                              .iter()
-                              .any(|authority| *authority == request.src) {
-                        let mut mpid_headers = vec![];
-
-                        for name in account.stored_messages().iter() {
-                            if let Ok(data) = self.chunk_store_outbox.get(name) {
-                                let mpid_message: MpidMessage = try!(deserialise(&data));
-                                mpid_headers.push(mpid_message.header().clone());
-                            }
-                        }
+                let account = match self.registered_account(&request.src, request.dst.name()) {
+                    Ok(account) => account,
+                    Err(error) => {
+                        return Self::reply_with_post_failure(routing_node,
+                                                             request,
+                                                             message_id,
+                                                             error);
+                    }
+                };
+                let mut mpid_headers = vec![];
 
-                        let src = request.dst.clone();
-                        let dst = request.src.clone();
-                        let wrapper = MpidMessageWrapper::GetOutboxHeadersResponse(mpid_headers);
-                        let serialised_wrapper = try!(serialise(&wrapper));
-                        let data = Data::Plain(PlainData::new(request.dst.name().clone(),
-                                                              serialised_wrapper));
-                        try!(routing_node.send_post_request(src, dst, data, message_id.clone()));
+                for name in account.stored_messages().iter() {
+                    if let Ok(data) = self.chunk_store_outbox.get(name) {
+                        let mpid_message: MpidMessage = try!(deserialise(&data));
+                        mpid_headers.push(mpid_message.header().clone());
                     }
                 }
+
+                let src = request.dst.clone();
+                let dst = request.src.clone();
+                let wrapper = MpidMessageWrapper::GetOutboxHeadersResponse(mpid_headers);
+                let serialised_wrapper = try!(serialise(&wrapper));
+                let data = Data::Plain(PlainData::new(request.dst.name().clone(),
+                                                      serialised_wrapper));
+                try!(routing_node.send_post_request(src, dst, data, message_id.clone()));
             }
             _ => unreachable!("Error in vault demuxing"),
         }
@@ -485,58 +514,66 @@ impl MpidManager {
         let mpid_message_wrapper: MpidMessageWrapper = try!(deserialise(&data.value()));
         match mpid_message_wrapper {
             MpidMessageWrapper::DeleteMessage(message_name) => {
-                if let Some(ref mut account) = self.accounts.get_mut(&request.dst.name().clone()) {
-                    let mut registered = false;
-
-                    if account.registered_clients()
-                              .iter()
-                              .any(|authority| *authority == request.src) {
-                        registered = true;
+                // Besides the sender's clients, the message's recipient may delete it once read.
+                let registered = match self.registered_account(&request.src, request.dst.name()) {
+                    Ok(_) => true,
+                    Err(ClientError::InvalidOperation) => false,
+                    Err(error) => {
+                        return Self::reply_with_delete_failure(routing_node,
+                                                               request,
+                                                               message_id,
+                                                               error);
                     }
-
-                    if let Ok(data) = self.chunk_store_outbox.get(&message_name) {
-                        if !registered {
-                            let mpid_message: MpidMessage = try!(deserialise(&data));
-                            if *mpid_message.recipient() != utils::client_name(&request.src) {
-                                return Ok(()); // !
-                            }
-                        }
-
-                        let data_size = data.len() as u64;
-                        try!(self.chunk_store_outbox.delete(&message_name));
-                        if !account.remove_from_outbox(data_size, &message_name) {
-                            warn!("Failed to remove message name from outbox.");
-                        }
-                    } else {
-                        error!("Failed to get from chunk store.");
-                        try!(routing_node.send_delete_failure(request.dst.clone(),
-                                                              request.src.clone(),
-                                                              request.clone(),
-                                                              Vec::new(),
-                                                              message_id))
+                };
+                let data = match self.chunk_store_outbox.get(&message_name) {
+                    Ok(data) => data,
+                    Err(_) => {
+                        return Self::reply_with_delete_failure(routing_node,
+                                                               request,
+                                                               message_id,
+                                                               ClientError::NoSuchData);
+                    }
+                };
+                if !registered {
+                    let mpid_message: MpidMessage = try!(deserialise(&data));
+                    if *mpid_message.recipient() != utils::client_name(&request.src) {
+                        return Self::reply_with_delete_failure(routing_node,
+                                                               request,
+                                                               message_id,
+                                                               ClientError::InvalidOperation);
                     }
                 }
+
+                let data_size = data.len() as u64;
+                try!(self.chunk_store_outbox.delete(&message_name));
+                if self.accounts.update(request.dst.name(), |account| {
+                    account.remove_from_outbox(data_size, &message_name)
+                }) != Some(true) {
+                    warn!("Failed to remove message name from outbox.");
+                }
             }
             MpidMessageWrapper::DeleteHeader(header_name) => {
-                if let Some(ref mut account) = self.accounts.get_mut(&request.dst.name().clone()) {
-                    if account.registered_clients()
-                              .iter()
-                              .any(|authority| *authority == request.src) {
-                        if let Ok(data) = self.chunk_store_inbox.get(&header_name) {
-                            let data_size = data.len() as u64;
-                            try!(self.chunk_store_inbox.delete(&header_name));
-                            if !account.remove_from_inbox(data_size, &header_name) {
-                                warn!("Failed to remove header name from inbox.");
-                            }
-                        } else {
-                            error!("Failed to get from chunk store.");
-                            try!(routing_node.send_delete_failure(request.dst.clone(),
-                                                                  request.src.clone(),
-                                                                  request.clone(),
-                                                                  Vec::new(),
-                                                                  message_id))
-                        }
+                if let Err(error) = self.registered_account(&request.src, request.dst.name()) {
+                    return Self::reply_with_delete_failure(routing_node,
+                                                           request,
+                                                           message_id,
+                                                           error);
+                }
+                let data = match self.chunk_store_inbox.get(&header_name) {
+                    Ok(data) => data,
+                    Err(_) => {
+                        return Self::reply_with_delete_failure(routing_node,
+                                                               request,
+                                                               message_id,
+                                                               ClientError::NoSuchData);
                     }
+                };
+                let data_size = data.len() as u64;
+                try!(self.chunk_store_inbox.delete(&header_name));
+                if self.accounts.update(request.dst.name(), |account| {
+                    account.remove_from_inbox(data_size, &header_name)
+                }) != Some(true) {
+                    warn!("Failed to remove header name from inbox.");
                 }
             }
             _ => unreachable!("Error in vault demuxing"),
@@ -566,17 +603,76 @@ impl MpidManager {
                                                      &account.stored_messages());
 
             let src = Authority::ClientManager(mpid_name.clone());
-            let refresh = Refresh::new(mpid_name,
-                                       RefreshValue::MpidManagerAccount(account.clone(),
-                                                                        stored_messages,
-                                                                        received_headers));
-            if let Ok(serialised_refresh) = serialise(&refresh) {
+            let refresh_value: RefreshValue = (account.clone(), stored_messages, received_headers);
+            let refresh = Refresh::new(PERSONA_NAME, mpid_name, &refresh_value);
+            if let Ok(serialised_refresh) = refresh.and_then(|refresh| serialise(&refresh)) {
                 debug!("MpidManager sending refresh for account {:?}", src.name());
                 let _ = routing_node.send_refresh_request(src, serialised_refresh);
+                self.refreshes_sent += 1;
+            }
+        }
+    }
+
+    // Returns the account `name` if `client` is registered with it.
+    fn registered_account(&self,
+                          client: &Authority,
+                          name: &XorName)
+                          -> Result<&Account, ClientError> {
+        match self.accounts.get(name) {
+            Some(account) => {
+                if account.registered_clients().iter().any(|authority| authority == client) {
+                    Ok(account)
+                } else {
+                    Err(ClientError::InvalidOperation)
+                }
             }
+            None => Err(ClientError::NoSuchAccount),
         }
     }
 
+    // Returns `Err(error)` if it was a client error, so the registry can count it.
+    fn reply_with_put_failure(routing_node: &RoutingNode,
+                              request: &RequestMessage,
+                              message_id: MessageId,
+                              error: ClientError)
+                              -> Result<(), InternalError> {
+        let external_error_indicator = try!(serialise(&error));
+        try!(routing_node.send_put_failure(request.dst.clone(),
+                                           request.src.clone(),
+                                           request.clone(),
+                                           external_error_indicator,
+                                           message_id));
+        Err(InternalError::Client(error))
+    }
+
+    fn reply_with_post_failure(routing_node: &RoutingNode,
+                               request: &RequestMessage,
+                               message_id: MessageId,
+                               error: ClientError)
+                               -> Result<(), InternalError> {
+        let external_error_indicator = try!(serialise(&error));
+        try!(routing_node.send_post_failure(request.dst.clone(),
+                                            request.src.clone(),
+                                            request.clone(),
+                                            external_error_indicator,
+                                            message_id));
+        Err(InternalError::Client(error))
+    }
+
+    fn reply_with_delete_failure(routing_node: &RoutingNode,
+                                 request: &RequestMessage,
+                                 message_id: MessageId,
+                                 error: ClientError)
+                                 -> Result<(), InternalError> {
+        let external_error_indicator = try!(serialise(&error));
+        try!(routing_node.send_delete_failure(request.dst.clone(),
+                                              request.src.clone(),
+                                              request.clone(),
+                                              external_error_indicator,
+                                              message_id));
+        Err(InternalError::Client(error))
+    }
+
     fn fetch_chunks(storage: &ChunkStore, names: &Vec<XorName>) -> Vec<PlainData> {
         let mut datas = Vec::new();
         for name in names.iter() {
@@ -596,6 +692,117 @@ impl MpidManager {
     }
 }
 
+impl Persona for MpidManager {
+    fn name(&self) -> &'static str {
+        PERSONA_NAME
+    }
+
+    fn accepts_request(&self, src: &Authority, dst: &Authority, content: &RequestContent) -> bool {
+        match (src, dst, content) {
+            (&Authority::Client{ .. },
+             &Authority::ClientManager(_),
+             &RequestContent::Put(Data::Plain(_), _)) |
+            (&Authority::ClientManager(_),
+             &Authority::ClientManager(_),
+             &RequestContent::Put(Data::Plain(_), _)) |
+            (&Authority::Client{ .. },
+             &Authority::ClientManager(_),
+             &RequestContent::Post(Data::Plain(_), _)) |
+            (&Authority::ClientManager(_),
+             &Authority::ClientManager(_),
+             &RequestContent::Post(Data::Plain(_), _)) |
+            (&Authority::Client{ .. },
+             &Authority::ClientManager(_),
+             &RequestContent::Delete(Data::Plain(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    fn accepts_response(&self,
+                        src: &Authority,
+                        dst: &Authority,
+                        content: &ResponseContent)
+                        -> bool {
+        match (src, dst, content) {
+            (&Authority::ClientManager(_),
+             &Authority::ClientManager(_),
+             &ResponseContent::PutFailure{ .. }) => true,
+            _ => false,
+        }
+    }
+
+    fn on_request(&mut self,
+                  routing_node: &RoutingNode,
+                  request: &RequestMessage)
+                  -> Result<(), InternalError> {
+        match request.content {
+            RequestContent::Put(..) => self.handle_put(routing_node, request),
+            RequestContent::Post(..) => self.handle_post(routing_node, request),
+            RequestContent::Delete(..) => self.handle_delete(routing_node, request),
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_response(&mut self,
+                   routing_node: &RoutingNode,
+                   response: &ResponseMessage)
+                   -> Result<(), InternalError> {
+        match response.content {
+            ResponseContent::PutFailure{ ref request, .. } => {
+                self.handle_put_failure(routing_node, request)
+            }
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_refresh(&mut self,
+                  _routing_node: &RoutingNode,
+                  src: &Authority,
+                  dst: &Authority,
+                  refresh: &Refresh)
+                  -> Result<(), InternalError> {
+        match (src, dst) {
+            (&Authority::ClientManager(_), &Authority::ClientManager(_)) => {
+                let (account, stored_messages, received_headers) =
+                    try!(refresh.value::<RefreshValue>());
+                self.handle_refresh(refresh.name, &account, &stored_messages, &received_headers);
+                Ok(())
+            }
+            _ => Err(InternalError::UnknownRefreshType(src.clone(), dst.clone(), refresh.clone())),
+        }
+    }
+
+    fn on_node_added(&mut self, routing_node: &RoutingNode, _node_added: &XorName) {
+        self.handle_churn(routing_node)
+    }
+
+    fn on_node_lost(&mut self, routing_node: &RoutingNode, _node_lost: &XorName) {
+        self.handle_churn(routing_node)
+    }
+
+    fn on_drain(&mut self, routing_node: &RoutingNode) {
+        self.handle_churn(routing_node)
+    }
+
+    fn stats(&self) -> Vec<(&'static str, u64)> {
+        vec![("accounts", self.accounts.iter().len() as u64),
+             ("refreshes_sent_total", self.refreshes_sent)]
+    }
+
+    fn account_names(&self) -> Vec<XorName> {
+        self.accounts.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn chunk_store_usage(&self) -> Vec<(&'static str, u64, u64)> {
+        vec![(MPID_MANAGER_INBOX,
+              self.chunk_store_inbox.used_space(),
+              self.chunk_store_inbox.max_space()),
+             (MPID_MANAGER_OUTBOX,
+              self.chunk_store_outbox.used_space(),
+              self.chunk_store_outbox.max_space())]
+    }
+}
+
 
 
 #[cfg(all(test, feature = "use-mock-routing"))]
@@ -605,10 +812,10 @@ mod test {
     use maidsafe_utilities::serialisation;
     use rand;
     use routing::{Authority, Data, MessageId, PlainData, RequestContent, RequestMessage,
-                  ResponseContent};
+                  ResponseContent, ResponseMessage};
     use sodiumoxide::crypto::sign;
     use std::sync::mpsc;
-    use utils::generate_random_vec_u8;
+    use utils::{self, generate_random_vec_u8};
     use vault::RoutingNode;
     use xor_name::XorName;
     use mpid_messaging::{MpidHeader, MpidMessage, MpidMessageWrapper};
@@ -631,7 +838,7 @@ mod test {
                 proxy_node_name: from.clone(),
             },
             routing: unwrap_result!(RoutingNode::new(mpsc::channel().0)),
-            mpid_manager: MpidManager::new(),
+            mpid_manager: unwrap_result!(MpidManager::new(&utils::test_config())),
         }
     }
 
@@ -703,7 +910,8 @@ mod test {
                         header: &MpidHeader,
                         src: &Authority,
                         dst: &Authority,
-                        id: &MessageId) {
+                        id: &MessageId)
+                        -> Result<(), InternalError> {
         let wrapper = MpidMessageWrapper::GetMessage(header.clone());
         let name = unwrap_result!(header.name());
         let value = unwrap_result!(serialisation::serialise(&wrapper));
@@ -714,17 +922,15 @@ mod test {
             content: RequestContent::Post(Data::Plain(plain_data.clone()), id.clone()),
         };
 
-        match env.mpid_manager.handle_post(&env.routing, &request) {
-            Ok(()) => (),
-            Err(error) => panic!("Error: {:?}", error),
-        }
+        env.mpid_manager.handle_post(&env.routing, &request)
     }
 
     fn delete_mpid_header(env: &mut Environment,
                           name: &XorName,
                           src: &Authority,
                           dst: &Authority,
-                          id: &MessageId) {
+                          id: &MessageId)
+                          -> Result<(), InternalError> {
         let wrapper = MpidMessageWrapper::DeleteHeader(name.clone());
         let value = unwrap_result!(serialisation::serialise(&wrapper));
         let plain_data = PlainData::new(name.clone(), value);
@@ -734,17 +940,15 @@ mod test {
             content: RequestContent::Delete(Data::Plain(plain_data.clone()), id.clone()),
         };
 
-        match env.mpid_manager.handle_delete(&env.routing, &request) {
-            Ok(()) => (),
-            Err(error) => panic!("Error: {:?}", error),
-        }
+        env.mpid_manager.handle_delete(&env.routing, &request)
     }
 
     fn delete_mpid_message(env: &mut Environment,
                            name: &XorName,
                            src: &Authority,
                            dst: &Authority,
-                           id: &MessageId) {
+                           id: &MessageId)
+                           -> Result<(), InternalError> {
         let wrapper = MpidMessageWrapper::DeleteMessage(name.clone());
         let value = unwrap_result!(serialisation::serialise(&wrapper));
         let plain_data = PlainData::new(name.clone(), value);
@@ -754,9 +958,27 @@ mod test {
             content: RequestContent::Delete(Data::Plain(plain_data.clone()), id.clone()),
         };
 
-        match env.mpid_manager.handle_delete(&env.routing, &request) {
-            Ok(()) => (),
-            Err(error) => panic!("Error: {:?}", error),
+        env.mpid_manager.handle_delete(&env.routing, &request)
+    }
+
+    // Checks that the request failed with `expected`, which was sent back in the last of
+    // `failures`.
+    fn check_failure(result: Result<(), InternalError>,
+                     failures: &[ResponseMessage],
+                     expected: ClientError) {
+        match result {
+            Err(InternalError::Client(ref error)) if error.kind() == expected.kind() => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        let failure = unwrap_option!(failures.last(), "Failure response");
+        match failure.content {
+            ResponseContent::PostFailure { ref external_error_indicator, .. } |
+            ResponseContent::DeleteFailure { ref external_error_indicator, .. } => {
+                let error: ClientError =
+                    unwrap_result!(serialisation::deserialise(external_error_indicator));
+                assert_eq!(error.kind(), expected.kind());
+            }
+            _ => unreachable!(),
         }
     }
 
@@ -890,6 +1112,17 @@ mod test {
             Err(InternalError::Client(ClientError::DataExists)) => (),
             Err(_) => panic!("Unexpected error."),
         }
+        let put_failures = env.routing.put_failures_given();
+        assert_eq!(put_failures.len(), 1);
+        match put_failures[0].content {
+            ResponseContent::PutFailure { ref external_error_indicator, .. } => {
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::DataExists => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
+            }
+            _ => unreachable!(),
+        }
 
         // put header...
         let mpid_header_wrapper = MpidMessageWrapper::PutHeader(mpid_header.clone());
@@ -907,8 +1140,7 @@ mod test {
             Err(error) => panic!("Error: {:?}", error),
         }
 
-        let put_failures = env.routing.put_failures_given();
-        assert!(put_failures.is_empty());
+        assert_eq!(env.routing.put_failures_given().len(), 1);
         let put_requests = env.routing.put_requests_given();
         assert_eq!(put_requests.len(), 1);
         assert_eq!(put_requests[0].src, env.our_authority);
@@ -932,6 +1164,168 @@ mod test {
         }
     }
 
+    #[test]
+    fn put_message_into_full_outbox() {
+        let mut env = environment_setup();
+        let src = env.client.clone();
+        let dst = env.our_authority.clone();
+        register_online(&mut env, &src, &dst);
+        let _ = env.mpid_manager.accounts.update(env.our_authority.name(), |account| {
+            account.outbox.space_available = 0
+        });
+
+        let (_public_key, secret_key) = sign::gen_keypair();
+        let mpid_message = unwrap_result!(MpidMessage::new(rand::random::<XorName>(),
+                                                           generate_random_vec_u8(128),
+                                                           rand::random::<XorName>(),
+                                                           generate_random_vec_u8(128),
+                                                           &secret_key));
+        let wrapper = MpidMessageWrapper::PutMessage(mpid_message.clone());
+        let name = unwrap_result!(mpid_message.header().name());
+        let value = unwrap_result!(serialisation::serialise(&wrapper));
+        let request = RequestMessage {
+            src: env.client.clone(),
+            dst: env.our_authority.clone(),
+            content: RequestContent::Put(Data::Plain(PlainData::new(name, value)),
+                                         MessageId::new()),
+        };
+        match env.mpid_manager.handle_put(&env.routing, &request) {
+            Err(InternalError::Client(ClientError::OutboxFull)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        assert!(env.routing.put_requests_given().is_empty());
+        let put_failures = env.routing.put_failures_given();
+        assert_eq!(put_failures.len(), 1);
+        assert_eq!(put_failures[0].dst, env.client);
+        match put_failures[0].content {
+            ResponseContent::PutFailure { ref external_error_indicator, .. } => {
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::OutboxFull => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn put_header_into_full_inbox() {
+        let mut env = environment_setup();
+        let src = env.client.clone();
+        let dst = env.our_authority.clone();
+        register_online(&mut env, &src, &dst);
+
+        let (_public_key, secret_key) = sign::gen_keypair();
+        let sender = env.our_authority.name().clone();
+        let receiver = rand::random::<XorName>();
+        let mpid_message = unwrap_result!(MpidMessage::new(sender,
+                                                           generate_random_vec_u8(128),
+                                                           receiver,
+                                                           generate_random_vec_u8(128),
+                                                           &secret_key));
+        put_mpid_message(&mut env, &mpid_message, &MessageId::new());
+        let put_requests = env.routing.put_requests_given();
+        assert_eq!(put_requests.len(), 1);
+
+        // The receiver's manager refuses the header.
+        let mut receiver_account = Account::default();
+        receiver_account.inbox.space_available = 0;
+        let _ = env.mpid_manager.accounts.insert(receiver, receiver_account);
+        match env.mpid_manager.handle_put(&env.routing, &put_requests[0]) {
+            Err(InternalError::Client(ClientError::InboxFull)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        // The sender's manager passes the failure on to the sender's clients.
+        unwrap_result!(env.mpid_manager.handle_put_failure(&env.routing, &put_requests[0]));
+        let put_failures = env.routing.put_failures_given();
+        assert_eq!(put_failures.len(), 2);
+        assert_eq!(put_failures[0].dst, env.our_authority);
+        assert_eq!(put_failures[1].dst, env.client);
+        for put_failure in &put_failures {
+            match put_failure.content {
+                ResponseContent::PutFailure { ref external_error_indicator, .. } => {
+                    match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                        ClientError::InboxFull => (),
+                        error => panic!("Unexpected error {:?}", error),
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn unauthorised_requests_fail() {
+        let mut env = environment_setup();
+        let sender = env.client.clone();
+        let our_authority = env.our_authority.clone();
+        let stranger = generate_receiver();
+
+        // Nothing can be deleted from an account which doesn't exist.
+        let result = delete_mpid_message(&mut env,
+                                         &rand::random(),
+                                         &sender,
+                                         &our_authority,
+                                         &MessageId::new());
+        check_failure(result,
+                      &env.routing.delete_failures_given(),
+                      ClientError::NoSuchAccount);
+
+        register_online(&mut env, &sender, &our_authority);
+        let receiver = generate_receiver();
+        let (_public_key, secret_key) = sign::gen_keypair();
+        let mpid_message = unwrap_result!(MpidMessage::new(our_authority.name().clone(),
+                                                           generate_random_vec_u8(128),
+                                                           utils::client_name(&receiver),
+                                                           generate_random_vec_u8(128),
+                                                           &secret_key));
+        put_mpid_message(&mut env, &mpid_message, &MessageId::new());
+        let message_name = unwrap_result!(mpid_message.header().name());
+
+        // The sender's outbox can't be listed by other clients.
+        let wrapper = MpidMessageWrapper::GetOutboxHeaders;
+        let value = unwrap_result!(serialisation::serialise(&wrapper));
+        let request = RequestMessage {
+            src: stranger.clone(),
+            dst: our_authority.clone(),
+            content: RequestContent::Post(Data::Plain(PlainData::new(our_authority.name()
+                                                                                  .clone(),
+                                                                     value)),
+                                          MessageId::new()),
+        };
+        let result = env.mpid_manager.handle_post(&env.routing, &request);
+        check_failure(result,
+                      &env.routing.post_failures_given(),
+                      ClientError::InvalidOperation);
+
+        // Only the recipient's managers can fetch the message.
+        let result = get_mpid_message(&mut env,
+                                      mpid_message.header(),
+                                      &Authority::ClientManager(rand::random()),
+                                      &our_authority,
+                                      &MessageId::new());
+        check_failure(result,
+                      &env.routing.post_failures_given(),
+                      ClientError::InvalidOperation);
+
+        // Besides the sender's clients, only the recipient can delete it.
+        let result = delete_mpid_message(&mut env,
+                                         &message_name,
+                                         &stranger,
+                                         &our_authority,
+                                         &MessageId::new());
+        check_failure(result,
+                      &env.routing.delete_failures_given(),
+                      ClientError::InvalidOperation);
+        unwrap_result!(delete_mpid_message(&mut env,
+                                           &message_name,
+                                           &receiver,
+                                           &our_authority,
+                                           &MessageId::new()));
+        assert_eq!(env.routing.delete_failures_given().len(), 2);
+    }
+
     #[test]
     fn get_message() {
         let mut env = environment_setup();
@@ -980,7 +1374,7 @@ mod test {
         let src = Authority::ClientManager(receiver_name.clone());
         let dst = env.our_authority.clone();
         let message_id = MessageId::new();
-        get_mpid_message(&mut env, &mpid_header, &src, &dst, &message_id);
+        unwrap_result!(get_mpid_message(&mut env, &mpid_header, &src, &dst, &message_id));
 
         let post_requests = env.routing.post_requests_given();
         assert_eq!(post_requests.len(), 1);
@@ -1199,14 +1593,21 @@ mod test {
         // delete message...
         let mpid_header_name = unwrap_result!(mpid_message.header().name());
         let message_id = MessageId::new();
-        delete_mpid_message(&mut env, &mpid_header_name, &src, &dst, &message_id);
+        unwrap_result!(delete_mpid_message(&mut env,
+                                           &mpid_header_name,
+                                           &src,
+                                           &dst,
+                                           &message_id));
 
         // get message...
         let mpid_header = mpid_message.header().clone();
         let src = Authority::ClientManager(receiver_name.clone());
         let dst = env.our_authority.clone();
         let message_id = MessageId::new();
-        get_mpid_message(&mut env, &mpid_header, &src, &dst, &message_id);
+        match get_mpid_message(&mut env, &mpid_header, &src, &dst, &message_id) {
+            Err(InternalError::Client(ClientError::NoSuchData)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
 
         let post_failures = env.routing.post_failures_given();
         assert_eq!(post_failures.len(), 1);
@@ -1226,7 +1627,10 @@ mod test {
                 };
                 assert_eq!(*id, message_id);
                 assert_eq!(*request, get_request);
-                assert_eq!(*external_error_indicator, vec![]);
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::NoSuchData => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
             }
             _ => unreachable!(),
         }
@@ -1289,7 +1693,11 @@ mod test {
         // delete header...
         let mpid_header_name = unwrap_result!(mpid_header.name());
         let message_id = MessageId::new();
-        delete_mpid_header(&mut env, &mpid_header_name, &receiver, &dst, &message_id);
+        unwrap_result!(delete_mpid_header(&mut env,
+                                          &mpid_header_name,
+                                          &receiver,
+                                          &dst,
+                                          &message_id));
 
         let delete_requests = env.routing.delete_requests_given();
         assert!(delete_requests.is_empty());
@@ -1298,7 +1706,10 @@ mod test {
 
         // delete header again...
         let message_id = MessageId::new();
-        delete_mpid_header(&mut env, &mpid_header_name, &receiver, &dst, &message_id);
+        match delete_mpid_header(&mut env, &mpid_header_name, &receiver, &dst, &message_id) {
+            Err(InternalError::Client(ClientError::NoSuchData)) => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
 
         let delete_requests = env.routing.delete_requests_given();
         assert!(delete_requests.is_empty());
@@ -1319,7 +1730,10 @@ mod test {
                 };
                 assert_eq!(*id, message_id);
                 assert_eq!(*request, delete_request);
-                assert_eq!(*external_error_indicator, vec![]);
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::NoSuchData => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
             }
             _ => unreachable!(),
         }
@@ -1377,7 +1791,7 @@ mod test {
         let src = Authority::ClientManager(receiver_name.clone());
         let dst = env.our_authority.clone();
         let message_id = MessageId::new();
-        get_mpid_message(&mut env, &mpid_header, &src, &dst, &message_id);
+        unwrap_result!(get_mpid_message(&mut env, &mpid_header, &src, &dst, &message_id));
 
         let post_requests = env.routing.post_requests_given();
         assert_eq!(post_requests.len(), 1);
@@ -15,16 +15,23 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-use error::InternalError;
+use clock;
+use config_handler::Config;
+use error::{ClientError, InternalError};
 use maidsafe_utilities::serialisation;
-use routing::{Authority, Data, MessageId, RequestContent, RequestMessage};
+use personas::Persona;
+use routing::{Authority, Data, MessageId, RequestContent, RequestMessage, ResponseContent,
+              ResponseMessage};
 use sodiumoxide::crypto::hash::sha512;
+use state_store::StateStore;
 use std::collections::HashMap;
 use time::{Duration, SteadyTime};
-use types::{Refresh, RefreshValue};
+use types::{DataLost, Refresh};
 use vault::RoutingNode;
 use xor_name::XorName;
 
+pub const PERSONA_NAME: &'static str = "PmidManager";
+
 #[derive(RustcEncodable, RustcDecodable, PartialEq, Eq, Debug, Clone)]
 pub struct Account {
     stored_total_size: u64,
@@ -61,7 +68,6 @@ impl Account {
         }
     }
 
-    #[allow(dead_code)]
     fn handle_lost_data(&mut self, size: u64) {
         self.delete_data(size);
         self.lost_total_size += size;
@@ -94,24 +100,26 @@ impl MetadataForPutRequest {
     pub fn new(request: RequestMessage) -> MetadataForPutRequest {
         MetadataForPutRequest {
             request: request,
-            creation_timestamp: SteadyTime::now(),
+            creation_timestamp: clock::now(),
         }
     }
 }
 
 
 pub struct PmidManager {
-    accounts: HashMap<XorName, Account>,
+    accounts: StateStore<Account>,
     // key -- (message_id, targeted pmid_node)
     ongoing_puts: HashMap<(MessageId, XorName), MetadataForPutRequest>,
+    refreshes_sent: u64,
 }
 
 impl PmidManager {
-    pub fn new() -> PmidManager {
-        PmidManager {
-            accounts: HashMap::new(),
+    pub fn new(config: &Config) -> Result<PmidManager, InternalError> {
+        Ok(PmidManager {
+            accounts: try!(StateStore::open(config, PERSONA_NAME)),
             ongoing_puts: HashMap::new(),
-        }
+            refreshes_sent: 0,
+        })
     }
 
     pub fn handle_put(&mut self,
@@ -123,10 +131,8 @@ impl PmidManager {
             _ => unreachable!("Error in vault demuxing"),
         };
         // Put data always being allowed, i.e. no early alert
-        self.accounts
-            .entry(request.dst.name().clone())
-            .or_insert(Account::default())
-            .put_data(data.payload_size() as u64);
+        self.accounts.update_or_default(request.dst.name().clone(),
+                                        |account| account.put_data(data.payload_size() as u64));
 
         let src = Authority::NodeManager(request.dst.name().clone());
         let dst = Authority::ManagedNode(request.dst.name().clone());
@@ -143,7 +149,7 @@ impl PmidManager {
         let time_limit = Duration::minutes(1);
         let mut timed_out_puts = Vec::<(MessageId, XorName)>::new();
         for (key, metadata_for_put) in &self.ongoing_puts {
-            if metadata_for_put.creation_timestamp + time_limit < SteadyTime::now() {
+            if metadata_for_put.creation_timestamp + time_limit < clock::now() {
                 timed_out_puts.push(key.clone());
             }
         }
@@ -196,12 +202,45 @@ impl PmidManager {
         let src = request.dst.clone();
         let dst = request.src.clone();
         trace!("As {:?} sending Put failure to {:?} of data {}", src, dst, data.name());
-        let _ = routing_node.send_put_failure(src, dst, request.clone(), vec![], message_id);
+        let external_error_indicator = try!(serialisation::serialise(&ClientError::StoreError));
+        let _ = routing_node.send_put_failure(src,
+                                              dst,
+                                              request.clone(),
+                                              external_error_indicator,
+                                              message_id);
 
-        if let Some(account) = self.accounts.get_mut(request.dst.name()) {
-            account.delete_data(data.payload_size() as u64);
+        let _ = self.accounts.update(request.dst.name(),
+                                     |account| account.delete_data(data.payload_size() as u64));
+
+        Ok(())
+    }
+
+    // The PmidNode has lost a chunk, either evicting it or finding it corrupt.  Adjust its account
+    // and pass the notification on to the chunk's managers.
+    pub fn handle_post(&mut self,
+                       routing_node: &RoutingNode,
+                       request: &RequestMessage) -> Result<(), InternalError> {
+        let (data, message_id) = match request.content {
+            RequestContent::Post(Data::Plain(ref data), ref message_id) => {
+                (data.clone(), message_id.clone())
+            }
+            _ => unreachable!("Error in vault demuxing"),
+        };
+        // A PmidNode may only report on the chunks it holds itself.
+        let pmid_node = request.src.name().clone();
+        if pmid_node != *request.dst.name() {
+            warn!("{:?} is not allowed to report lost data for {:?}", request.src, request.dst);
+            return Ok(());
         }
 
+        let data_lost = try!(serialisation::deserialise::<DataLost>(&data.value()));
+        let _ = self.accounts.update(&pmid_node,
+                                     |account| account.handle_lost_data(data_lost.size));
+
+        let src = Authority::NodeManager(pmid_node);
+        let dst = Authority::NaeManager(data_lost.data_name);
+        trace!("As {:?} notifying {:?} of lost data", src, dst);
+        let _ = routing_node.send_post_request(src, dst, Data::Plain(data), message_id);
         Ok(())
     }
 
@@ -217,15 +256,134 @@ impl PmidManager {
             }
 
             let src = Authority::NodeManager(pmid_node.clone());
-            let refresh = Refresh::new(pmid_node,
-                                       RefreshValue::PmidManagerAccount(account.clone()));
-            if let Ok(serialised_refresh) = serialisation::serialise(&refresh) {
+            let refresh = Refresh::new(PERSONA_NAME, pmid_node, account);
+            if let Ok(serialised_refresh) = refresh.and_then(|refresh| {
+                serialisation::serialise(&refresh)
+            }) {
                 debug!("PmidManager sending refresh for account {:?}", src.name());
                 let _ = routing_node.send_refresh_request(src, serialised_refresh);
+                self.refreshes_sent += 1;
+            }
+        }
+    }
+
+}
+
+impl Persona for PmidManager {
+    fn name(&self) -> &'static str {
+        PERSONA_NAME
+    }
+
+    fn accepts_request(&self, src: &Authority, dst: &Authority, content: &RequestContent) -> bool {
+        match (src, dst, content) {
+            (&Authority::NaeManager(_),
+             &Authority::NodeManager(_),
+             &RequestContent::Put(Data::Immutable(_), _)) |
+            (&Authority::ManagedNode(_),
+             &Authority::NodeManager(_),
+             &RequestContent::Post(Data::Plain(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    fn accepts_response(&self,
+                        src: &Authority,
+                        dst: &Authority,
+                        content: &ResponseContent)
+                        -> bool {
+        match (src, dst, content) {
+            (&Authority::ManagedNode(_),
+             &Authority::NodeManager(_),
+             &ResponseContent::PutSuccess(..)) |
+            (&Authority::ManagedNode(_),
+             &Authority::NodeManager(_),
+             &ResponseContent::PutFailure{ .. }) => true,
+            _ => false,
+        }
+    }
+
+    fn on_request(&mut self,
+                  routing_node: &RoutingNode,
+                  request: &RequestMessage)
+                  -> Result<(), InternalError> {
+        match request.content {
+            RequestContent::Put(..) => self.handle_put(routing_node, request),
+            RequestContent::Post(..) => self.handle_post(routing_node, request),
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_response(&mut self,
+                   routing_node: &RoutingNode,
+                   response: &ResponseMessage)
+                   -> Result<(), InternalError> {
+        match response.content {
+            ResponseContent::PutSuccess(_, ref message_id) => {
+                self.handle_put_success(routing_node, response.src.name(), message_id)
+            }
+            ResponseContent::PutFailure{ ref request, .. } => {
+                self.handle_put_failure(routing_node, request)
             }
+            _ => unreachable!("Error in vault demuxing"),
         }
     }
 
+    fn on_refresh(&mut self,
+                  _routing_node: &RoutingNode,
+                  src: &Authority,
+                  dst: &Authority,
+                  refresh: &Refresh)
+                  -> Result<(), InternalError> {
+        match (src, dst) {
+            (&Authority::NodeManager(_), &Authority::NodeManager(_)) => {
+                let account = try!(refresh.value::<Account>());
+                self.handle_refresh(refresh.name, account);
+                Ok(())
+            }
+            _ => Err(InternalError::UnknownRefreshType(src.clone(), dst.clone(), refresh.clone())),
+        }
+    }
+
+    fn on_node_added(&mut self, routing_node: &RoutingNode, _node_added: &XorName) {
+        self.handle_churn(routing_node)
+    }
+
+    fn on_node_lost(&mut self, routing_node: &RoutingNode, _node_lost: &XorName) {
+        self.handle_churn(routing_node)
+    }
+
+    fn on_drain(&mut self, routing_node: &RoutingNode) {
+        self.handle_churn(routing_node)
+    }
+
+    fn on_tick(&mut self, routing_node: &RoutingNode) {
+        self.check_timeout(routing_node)
+    }
+
+    // The PmidNodes we manage depend on our name, which changes when we rejoin.
+    fn on_disconnected(&mut self) {
+        self.accounts.clear_in_memory();
+        self.ongoing_puts.clear();
+    }
+
+    fn stats(&self) -> Vec<(&'static str, u64)> {
+        vec![("accounts", self.accounts.iter().len() as u64),
+             ("ongoing_puts", self.ongoing_puts.len() as u64),
+             ("refreshes_sent_total", self.refreshes_sent)]
+    }
+
+    fn account_names(&self) -> Vec<XorName> {
+        self.accounts.iter().map(|(name, _)| *name).collect()
+    }
+
+    fn ongoing_requests(&self) -> Vec<String> {
+        self.ongoing_puts
+            .keys()
+            .map(|&(ref message_id, ref pmid_node)| {
+                format!("Put {:?} to {}", message_id, pmid_node)
+            })
+            .collect()
+    }
 }
 
 
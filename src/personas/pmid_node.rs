@@ -16,26 +16,52 @@
 // relating to use of the SAFE Network Software.
 
 use chunk_store::ChunkStore;
-use default_chunk_store;
+use clock;
+use config_handler::Config;
+use default_chunk_store::{self, PMID_NODE};
 use error::{ClientError, InternalError};
 use maidsafe_utilities::serialisation;
-use routing::{Data, DataRequest, ImmutableData, ImmutableDataType,
-              MessageId, RequestContent, RequestMessage};
+use personas::Persona;
+use rng;
+use routing::{Authority, Data, DataRequest, ImmutableData, ImmutableDataType,
+              MessageId, PlainData, RequestContent, RequestMessage};
 use sodiumoxide::crypto::hash::sha512;
+use std::cmp::min;
+use std::collections::HashSet;
+use std::mem;
+use time::{Duration, SteadyTime};
+use types::{Audit, DataLost};
+use utils;
 use vault::RoutingNode;
 use xor_name::XorName;
 
+pub const PERSONA_NAME: &'static str = "PmidNode";
+
+const SCRUB_INTERVAL_SECS: i64 = 60;
+// Number of chunks checked by each scrub.
+const SCRUB_BATCH_SIZE: usize = 10;
+
 pub struct PmidNode {
     chunk_store: ChunkStore,
+    last_scrub: SteadyTime,
+    // Index into the chunk store's names of the next chunk to be scrubbed.
+    scrub_cursor: usize,
+    // While draining, the chunks being handed off which the managers haven't retrieved yet.
+    handing_off: Option<HashSet<XorName>>,
+    // Errors sent to the managers since the registry last took them.
+    client_errors: Vec<ClientError>,
 }
 
 impl PmidNode {
-    pub fn new() -> Result<PmidNode, InternalError> {
+    pub fn new(config: &Config) -> Result<PmidNode, InternalError> {
         Ok(PmidNode {
-            // TODO allow adjustable max_disk_space and return meaningful error rather than panic
-            // if the ChunkStore creation fails.
-            // See https://maidsafe.atlassian.net/browse/MAID-1189
-            chunk_store: try!(default_chunk_store::new()),
+            chunk_store: try!(default_chunk_store::new(config,
+                                                       PMID_NODE,
+                                                       config.pmid_node_capacity())),
+            last_scrub: clock::now(),
+            scrub_cursor: 0,
+            handing_off: None,
+            client_errors: Vec::new(),
         })
     }
 
@@ -61,18 +87,14 @@ impl PmidNode {
                                                       request.src.clone(),
                                                       immutable_data,
                                                       message_id.clone());
+                if let Some(ref mut handing_off) = self.handing_off {
+                    let _ = handing_off.remove(data_name);
+                }
                 return Ok(());
             }
         }
-        let error = ClientError::NoSuchData;
-        let external_error_indicator = try!(serialisation::serialise(&error));
         trace!("As {:?} sending get failure of data {} to {:?}", request.dst, data_name, request.src);
-        let _ = routing_node.send_get_failure(request.dst.clone(),
-                                              request.src.clone(),
-                                              request.clone(),
-                                              external_error_indicator,
-                                              *message_id);
-        Ok(())
+        self.reply_with_failure(routing_node, request, *message_id, ClientError::NoSuchData)
     }
 
     pub fn handle_put(&mut self,
@@ -84,6 +106,13 @@ impl PmidNode {
             _ => unreachable!("Error in vault demuxing"),
         };
         let data_name = data.name();
+        if self.handing_off.is_some() {
+            trace!("As {:?} refusing to store {} while leaving", request.dst, data_name);
+            return self.reply_with_failure(routing_node,
+                                           request,
+                                           message_id,
+                                           ClientError::StoreError);
+        }
         info!("pmid_node {:?} storing {:?}", request.dst.name(), data_name);
         let serialised_data = try!(serialisation::serialise(&data));
         if self.chunk_store.has_space(serialised_data.len() as u64) {
@@ -97,8 +126,14 @@ impl PmidNode {
         // If we can't store the data and it's a Backup or Sacrificial copy, just notify PmidManager
         // to update the account - replication shall not be carried out for it.
         if *data.get_type_tag() != ImmutableDataType::Normal {
-            // self.notify_managers_of_sacrifice(our_authority, data, response_token);
-            return Ok(());
+            trace!("As {:?} refusing to store {:?} copy {}",
+                   request.dst,
+                   data.get_type_tag(),
+                   data_name);
+            return self.reply_with_failure(routing_node,
+                                           request,
+                                           message_id,
+                                           ClientError::StoreError);
         }
 
         // If we can't store the data and it's a Normal copy, try to make room for it by clearing
@@ -117,9 +152,14 @@ impl PmidNode {
             let parsed_data = match serialisation::deserialise::<ImmutableData>(&fetched_data) {
                 Ok(data) => data,
                 Err(_) => {
-                    // remove corrupted data and notify manager group
+                    // remove corrupted data and notify manager group.  Its payload size can't be
+                    // known, so the space it took up is reported instead.
                     let _ = self.chunk_store.delete(name);
-                    // self.notify_managers_of_sacrifice(our_authority, data, response_token);
+                    let _ = self.notify_managers_of_lost_data(routing_node,
+                                                              &request.dst,
+                                                              name,
+                                                              fetched_data.len() as u64,
+                                                              false);
                     continue;
                 }
             };
@@ -131,7 +171,11 @@ impl PmidNode {
                     // For sacrificed data, just notify PmidManager to update the account and
                     // ImmutableDataManager need to adjust its farming rate, replication shall not be carried
                     // out for it.
-                    // self.notify_managers_of_sacrifice(&our_authority, parsed_data, &response_token);
+                    let _ = self.notify_managers_of_lost_data(routing_node,
+                                                              &request.dst,
+                                                              name,
+                                                              parsed_data.payload_size() as u64,
+                                                              false);
                     if emptied_space > required_space {
                         try!(self.chunk_store.put(&data_name, &serialised_data));
                         let _ = self.notify_managers_of_success(routing_node, &data_name, &message_id, request);
@@ -143,10 +187,49 @@ impl PmidNode {
         }
 
         // We failed to make room for it - replication needs to be carried out.
-        let src = request.dst.clone();
-        let dst = request.src.clone();
-        trace!("As {:?} sending Put failure of data {} to {:?} ", src, data_name, dst);
-        let _ = routing_node.send_put_failure(src, dst, request.clone(), vec![], message_id);
+        trace!("As {:?} sending Put failure of data {} to {:?} ",
+               request.dst,
+               data_name,
+               request.src);
+        self.reply_with_failure(routing_node, request, message_id, ClientError::StoreError)
+    }
+
+    // Answers an audit challenge from the managers of one of our chunks.  If we don't have the
+    // chunk, no answer is sent and the managers will deem us to have failed the audit.
+    pub fn handle_post(&mut self,
+                       routing_node: &RoutingNode,
+                       request: &RequestMessage)
+                       -> Result<(), InternalError> {
+        let (data, message_id) = match request.content {
+            RequestContent::Post(Data::Plain(ref data), ref message_id) => (data, message_id),
+            _ => unreachable!("Error in vault demuxing"),
+        };
+        let nonce = match try!(serialisation::deserialise::<Audit>(&data.value())) {
+            Audit::Challenge { nonce } => nonce,
+            Audit::Response { .. } => {
+                warn!("As {:?} received an audit response from {:?}", request.dst, request.src);
+                return Ok(());
+            }
+        };
+        let data_name = data.name();
+        let serialised_chunk = match self.chunk_store.get(&data_name) {
+            Ok(serialised_chunk) => serialised_chunk,
+            Err(_) => {
+                warn!("As {:?} unable to answer audit of {} - not held", request.dst, data_name);
+                return Ok(());
+            }
+        };
+        let response = Audit::Response {
+            digest: utils::audit_digest(&nonce, &serialised_chunk),
+            nonce: nonce,
+        };
+        let data = Data::Plain(PlainData::new(data_name,
+                                              try!(serialisation::serialise(&response))));
+        trace!("As {:?} answering audit of {} from {:?}", request.dst, data_name, request.src);
+        let _ = routing_node.send_post_request(request.dst.clone(),
+                                               request.src.clone(),
+                                               data,
+                                               *message_id);
         Ok(())
     }
 
@@ -163,20 +246,429 @@ impl PmidNode {
         Ok(())
     }
 
-    // fn notify_managers_of_sacrifice(&self,
-    //                                 our_authority: &::routing::Authority,
-    //                                 data: ImmutableData,
-    //                                 response_token: &Option<::routing::SignedToken>) {
-    //     let location = Authority::NodeManager(our_authority.name().clone());
-    //     let error =
-    //         ::routing::error::ResponseError::HadToClearSacrificial(data.name(),
-    //                                                                data.payload_size() as u32);
-    //     debug!("As {:?} sacrificing data {:?} freeing space {:?}, notifying {:?}", our_authority,
-    //            data.name(), data.payload_size(), location);
-    //     self.routing.put_response(our_authority.clone(), location, error, response_token.clone());
-    // }
+    // Sends the failure response to `request` carrying `error`.
+    fn reply_with_failure(&mut self,
+                          routing_node: &RoutingNode,
+                          request: &RequestMessage,
+                          message_id: MessageId,
+                          error: ClientError)
+                          -> Result<(), InternalError> {
+        let src = request.dst.clone();
+        let dst = request.src.clone();
+        let external_error_indicator = try!(serialisation::serialise(&error));
+        let _ = match request.content {
+            RequestContent::Get(..) => {
+                routing_node.send_get_failure(src,
+                                              dst,
+                                              request.clone(),
+                                              external_error_indicator,
+                                              message_id)
+            }
+            _ => {
+                routing_node.send_put_failure(src,
+                                              dst,
+                                              request.clone(),
+                                              external_error_indicator,
+                                              message_id)
+            }
+        };
+        self.client_errors.push(error);
+        Ok(())
+    }
+
+    // Checks the next batch of chunks, deleting any which are corrupt so that the managers can
+    // replicate them from another holder.
+    fn scrub(&mut self, routing_node: &RoutingNode) {
+        if clock::now() - self.last_scrub < Duration::seconds(SCRUB_INTERVAL_SECS) {
+            return;
+        }
+        self.last_scrub = clock::now();
+        let names = self.chunk_store.names();
+        if names.is_empty() {
+            return;
+        }
+        let our_authority = match routing_node.name() {
+            Ok(name) => Authority::ManagedNode(name),
+            Err(error) => {
+                warn!("Unable to scrub chunks: {:?}", error);
+                return;
+            }
+        };
+        for index in 0..min(SCRUB_BATCH_SIZE, names.len()) {
+            let name = &names[(self.scrub_cursor + index) % names.len()];
+            let serialised_chunk = match self.chunk_store.get(name) {
+                Ok(serialised_chunk) => serialised_chunk,
+                Err(_) => continue,
+            };
+            let size = match serialisation::deserialise::<ImmutableData>(&serialised_chunk) {
+                Ok(ref data) if data.name() == *name => continue,
+                Ok(data) => data.payload_size() as u64,
+                // A chunk which can't be parsed has no known payload size, so the space it took
+                // up is reported instead.
+                Err(_) => serialised_chunk.len() as u64,
+            };
+            warn!("As {:?} deleting corrupt chunk {}", our_authority, name);
+            let _ = self.chunk_store.delete(name);
+            let _ = self.notify_managers_of_lost_data(routing_node,
+                                                      &our_authority,
+                                                      name,
+                                                      size,
+                                                      false);
+        }
+        self.scrub_cursor = (self.scrub_cursor + SCRUB_BATCH_SIZE) % names.len();
+    }
+
+    fn notify_managers_of_lost_data(&self,
+                                    routing_node: &RoutingNode,
+                                    our_authority: &Authority,
+                                    data_name: &XorName,
+                                    size: u64,
+                                    handing_off: bool)
+                                    -> Result<(), InternalError> {
+        let data_lost = DataLost {
+            data_name: *data_name,
+            size: size,
+            handing_off: handing_off,
+        };
+        let data = Data::Plain(PlainData::new(*data_name,
+                                              try!(serialisation::serialise(&data_lost))));
+        let src = our_authority.clone();
+        let dst = Authority::NodeManager(our_authority.name().clone());
+        debug!("As {:?} lost data {} freeing space {}, notifying {:?}",
+               src,
+               data_name,
+               size,
+               dst);
+        let _ = routing_node.send_post_request(src, dst, data, rng::new_message_id());
+        Ok(())
+    }
 }
 
+impl Persona for PmidNode {
+    fn name(&self) -> &'static str {
+        PERSONA_NAME
+    }
+
+    fn accepts_request(&self, src: &Authority, dst: &Authority, content: &RequestContent) -> bool {
+        match (src, dst, content) {
+            (&Authority::NaeManager(_),
+             &Authority::ManagedNode(_),
+             &RequestContent::Get(DataRequest::Immutable(_, _), _)) |
+            (&Authority::NodeManager(_),
+             &Authority::ManagedNode(_),
+             &RequestContent::Put(Data::Immutable(_), _)) |
+            (&Authority::NaeManager(_),
+             &Authority::ManagedNode(_),
+             &RequestContent::Post(Data::Plain(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    fn on_request(&mut self,
+                  routing_node: &RoutingNode,
+                  request: &RequestMessage)
+                  -> Result<(), InternalError> {
+        match request.content {
+            RequestContent::Get(..) => self.handle_get(routing_node, request),
+            RequestContent::Put(..) => self.handle_put(routing_node, request),
+            RequestContent::Post(..) => self.handle_post(routing_node, request),
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_tick(&mut self, routing_node: &RoutingNode) {
+        self.scrub(routing_node)
+    }
+
+    // Tells the managers of each chunk that we're leaving, so that they replicate it while we can
+    // still serve it.  Sacrificial copies aren't replicated, so they aren't waited for.
+    fn on_drain(&mut self, routing_node: &RoutingNode) {
+        let our_authority = match routing_node.name() {
+            Ok(name) => Authority::ManagedNode(name),
+            Err(error) => {
+                warn!("Unable to hand off chunks: {:?}", error);
+                return;
+            }
+        };
+        let mut handing_off = HashSet::new();
+        for name in self.chunk_store.names() {
+            let data = match self.chunk_store
+                                 .get(&name)
+                                 .ok()
+                                 .and_then(|chunk| {
+                                     serialisation::deserialise::<ImmutableData>(&chunk).ok()
+                                 }) {
+                Some(data) => data,
+                None => continue,
+            };
+            if *data.get_type_tag() != ImmutableDataType::Sacrificial {
+                let _ = handing_off.insert(name);
+            }
+            let _ = self.notify_managers_of_lost_data(routing_node,
+                                                      &our_authority,
+                                                      &name,
+                                                      data.payload_size() as u64,
+                                                      true);
+        }
+        info!("As {:?} handing off {} chunks", our_authority, handing_off.len());
+        self.handing_off = Some(handing_off);
+    }
+
+    fn is_drained(&self) -> bool {
+        self.handing_off.as_ref().map_or(true, |handing_off| handing_off.is_empty())
+    }
+
+    fn stats(&self) -> Vec<(&'static str, u64)> {
+        vec![("chunks_stored", self.chunk_store.names().len() as u64)]
+    }
+
+    fn chunk_store_usage(&self) -> Vec<(&'static str, u64, u64)> {
+        vec![(PMID_NODE, self.chunk_store.used_space(), self.chunk_store.max_space())]
+    }
+
+    fn take_client_errors(&mut self) -> Vec<ClientError> {
+        mem::replace(&mut self.client_errors, Vec::new())
+    }
+}
+
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use super::SCRUB_INTERVAL_SECS;
+    use clock;
+    use config_handler::Config;
+    use error::ClientError;
+    use maidsafe_utilities::serialisation;
+    use personas::Persona;
+    use rand::random;
+    use routing::{Authority, Data, DataRequest, ImmutableData, ImmutableDataType, MessageId,
+                  PlainData, RequestContent, RequestMessage, ResponseContent};
+    use std::sync::mpsc;
+    use time::Duration;
+    use types::{Audit, DataLost};
+    use utils::{self, generate_random_vec_u8};
+    use vault::RoutingNode;
+    use xor_name::XorName;
+
+    struct Environment {
+        our_authority: Authority,
+        routing: RoutingNode,
+        pmid_node: PmidNode,
+    }
+
+    fn environment_setup(capacity: Option<u64>) -> Environment {
+        let routing = unwrap_result!(RoutingNode::new(mpsc::channel().0));
+        let config = Config { pmid_node_capacity: capacity, ..utils::test_config() };
+        Environment {
+            our_authority: Authority::ManagedNode(unwrap_result!(routing.name())),
+            routing: routing,
+            pmid_node: unwrap_result!(PmidNode::new(&config)),
+        }
+    }
+
+    impl Environment {
+        fn put(&mut self, type_tag: ImmutableDataType) -> (ImmutableData, RequestMessage) {
+            let data = ImmutableData::new(type_tag, generate_random_vec_u8(1024));
+            let request = RequestMessage {
+                src: Authority::NodeManager(self.our_authority.name().clone()),
+                dst: self.our_authority.clone(),
+                content: RequestContent::Put(Data::Immutable(data.clone()), MessageId::new()),
+            };
+            unwrap_result!(self.pmid_node.handle_put(&self.routing, &request));
+            (data, request)
+        }
+
+        fn get(&mut self, data_name: XorName) {
+            let request = RequestMessage {
+                src: Authority::NaeManager(data_name),
+                dst: self.our_authority.clone(),
+                content: RequestContent::Get(DataRequest::Immutable(data_name,
+                                                                    ImmutableDataType::Normal),
+                                             MessageId::new()),
+            };
+            unwrap_result!(self.pmid_node.handle_get(&self.routing, &request));
+        }
+
+        fn audit(&mut self, data_name: XorName, nonce: Vec<u8>) {
+            let challenge = Audit::Challenge { nonce: nonce };
+            let data = PlainData::new(data_name,
+                                      unwrap_result!(serialisation::serialise(&challenge)));
+            let request = RequestMessage {
+                src: Authority::NaeManager(data_name),
+                dst: self.our_authority.clone(),
+                content: RequestContent::Post(Data::Plain(data), MessageId::new()),
+            };
+            unwrap_result!(self.pmid_node.handle_post(&self.routing, &request));
+        }
+
+        // The DataLost notifications sent to our managers so far.
+        fn data_lost_given(&self) -> Vec<DataLost> {
+            let manager = Authority::NodeManager(self.our_authority.name().clone());
+            self.routing
+                .post_requests_given()
+                .into_iter()
+                .filter(|request| request.dst == manager)
+                .map(|request| {
+                    match request.content {
+                        RequestContent::Post(Data::Plain(data), _) => {
+                            unwrap_result!(serialisation::deserialise::<DataLost>(&data.value()))
+                        }
+                        _ => unreachable!(),
+                    }
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn answer_audit() {
+        let mut env = environment_setup(None);
+        let (data, _) = env.put(ImmutableDataType::Normal);
+        let nonce = generate_random_vec_u8(16);
+        env.audit(data.name(), nonce.clone());
+
+        let post_requests = env.routing.post_requests_given();
+        assert_eq!(post_requests.len(), 1);
+        assert_eq!(post_requests[0].src, env.our_authority);
+        assert_eq!(post_requests[0].dst, Authority::NaeManager(data.name()));
+        let serialised_chunk = unwrap_result!(serialisation::serialise(&data));
+        match post_requests[0].content {
+            RequestContent::Post(Data::Plain(ref response), _) => {
+                assert_eq!(response.name(), data.name());
+                assert_eq!(unwrap_result!(serialisation::deserialise::<Audit>(&response.value())),
+                           Audit::Response {
+                               digest: utils::audit_digest(&nonce, &serialised_chunk),
+                               nonce: nonce,
+                           });
+            }
+            _ => unreachable!(),
+        }
+
+        // A chunk we don't hold can't be answered for.
+        env.audit(random(), generate_random_vec_u8(16));
+        assert_eq!(env.routing.post_requests_given().len(), 1);
+    }
+
+    #[test]
+    fn scrub_deletes_corrupt_chunks() {
+        let mut env = environment_setup(None);
+        let (data, _) = env.put(ImmutableDataType::Normal);
+        let corrupt_name = random::<XorName>();
+        let corrupt_chunk = generate_random_vec_u8(100);
+        unwrap_result!(env.pmid_node.chunk_store.put(&corrupt_name, &corrupt_chunk));
+        // A valid chunk stored under the wrong name is corrupt too.
+        let misnamed_data = ImmutableData::new(ImmutableDataType::Normal,
+                                               generate_random_vec_u8(100));
+        let misnamed_name = random::<XorName>();
+        let misnamed_chunk = unwrap_result!(serialisation::serialise(&misnamed_data));
+        unwrap_result!(env.pmid_node.chunk_store.put(&misnamed_name, &misnamed_chunk));
+
+        // Nothing is scrubbed until the scrub interval has passed.
+        env.pmid_node.on_tick(&env.routing);
+        assert!(env.pmid_node.chunk_store.has_chunk(&corrupt_name));
+
+        clock::advance(Duration::seconds(SCRUB_INTERVAL_SECS));
+        env.pmid_node.on_tick(&env.routing);
+        assert!(!env.pmid_node.chunk_store.has_chunk(&corrupt_name));
+        assert!(!env.pmid_node.chunk_store.has_chunk(&misnamed_name));
+        assert!(env.pmid_node.chunk_store.has_chunk(&data.name()));
+        let mut data_lost = env.data_lost_given();
+        data_lost.sort_by(|lhs, rhs| lhs.data_name.cmp(&rhs.data_name));
+        let mut expected = vec![DataLost {
+                                    data_name: corrupt_name,
+                                    size: corrupt_chunk.len() as u64,
+                                    handing_off: false,
+                                },
+                                DataLost {
+                                    data_name: misnamed_name,
+                                    size: misnamed_data.payload_size() as u64,
+                                    handing_off: false,
+                                }];
+        expected.sort_by(|lhs, rhs| lhs.data_name.cmp(&rhs.data_name));
+        assert_eq!(data_lost, expected);
+    }
+
+    #[test]
+    fn evicting_sacrificial_copy_notifies_managers() {
+        // Room for two chunks but not three.
+        let mut env = environment_setup(Some(2500));
+        let (sacrificial_0, _) = env.put(ImmutableDataType::Sacrificial);
+        let (sacrificial_1, _) = env.put(ImmutableDataType::Sacrificial);
+        assert!(env.data_lost_given().is_empty());
+
+        let (normal, _) = env.put(ImmutableDataType::Normal);
+        assert!(env.pmid_node.chunk_store.has_chunk(&normal.name()));
+        assert_eq!(env.routing.put_successes_given().len(), 3);
+        let data_lost = env.data_lost_given();
+        assert_eq!(data_lost.len(), 1);
+        let evicted = if data_lost[0].data_name == sacrificial_0.name() {
+            sacrificial_0
+        } else {
+            sacrificial_1
+        };
+        assert_eq!(data_lost[0],
+                   DataLost {
+                       data_name: evicted.name(),
+                       size: evicted.payload_size() as u64,
+                       handing_off: false,
+                   });
+        assert!(!env.pmid_node.chunk_store.has_chunk(&evicted.name()));
+    }
+
+    #[test]
+    fn drain_hands_off_chunks() {
+        let mut env = environment_setup(None);
+        let (normal, _) = env.put(ImmutableDataType::Normal);
+        let (sacrificial, _) = env.put(ImmutableDataType::Sacrificial);
+        assert!(env.pmid_node.is_drained());
+
+        env.pmid_node.on_drain(&env.routing);
+        let mut data_lost = env.data_lost_given();
+        data_lost.sort_by(|lhs, rhs| lhs.data_name.cmp(&rhs.data_name));
+        let mut expected = vec![DataLost {
+                                    data_name: normal.name(),
+                                    size: normal.payload_size() as u64,
+                                    handing_off: true,
+                                },
+                                DataLost {
+                                    data_name: sacrificial.name(),
+                                    size: sacrificial.payload_size() as u64,
+                                    handing_off: true,
+                                }];
+        expected.sort_by(|lhs, rhs| lhs.data_name.cmp(&rhs.data_name));
+        assert_eq!(data_lost, expected);
+        // Only the Normal chunk needs to be retrieved before we're drained.
+        assert!(!env.pmid_node.is_drained());
+
+        // New chunks are refused while handing off.
+        let (refused, request) = env.put(ImmutableDataType::Normal);
+        assert!(!env.pmid_node.chunk_store.has_chunk(&refused.name()));
+        let put_failures = env.routing.put_failures_given();
+        assert_eq!(put_failures.len(), 1);
+        match put_failures[0].content {
+            ResponseContent::PutFailure { request: ref failed_request,
+                                          ref external_error_indicator,
+                                          .. } => {
+                assert_eq!(*failed_request, request);
+                let error = serialisation::deserialise::<ClientError>(external_error_indicator);
+                match unwrap_result!(error) {
+                    ClientError::StoreError => (),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+        let client_errors = env.pmid_node.take_client_errors();
+        assert_eq!(client_errors.len(), 1);
+        assert_eq!(client_errors[0].kind(), ClientError::StoreError.kind());
+        assert!(env.pmid_node.take_client_errors().is_empty());
+
+        env.get(normal.name());
+        assert_eq!(env.routing.get_successes_given().len(), 1);
+        assert!(env.pmid_node.is_drained());
+    }
+}
 
 // #[cfg(all(test, feature = "use-mock-routing"))]
 // mod test {
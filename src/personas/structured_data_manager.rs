@@ -16,26 +16,93 @@
 // relating to use of the SAFE Network Software.
 
 use chunk_store::ChunkStore;
-use default_chunk_store;
+use clock;
+use config_handler::Config;
+use default_chunk_store::{self, STRUCTURED_DATA_HISTORY, STRUCTURED_DATA_MANAGER};
 use error::{ClientError, InternalError};
 use maidsafe_utilities::serialisation;
-use routing::{Authority, Data, DataRequest, RequestContent, RequestMessage, StructuredData};
+use personas::Persona;
+use routing::{Authority, Data, DataRequest, RequestContent, RequestMessage, RoutingError,
+              StructuredData};
 use sodiumoxide::crypto::hash::sha512;
-use types::{Refresh, RefreshValue};
+use sodiumoxide::crypto::sign;
+use state_store::StateStore;
+use time::Duration;
+use types::Refresh;
 use vault::RoutingNode;
+use xor_name::XorName;
+
+pub const PERSONA_NAME: &'static str = "StructuredDataManager";
+const TOMBSTONES: &'static str = "StructuredDataTombstones";
+
+/// A StructuredData together with the past versions of it which are kept, oldest first.
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Versions {
+    pub current: StructuredData,
+    pub history: Vec<StructuredData>,
+}
+
+/// Left in place of a deleted StructuredData until it expires, so that the name can't be reused.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Tombstone {
+    /// The version carried by the Delete request.
+    pub version: u64,
+    /// The owner keys of that version.
+    pub owner_keys: Vec<sign::PublicKey>,
+    /// When the data was deleted, as given by `clock::wall_time_ms`.  The tombstone is collected a
+    /// fixed time after this wherever it's held, however often it's refreshed.
+    pub deleted_at_ms: i64,
+}
+
+/// The value of a StructuredDataManager refresh.
+#[derive(RustcEncodable, RustcDecodable)]
+pub enum Entry {
+    Live(Versions),
+    Deleted(Tombstone),
+}
+
+/// Returns the name to `Get` as `DataRequest::Plain` from the StructuredDataManagers of
+/// `data_name` to retrieve version `version` of that StructuredData.
+pub fn version_name(data_name: &XorName, version: u64) -> XorName {
+    let mut input = data_name.0.to_vec();
+    input.extend((0..8).rev().map(|byte| (version >> (8 * byte)) as u8));
+    XorName(sha512::hash(&input).0)
+}
 
 pub struct StructuredDataManager {
     chunk_store: ChunkStore,
+    // Past versions of each StructuredData, keyed by its name.
+    history_store: ChunkStore,
+    tombstones: StateStore<Tombstone>,
+    tombstone_retention: Duration,
+    config: Config,
+    refreshes_sent: u64,
 }
 
 impl StructuredDataManager {
-    pub fn new() -> StructuredDataManager {
-        StructuredDataManager {
-            // TODO allow adjustable max_disk_space and return meaningful error rather than panic
-            // if the ChunkStore creation fails.
-            // See https://maidsafe.atlassian.net/browse/MAID-1370
-            chunk_store: default_chunk_store::new().unwrap(),
+    pub fn new(config: &Config) -> Result<StructuredDataManager, InternalError> {
+        let capacity = config.structured_data_manager_capacity();
+        let mut chunk_store = try!(default_chunk_store::new(config,
+                                                            STRUCTURED_DATA_MANAGER,
+                                                            capacity));
+        // Earlier versions marked deleted data with an empty chunk, which was never collected.
+        for data_name in chunk_store.names() {
+            if chunk_store.get(&data_name).map(|data| data.is_empty()).unwrap_or(false) {
+                let _ = chunk_store.delete(&data_name);
+            }
         }
+        let tombstone_retention =
+            Duration::seconds(config.structured_data_tombstone_retention_secs() as i64);
+        Ok(StructuredDataManager {
+            chunk_store: chunk_store,
+            history_store: try!(default_chunk_store::new(config,
+                                                         STRUCTURED_DATA_HISTORY,
+                                                         capacity)),
+            tombstones: try!(StateStore::open(config, TOMBSTONES)),
+            tombstone_retention: tombstone_retention,
+            config: config.clone(),
+            refreshes_sent: 0,
+        })
     }
 
     pub fn handle_get(&mut self,
@@ -63,7 +130,51 @@ impl StructuredDataManager {
             }
         }
 
-        let error = ClientError::NoSuchData;
+        let error = self.missing_data_error(&data_name);
+        let external_error_indicator = try!(serialisation::serialise(&error));
+        try!(routing_node.send_get_failure(request.dst.clone(),
+                                           request.src.clone(),
+                                           request.clone(),
+                                           external_error_indicator,
+                                           message_id.clone()));
+        Ok(())
+    }
+
+    // A versioned Get asks for the `version_name` of one of the versions of the StructuredData
+    // named by the request's destination, current or kept.
+    pub fn handle_get_version(&mut self,
+                              routing_node: &RoutingNode,
+                              request: &RequestMessage)
+                              -> Result<(), InternalError> {
+        let (requested_name, message_id) = match request.content {
+            RequestContent::Get(DataRequest::Plain(ref name), ref message_id) => (name, message_id),
+            _ => unreachable!("Error in vault demuxing"),
+        };
+
+        let data_name = request.dst.name().clone();
+        let mut versions = self.history(&data_name);
+        if let Ok(serialised_data) = self.chunk_store.get(&data_name) {
+            if let Ok(current) = serialisation::deserialise(&serialised_data) {
+                versions.push(current);
+            }
+        }
+        let found = versions.into_iter()
+                             .find(|data| version_name(&data_name, data.get_version()) ==
+                                         *requested_name);
+        if let Some(data) = found {
+            debug!("As {:?} sending version {} of {:?} to {:?}",
+                   request.dst,
+                   data.get_version(),
+                   data_name,
+                   request.src);
+            let _ = routing_node.send_get_success(request.dst.clone(),
+                                                  request.src.clone(),
+                                                  Data::Structured(data),
+                                                  message_id.clone());
+            return Ok(());
+        }
+
+        let error = self.missing_data_error(&data_name);
         let external_error_indicator = try!(serialisation::serialise(&error));
         try!(routing_node.send_get_failure(request.dst.clone(),
                                            request.src.clone(),
@@ -91,9 +202,16 @@ impl StructuredDataManager {
         let response_src = request.dst.clone();
         let response_dst = request.src.clone();
 
-        if self.chunk_store.has_chunk(&data_name) {
+        let error = if self.tombstones.contains_key(&data_name) {
+            debug!("SD {:?} has been deleted", data_name);
+            Some(ClientError::DataDeleted)
+        } else if self.chunk_store.has_chunk(&data_name) {
             debug!("Already have SD {:?}", data_name);
-            let error = ClientError::DataExists;
+            Some(ClientError::DataExists)
+        } else {
+            None
+        };
+        if let Some(error) = error {
             let external_error_indicator = try!(serialisation::serialise(&error));
             trace!("SDM sending PutFailure for data {}", data_name);
             let _ = routing_node.send_put_failure(response_src,
@@ -121,37 +239,21 @@ impl StructuredDataManager {
             _ => unreachable!("Error in vault demuxing"),
         };
 
-        if let Ok(serialised_data) = self.chunk_store.get(&new_data.name()) {
-            if let Ok(mut existing_data) =
-                   serialisation::deserialise::<StructuredData>(&serialised_data) {
-                debug!("StructuredDataManager updating {:?} to {:?}",
-                       existing_data,
-                       new_data);
-                if existing_data.replace_with_other(new_data.clone()).is_ok() {
-                    if let Ok(serialised_data) = serialisation::serialise(&existing_data) {
-                        if let Ok(()) = self.chunk_store
-                                            .put(&existing_data.name(), &serialised_data) {
-                            if let Ok(serialised_request) = serialisation::serialise(request) {
-                                let digest = sha512::hash(&serialised_request[..]);
-                                let _ = routing_node.send_post_success(request.dst.clone(),
-                                                                       request.src.clone(),
-                                                                       digest,
-                                                                       message_id.clone());
-                                return Ok(());
-                            }
-                        }
-                    }
-                } else {
-                    return Ok(());
-                }
-            }
+        if let Err(error) = self.update(new_data) {
+            let external_error_indicator = try!(serialisation::serialise(&error));
+            try!(routing_node.send_post_failure(request.dst.clone(),
+                                                request.src.clone(),
+                                                request.clone(),
+                                                external_error_indicator,
+                                                message_id.clone()));
+            return Err(InternalError::Client(error));
         }
 
-        try!(routing_node.send_post_failure(request.dst.clone(),
-                                            request.src.clone(),
-                                            request.clone(),
-                                            Vec::new(),
-                                            message_id.clone()));
+        let digest = sha512::hash(&try!(serialisation::serialise(request))[..]);
+        let _ = routing_node.send_post_success(request.dst.clone(),
+                                               request.src.clone(),
+                                               digest,
+                                               message_id.clone());
         Ok(())
     }
 
@@ -162,59 +264,81 @@ impl StructuredDataManager {
                          -> Result<(), InternalError> {
         let (data, message_id) = match request.content {
             RequestContent::Delete(Data::Structured(ref data), ref message_id) => {
-                (data.clone(), message_id.clone())
+                (data, message_id)
             }
             _ => unreachable!("Error in vault demuxing"),
         };
 
-        if let Ok(serialised_data) = self.chunk_store.get(&data.name()) {
-            if let Ok(existing_data) =
-                   serialisation::deserialise::<StructuredData>(&serialised_data) {
-                debug!("StructuredDataManager deleting {:?} with requested new version {:?}",
-                       existing_data,
-                       data);
-                if existing_data.validate_self_against_successor(&data).is_ok() {
-                    // Reducing content to empty to avoid later on put bearing the same name
-                    // chunk_store::put() deletes the old data automatically
-                    if let Ok(()) = self.chunk_store.put(&data.name(), &[]) {
-                        if let Ok(serialised_request) = serialisation::serialise(request) {
-                            let digest = sha512::hash(&serialised_request[..]);
-                            let _ = routing_node.send_delete_success(request.dst.clone(),
-                                                                     request.src.clone(),
-                                                                     digest,
-                                                                     message_id.clone());
-                            return Ok(());
-                        }
-                    }
-                }
-            }
+        if let Err(error) = self.delete(data) {
+            let external_error_indicator = try!(serialisation::serialise(&error));
+            try!(routing_node.send_delete_failure(request.dst.clone(),
+                                                  request.src.clone(),
+                                                  request.clone(),
+                                                  external_error_indicator,
+                                                  message_id.clone()));
+            return Err(InternalError::Client(error));
         }
 
-        try!(routing_node.send_delete_failure(request.dst.clone(),
-                                              request.src.clone(),
-                                              request.clone(),
-                                              Vec::new(),
-                                              message_id.clone()));
+        let digest = sha512::hash(&try!(serialisation::serialise(request))[..]);
+        let _ = routing_node.send_delete_success(request.dst.clone(),
+                                                 request.src.clone(),
+                                                 digest,
+                                                 message_id.clone());
         Ok(())
     }
 
-    pub fn handle_refresh(&mut self, structured_data: StructuredData) -> Result<(), InternalError> {
-        if self.chunk_store.has_chunk(&structured_data.name()) {
-            if let Ok(serialised_data) = self.chunk_store.get(&structured_data.name()) {
+    pub fn handle_refresh(&mut self,
+                          data_name: &XorName,
+                          entry: Entry)
+                          -> Result<(), InternalError> {
+        match entry {
+            Entry::Live(versions) => self.handle_versions_refresh(versions),
+            Entry::Deleted(tombstone) => self.handle_tombstone_refresh(data_name, tombstone),
+        }
+    }
+
+    // Live data is ignored while we hold its tombstone.  The received history is only merged if
+    // the data is new to us or validly succeeds the version we hold.
+    fn handle_versions_refresh(&mut self, versions: Versions) -> Result<(), InternalError> {
+        let Versions { current: structured_data, history } = versions;
+        let data_name = structured_data.name();
+        if self.tombstones.contains_key(&data_name) {
+            return Ok(());
+        }
+        if self.chunk_store.has_chunk(&data_name) {
+            if let Ok(serialised_data) = self.chunk_store.get(&data_name) {
                 if let Ok(existing_data) =
                        serialisation::deserialise::<StructuredData>(&serialised_data) {
                     if existing_data.validate_self_against_successor(&structured_data).is_ok() {
                         // chunk_store::put() deletes the old data automatically
-                        return Ok(try!(self.chunk_store.put(&structured_data.name(),
-                                                            &try!(serialisation::serialise(&structured_data)))));
+                        let serialised_data = try!(serialisation::serialise(&structured_data));
+                        try!(self.chunk_store.put(&data_name, &serialised_data));
+                        return self.merge_history(&data_name,
+                                                  structured_data.get_type_tag(),
+                                                  history);
                     }
                 }
             }
+            Ok(())
         } else {
-            return Ok(try!(self.chunk_store
-                               .put(&structured_data.name(),
-                                    &try!(serialisation::serialise(&structured_data)))));
+            try!(self.chunk_store
+                     .put(&data_name, &try!(serialisation::serialise(&structured_data))));
+            self.merge_history(&data_name, structured_data.get_type_tag(), history)
         }
+    }
+
+    fn handle_tombstone_refresh(&mut self,
+                                data_name: &XorName,
+                                tombstone: Tombstone)
+                                -> Result<(), InternalError> {
+        if self.tombstones.contains_key(data_name) {
+            return Ok(());
+        }
+        if self.chunk_store.has_chunk(data_name) {
+            try!(self.chunk_store.delete(data_name));
+        }
+        try!(self.remove_history(data_name));
+        let _ = self.tombstones.insert(*data_name, tombstone);
         Ok(())
     }
 
@@ -232,18 +356,596 @@ impl StructuredDataManager {
                     Err(_) => continue,
                 };
 
-            let src = Authority::NaeManager(data_name.clone());
-            let refresh = Refresh::new(&data_name,
-                                       RefreshValue::StructuredDataManager(structured_data));
-            if let Ok(serialised_refresh) = serialisation::serialise(&refresh) {
-                debug!("SD Manager sending refresh for data {:?}", src.name());
-                let _ = routing_node.send_refresh_request(src, serialised_refresh);
+            let versions = Versions {
+                current: structured_data,
+                history: self.history(&data_name),
+            };
+            self.send_refresh(routing_node, &data_name, &Entry::Live(versions));
+        }
+
+        let tombstones = self.tombstones
+                             .iter()
+                             .map(|(data_name, tombstone)| (*data_name, tombstone.clone()))
+                             .collect::<Vec<_>>();
+        for (data_name, tombstone) in tombstones {
+            self.send_refresh(routing_node, &data_name, &Entry::Deleted(tombstone));
+        }
+    }
+
+    fn send_refresh(&mut self, routing_node: &RoutingNode, data_name: &XorName, entry: &Entry) {
+        let src = Authority::NaeManager(data_name.clone());
+        let refresh = Refresh::new(PERSONA_NAME, data_name, entry);
+        if let Ok(serialised_refresh) = refresh.and_then(|refresh| {
+            serialisation::serialise(&refresh)
+        }) {
+            debug!("SD Manager sending refresh for data {:?}", src.name());
+            let _ = routing_node.send_refresh_request(src, serialised_refresh);
+            self.refreshes_sent += 1;
+        }
+    }
+
+    fn update(&mut self, new_data: &StructuredData) -> Result<(), ClientError> {
+        let mut existing_data = try!(self.get_stored(&new_data.name()));
+        debug!("StructuredDataManager updating {:?} to {:?}",
+               existing_data,
+               new_data);
+        try!(check_version(&existing_data, new_data));
+        let previous_data = existing_data.clone();
+        try!(existing_data.replace_with_other(new_data.clone()).map_err(successor_error));
+        try!(self.store(&existing_data));
+        self.add_to_history(previous_data);
+        Ok(())
+    }
+
+    fn delete(&mut self, data: &StructuredData) -> Result<(), ClientError> {
+        let data_name = data.name();
+        let existing_data = try!(self.get_stored(&data_name));
+        debug!("StructuredDataManager deleting {:?} with requested new version {:?}",
+               existing_data,
+               data);
+        try!(check_version(&existing_data, data));
+        try!(existing_data.validate_self_against_successor(data).map_err(successor_error));
+        // The tombstone prevents later Puts bearing the same name
+        if let Err(error) = self.chunk_store.delete(&data_name) {
+            error!("Failed to delete SD {:?}: {:?}", data_name, error);
+            return Err(ClientError::StoreError);
+        }
+        if let Err(error) = self.remove_history(&data_name) {
+            error!("Failed to remove history of {:?}: {:?}", data_name, error);
+        }
+        let tombstone = Tombstone {
+            version: data.get_version(),
+            owner_keys: data.get_owner_keys().clone(),
+            deleted_at_ms: clock::wall_time_ms(),
+        };
+        let _ = self.tombstones.insert(data_name, tombstone);
+        Ok(())
+    }
+
+    fn get_stored(&self, data_name: &XorName) -> Result<StructuredData, ClientError> {
+        let serialised_data = match self.chunk_store.get(data_name) {
+            Ok(serialised_data) => serialised_data,
+            Err(_) => return Err(self.missing_data_error(data_name)),
+        };
+        serialisation::deserialise(&serialised_data).map_err(|error| {
+            error!("Failed to parse SD {:?}: {:?}", data_name, error);
+            ClientError::StoreError
+        })
+    }
+
+    fn store(&mut self, data: &StructuredData) -> Result<(), ClientError> {
+        let data_name = data.name();
+        // chunk_store::put() deletes the old data automatically
+        match serialisation::serialise(data) {
+            Ok(serialised_data) => {
+                self.chunk_store.put(&data_name, &serialised_data).map_err(|error| {
+                    error!("Failed to store SD {:?}: {:?}", data_name, error);
+                    ClientError::StoreError
+                })
             }
+            Err(error) => {
+                error!("Failed to serialise SD {:?}: {:?}", data_name, error);
+                Err(ClientError::StoreError)
+            }
+        }
+    }
+
+    fn missing_data_error(&self, data_name: &XorName) -> ClientError {
+        if self.tombstones.contains_key(data_name) {
+            ClientError::DataDeleted
+        } else {
+            ClientError::NoSuchData
         }
     }
+
+    fn history(&self, data_name: &XorName) -> Vec<StructuredData> {
+        self.history_store
+            .get(data_name)
+            .ok()
+            .and_then(|data| serialisation::deserialise(&data).ok())
+            .unwrap_or_else(Vec::new)
+    }
+
+    // Stores `history` after dropping its oldest versions beyond the number kept for `type_tag`.
+    fn put_history(&mut self,
+                   data_name: &XorName,
+                   type_tag: u64,
+                   mut history: Vec<StructuredData>)
+                   -> Result<(), InternalError> {
+        let kept = self.config.structured_data_history(type_tag) as usize;
+        if history.len() > kept {
+            let excess = history.len() - kept;
+            let _ = history.drain(..excess);
+        }
+        if history.is_empty() {
+            return self.remove_history(data_name);
+        }
+        Ok(try!(self.history_store.put(data_name, &try!(serialisation::serialise(&history)))))
+    }
+
+    fn remove_history(&mut self, data_name: &XorName) -> Result<(), InternalError> {
+        if self.history_store.has_chunk(data_name) {
+            try!(self.history_store.delete(data_name));
+        }
+        Ok(())
+    }
+
+    fn add_to_history(&mut self, previous_data: StructuredData) {
+        let data_name = previous_data.name();
+        let type_tag = previous_data.get_type_tag();
+        let mut history = self.history(&data_name);
+        history.push(previous_data);
+        if let Err(error) = self.put_history(&data_name, type_tag, history) {
+            error!("Failed to store history of {:?}: {:?}", data_name, error);
+        }
+    }
+
+    // Adds any versions received in a refresh which we don't already hold.
+    fn merge_history(&mut self,
+                     data_name: &XorName,
+                     type_tag: u64,
+                     received: Vec<StructuredData>)
+                     -> Result<(), InternalError> {
+        if received.is_empty() {
+            return Ok(());
+        }
+        let mut history = self.history(data_name);
+        for data in received {
+            if !history.iter().any(|kept| kept.get_version() == data.get_version()) {
+                history.push(data);
+            }
+        }
+        history.sort_by(|lhs, rhs| lhs.get_version().cmp(&rhs.get_version()));
+        self.put_history(data_name, type_tag, history)
+    }
+}
+
+// Updates are conditional on the stored version being the one which the new version succeeds.
+fn check_version(existing_data: &StructuredData,
+                 new_data: &StructuredData)
+                 -> Result<(), ClientError> {
+    let current_version = existing_data.get_version();
+    if new_data.get_version() != current_version.wrapping_add(1) {
+        debug!("SD {:?} is at version {}, not the {} expected by the update",
+               existing_data.name(),
+               current_version,
+               new_data.get_version().wrapping_sub(1));
+        return Err(ClientError::VersionConflict(current_version));
+    }
+    Ok(())
+}
+
+// Maps a failed check of a new version against the stored one to the error sent to the client.
+fn successor_error(error: RoutingError) -> ClientError {
+    match error {
+        RoutingError::FailedSignature |
+        RoutingError::NotEnoughSignatures => ClientError::BadSignature,
+        _ => ClientError::InvalidSuccessor,
+    }
+}
+
+impl Persona for StructuredDataManager {
+    fn name(&self) -> &'static str {
+        PERSONA_NAME
+    }
+
+    fn accepts_request(&self, src: &Authority, dst: &Authority, content: &RequestContent) -> bool {
+        match (src, dst, content) {
+            (&Authority::Client{ .. },
+             &Authority::NaeManager(_),
+             &RequestContent::Get(DataRequest::Structured(_, _), _)) |
+            (&Authority::Client{ .. },
+             &Authority::NaeManager(_),
+             &RequestContent::Get(DataRequest::Plain(_), _)) |
+            (&Authority::ClientManager(_),
+             &Authority::NaeManager(_),
+             &RequestContent::Put(Data::Structured(_), _)) |
+            (&Authority::ClientManager(_),
+             &Authority::NaeManager(_),
+             &RequestContent::Post(Data::Structured(_), _)) |
+            (&Authority::ClientManager(_),
+             &Authority::NaeManager(_),
+             &RequestContent::Delete(Data::Structured(_), _)) => true,
+            _ => false,
+        }
+    }
+
+    fn on_request(&mut self,
+                  routing_node: &RoutingNode,
+                  request: &RequestMessage)
+                  -> Result<(), InternalError> {
+        match request.content {
+            RequestContent::Get(DataRequest::Plain(_), _) => {
+                self.handle_get_version(routing_node, request)
+            }
+            RequestContent::Get(..) => self.handle_get(routing_node, request),
+            RequestContent::Put(..) => self.handle_put(routing_node, request),
+            RequestContent::Post(..) => self.handle_post(routing_node, request),
+            RequestContent::Delete(..) => self.handle_delete(routing_node, request),
+            _ => unreachable!("Error in vault demuxing"),
+        }
+    }
+
+    fn on_refresh(&mut self,
+                  _routing_node: &RoutingNode,
+                  src: &Authority,
+                  dst: &Authority,
+                  refresh: &Refresh)
+                  -> Result<(), InternalError> {
+        match (src, dst) {
+            (&Authority::NaeManager(_), &Authority::NaeManager(_)) => {
+                let entry = try!(refresh.value::<Entry>());
+                self.handle_refresh(&refresh.name, entry)
+            }
+            _ => Err(InternalError::UnknownRefreshType(src.clone(), dst.clone(), refresh.clone())),
+        }
+    }
+
+    fn on_node_added(&mut self, routing_node: &RoutingNode, _node_added: &XorName) {
+        self.handle_churn(routing_node)
+    }
+
+    fn on_node_lost(&mut self, routing_node: &RoutingNode, _node_lost: &XorName) {
+        self.handle_churn(routing_node)
+    }
+
+    // Tombstones are collected once the configured retention period has passed since the deletion.
+    fn on_tick(&mut self, _routing_node: &RoutingNode) {
+        let expiry = clock::wall_time_ms() - self.tombstone_retention.num_milliseconds();
+        let expired = self.tombstones
+                          .iter()
+                          .filter(|&(_, tombstone)| tombstone.deleted_at_ms <= expiry)
+                          .map(|(data_name, _)| *data_name)
+                          .collect::<Vec<_>>();
+        for data_name in expired {
+            debug!("Collecting tombstone of SD {:?}", data_name);
+            let _ = self.tombstones.remove(&data_name);
+        }
+    }
+
+    fn on_drain(&mut self, routing_node: &RoutingNode) {
+        self.handle_churn(routing_node)
+    }
+
+    fn stats(&self) -> Vec<(&'static str, u64)> {
+        vec![("chunks_stored", self.chunk_store.names().len() as u64),
+             ("tombstones", self.tombstones.iter().count() as u64),
+             ("refreshes_sent_total", self.refreshes_sent)]
+    }
+
+    fn chunk_store_usage(&self) -> Vec<(&'static str, u64, u64)> {
+        vec![(STRUCTURED_DATA_MANAGER,
+              self.chunk_store.used_space(),
+              self.chunk_store.max_space()),
+             (STRUCTURED_DATA_HISTORY,
+              self.history_store.used_space(),
+              self.history_store.max_space())]
+    }
 }
 
 
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use clock;
+    use error::{ClientError, InternalError};
+    use maidsafe_utilities::serialisation;
+    use personas::Persona;
+    use rand::random;
+    use routing::{Authority, Data, DataRequest, MessageId, RequestContent, RequestMessage,
+                  ResponseContent, StructuredData};
+    use sodiumoxide::crypto::sign;
+    use std::sync::mpsc;
+    use time::Duration;
+    use types::Refresh;
+    use utils::{self, generate_random_vec_u8};
+    use vault::RoutingNode;
+    use xor_name::XorName;
+
+    const TYPE_TAG: u64 = 100;
+
+    fn client(keys: &(sign::PublicKey, sign::SecretKey)) -> Authority {
+        Authority::Client {
+            client_key: keys.0,
+            peer_id: random(),
+            proxy_node_name: random(),
+        }
+    }
+
+    // Returns versions 0 to `count - 1` of a new StructuredData owned by `keys`.
+    fn structured_data_versions(keys: &(sign::PublicKey, sign::SecretKey),
+                                count: u64)
+                                -> Vec<StructuredData> {
+        let identifier = random::<XorName>();
+        (0..count)
+            .map(|version| {
+                unwrap_result!(StructuredData::new(TYPE_TAG,
+                                                   identifier,
+                                                   version,
+                                                   generate_random_vec_u8(100),
+                                                   vec![keys.0],
+                                                   vec![],
+                                                   Some(&keys.1)))
+            })
+            .collect()
+    }
+
+    fn get_refresh_entry(request: &RequestMessage) -> Entry {
+        match request.content {
+            RequestContent::Refresh(ref content) => {
+                let refresh: Refresh = unwrap_result!(serialisation::deserialise(content));
+                unwrap_result!(refresh.value())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn post_keeps_past_versions() {
+        let mut config = utils::test_config();
+        config.structured_data_history = Some(2);
+        let mut structured_data_manager = unwrap_result!(StructuredDataManager::new(&config));
+        let routing = unwrap_result!(RoutingNode::new(mpsc::channel().0));
+        let keys = sign::gen_keypair();
+        let client = client(&keys);
+        let versions = structured_data_versions(&keys, 4);
+        let data_name = versions[0].name();
+        let our_authority = Authority::NaeManager(data_name);
+
+        let put_request = RequestMessage {
+            src: Authority::ClientManager(random()),
+            dst: our_authority.clone(),
+            content: RequestContent::Put(Data::Structured(versions[0].clone()), MessageId::new()),
+        };
+        unwrap_result!(structured_data_manager.handle_put(&routing, &put_request));
+        for data in &versions[1..] {
+            let post_request = RequestMessage {
+                src: Authority::ClientManager(random()),
+                dst: our_authority.clone(),
+                content: RequestContent::Post(Data::Structured(data.clone()), MessageId::new()),
+            };
+            unwrap_result!(structured_data_manager.handle_post(&routing, &post_request));
+        }
+        assert_eq!(routing.post_successes_given().len(), 3);
+
+        // Only the two most recent past versions are kept, but the current one can be fetched too.
+        for version in 0..4 {
+            let get_request = RequestMessage {
+                src: client.clone(),
+                dst: our_authority.clone(),
+                content: RequestContent::Get(DataRequest::Plain(version_name(&data_name,
+                                                                             version)),
+                                             MessageId::new()),
+            };
+            unwrap_result!(structured_data_manager.handle_get_version(&routing, &get_request));
+        }
+        assert_eq!(routing.get_failures_given().len(), 1);
+        let get_successes = routing.get_successes_given();
+        assert_eq!(get_successes.len(), 3);
+        for (get_success, data) in get_successes.iter().zip(&versions[1..]) {
+            match get_success.content {
+                ResponseContent::GetSuccess(Data::Structured(ref got), _) => assert_eq!(got, data),
+                _ => unreachable!(),
+            }
+        }
+
+        // The kept versions are carried in the refresh.
+        structured_data_manager.handle_churn(&routing);
+        let refresh_requests = routing.refresh_requests_given();
+        assert_eq!(refresh_requests.len(), 1);
+        let entry = get_refresh_entry(&refresh_requests[0]);
+        match entry {
+            Entry::Live(ref refreshed) => {
+                assert_eq!(refreshed.current, versions[3]);
+                assert_eq!(refreshed.history, versions[1..3].to_vec());
+            }
+            Entry::Deleted(_) => unreachable!(),
+        }
+
+        let mut other_config = utils::test_config();
+        other_config.structured_data_history = Some(2);
+        let mut other_manager = unwrap_result!(StructuredDataManager::new(&other_config));
+        unwrap_result!(other_manager.handle_refresh(&data_name, entry));
+        assert_eq!(other_manager.history(&data_name), versions[1..3].to_vec());
+    }
+
+    #[test]
+    fn invalid_refresh_is_not_merged() {
+        let mut structured_data_manager =
+            unwrap_result!(StructuredDataManager::new(&utils::test_config()));
+        let routing = unwrap_result!(RoutingNode::new(mpsc::channel().0));
+        let keys = sign::gen_keypair();
+        let versions = structured_data_versions(&keys, 1);
+        let data_name = versions[0].name();
+        let put_request = RequestMessage {
+            src: Authority::ClientManager(random()),
+            dst: Authority::NaeManager(data_name),
+            content: RequestContent::Put(Data::Structured(versions[0].clone()), MessageId::new()),
+        };
+        unwrap_result!(structured_data_manager.handle_put(&routing, &put_request));
+
+        // A refresh claiming versions signed by someone other than the owner is ignored, history
+        // included.
+        let forger_keys = sign::gen_keypair();
+        let forged_versions = (0..2)
+                                  .map(|version| {
+                                      unwrap_result!(StructuredData::new(
+                                          TYPE_TAG,
+                                          *versions[0].get_identifier(),
+                                          version,
+                                          generate_random_vec_u8(100),
+                                          vec![forger_keys.0],
+                                          vec![],
+                                          Some(&forger_keys.1)))
+                                  })
+                                  .collect::<Vec<_>>();
+        let entry = Entry::Live(Versions {
+            current: forged_versions[1].clone(),
+            history: vec![forged_versions[0].clone()],
+        });
+        unwrap_result!(structured_data_manager.handle_refresh(&data_name, entry));
+        assert_eq!(unwrap_result!(structured_data_manager.get_stored(&data_name)),
+                   versions[0]);
+        assert!(structured_data_manager.history(&data_name).is_empty());
+    }
+
+    #[test]
+    fn delete_leaves_tombstone_until_collected() {
+        let mut config = utils::test_config();
+        config.structured_data_tombstone_retention_secs = Some(60);
+        let mut structured_data_manager = unwrap_result!(StructuredDataManager::new(&config));
+        let routing = unwrap_result!(RoutingNode::new(mpsc::channel().0));
+        let keys = sign::gen_keypair();
+        let client = client(&keys);
+        let versions = structured_data_versions(&keys, 2);
+        let data_name = versions[0].name();
+        let our_authority = Authority::NaeManager(data_name);
+
+        let put_request = RequestMessage {
+            src: Authority::ClientManager(random()),
+            dst: our_authority.clone(),
+            content: RequestContent::Put(Data::Structured(versions[0].clone()), MessageId::new()),
+        };
+        unwrap_result!(structured_data_manager.handle_put(&routing, &put_request));
+        let delete_request = RequestMessage {
+            src: Authority::ClientManager(random()),
+            dst: our_authority.clone(),
+            content: RequestContent::Delete(Data::Structured(versions[1].clone()),
+                                            MessageId::new()),
+        };
+        unwrap_result!(structured_data_manager.handle_delete(&routing, &delete_request));
+        assert_eq!(routing.delete_successes_given().len(), 1);
+
+        // Gets and Puts of the deleted data are refused.
+        let get_request = RequestMessage {
+            src: client.clone(),
+            dst: our_authority.clone(),
+            content: RequestContent::Get(DataRequest::Structured(*versions[0].get_identifier(),
+                                                                 TYPE_TAG),
+                                         MessageId::new()),
+        };
+        unwrap_result!(structured_data_manager.handle_get(&routing, &get_request));
+        let get_failures = routing.get_failures_given();
+        assert_eq!(get_failures.len(), 1);
+        match get_failures[0].content {
+            ResponseContent::GetFailure { ref external_error_indicator, .. } => {
+                match serialisation::deserialise::<ClientError>(external_error_indicator) {
+                    Ok(ClientError::DataDeleted) => (),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+        match structured_data_manager.handle_put(&routing, &put_request) {
+            Err(InternalError::Client(ClientError::DataDeleted)) => (),
+            _ => unreachable!(),
+        }
+
+        // The tombstone is refreshed, and replaces the data held by other managers.
+        structured_data_manager.handle_churn(&routing);
+        let refresh_requests = routing.refresh_requests_given();
+        assert_eq!(refresh_requests.len(), 1);
+        let entry = get_refresh_entry(&refresh_requests[0]);
+        match entry {
+            Entry::Deleted(ref tombstone) => assert_eq!(tombstone.version, 1),
+            Entry::Live(_) => unreachable!(),
+        }
+        clock::advance(Duration::seconds(59));
+        let mut other_manager = unwrap_result!(StructuredDataManager::new(&utils::test_config()));
+        unwrap_result!(other_manager.handle_put(&routing, &put_request));
+        unwrap_result!(other_manager.handle_refresh(&data_name, entry));
+        assert!(!other_manager.chunk_store.has_chunk(&data_name));
+        assert!(other_manager.tombstones.contains_key(&data_name));
+
+        // Once the retention period has passed since the deletion, the tombstone is collected and
+        // the name can be reused, even where it was refreshed or reloaded late on.
+        drop(structured_data_manager);
+        let mut structured_data_manager = unwrap_result!(StructuredDataManager::new(&config));
+        structured_data_manager.on_tick(&routing);
+        other_manager.on_tick(&routing);
+        assert!(structured_data_manager.tombstones.contains_key(&data_name));
+        assert!(other_manager.tombstones.contains_key(&data_name));
+        clock::advance(Duration::seconds(2));
+        structured_data_manager.on_tick(&routing);
+        other_manager.on_tick(&routing);
+        assert!(!structured_data_manager.tombstones.contains_key(&data_name));
+        assert!(!other_manager.tombstones.contains_key(&data_name));
+        unwrap_result!(structured_data_manager.handle_put(&routing, &put_request));
+    }
+
+    #[test]
+    fn post_of_stale_version_conflicts() {
+        let mut structured_data_manager =
+            unwrap_result!(StructuredDataManager::new(&utils::test_config()));
+        let routing = unwrap_result!(RoutingNode::new(mpsc::channel().0));
+        let keys = sign::gen_keypair();
+        let versions = structured_data_versions(&keys, 2);
+        let our_authority = Authority::NaeManager(versions[0].name());
+        let request = |content| {
+            RequestMessage {
+                src: Authority::ClientManager(random()),
+                dst: our_authority.clone(),
+                content: content,
+            }
+        };
+
+        let put_request = request(RequestContent::Put(Data::Structured(versions[0].clone()),
+                                                      MessageId::new()));
+        unwrap_result!(structured_data_manager.handle_put(&routing, &put_request));
+        let post_request = request(RequestContent::Post(Data::Structured(versions[1].clone()),
+                                                        MessageId::new()));
+        unwrap_result!(structured_data_manager.handle_post(&routing, &post_request));
+
+        // Another client also tries to update version 0, but loses the race.
+        let racing_data = unwrap_result!(StructuredData::new(TYPE_TAG,
+                                                             *versions[1].get_identifier(),
+                                                             1,
+                                                             generate_random_vec_u8(100),
+                                                             vec![keys.0],
+                                                             vec![],
+                                                             Some(&keys.1)));
+        let post_request = request(RequestContent::Post(Data::Structured(racing_data),
+                                                        MessageId::new()));
+        match structured_data_manager.handle_post(&routing, &post_request) {
+            Err(InternalError::Client(ClientError::VersionConflict(1))) => (),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        let post_failures = routing.post_failures_given();
+        assert_eq!(post_failures.len(), 1);
+        match post_failures[0].content {
+            ResponseContent::PostFailure { ref external_error_indicator, .. } => {
+                match unwrap_result!(serialisation::deserialise(external_error_indicator)) {
+                    ClientError::VersionConflict(1) => (),
+                    error => panic!("Unexpected error {:?}", error),
+                }
+            }
+            _ => unreachable!(),
+        }
+        assert_eq!(unwrap_result!(structured_data_manager.get_stored(&versions[1].name())),
+                   versions[1]);
+    }
+}
+
 // #[cfg(all(test, feature = "use-mock-routing"))]
 // mod test {
 // use super::*;
@@ -0,0 +1,83 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! The source of randomness used by the personas, for message IDs, audit nonces and the choice of
+//! chunks to audit.
+//!
+//! Normally this is the thread's RNG, until it's seeded, after which it's a per-thread seeded RNG
+//! so that the personas' choices can be repeated.  A mock network seeds it with the network's
+//! seed, and a recorded journal holds the seed used while recording so that replaying makes the
+//! same choices.
+
+use maidsafe_utilities::serialisation::{deserialise, serialise};
+use rand::{self, Rand, Rng, SeedableRng, XorShiftRng};
+use routing::MessageId;
+use std::cell::RefCell;
+use xor_name::XorName;
+
+thread_local!(static SEEDED_RNG: RefCell<Option<XorShiftRng>> = RefCell::new(None));
+
+/// Seeds this thread's RNG, so that the values it produces from now on are determined by `seed`.
+pub fn seed(seed: [u32; 4]) {
+    SEEDED_RNG.with(|seeded_rng| *seeded_rng.borrow_mut() = Some(XorShiftRng::from_seed(seed)));
+}
+
+pub fn gen<T: Rand>() -> T {
+    SEEDED_RNG.with(|seeded_rng| {
+        match *seeded_rng.borrow_mut() {
+            Some(ref mut rng) => rng.gen(),
+            None => rand::random(),
+        }
+    })
+}
+
+/// Randomly chooses `amount` of the elements of `iterable`, or all of them if there are fewer.
+pub fn sample<T, I: IntoIterator<Item = T>>(iterable: I, amount: usize) -> Vec<T> {
+    SEEDED_RNG.with(|seeded_rng| {
+        match *seeded_rng.borrow_mut() {
+            Some(ref mut rng) => rand::sample(rng, iterable, amount),
+            None => rand::sample(&mut rand::thread_rng(), iterable, amount),
+        }
+    })
+}
+
+/// Returns a new random message ID.
+pub fn new_message_id() -> MessageId {
+    // A `MessageId` is a random name, but can only be created from one by decoding it.
+    let name = gen::<XorName>();
+    serialise(&name)
+        .and_then(|serialised_name| deserialise(&serialised_name))
+        .unwrap_or_else(|_| MessageId::new())
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use rand::random;
+
+    #[test]
+    fn seeded_values_repeat() {
+        let rng_seed = [random(), random(), random(), random()];
+        let mut values = Vec::new();
+        for _ in 0..2 {
+            seed(rng_seed);
+            values.push((new_message_id(), gen::<u64>(), sample(0..100, 10)));
+        }
+        assert_eq!(values[0], values[1]);
+        assert!(new_message_id() != values[0].0);
+    }
+}
@@ -0,0 +1,340 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Persistent persona state.
+//!
+//! The state is held in memory as a map, and is backed on disk by a snapshot of the whole map
+//! plus an append-only journal of every change made since that snapshot was taken.  Each journal
+//! record is a four-byte big-endian length followed by the serialised entry.
+
+use config_handler::Config;
+use error::InternalError;
+use maidsafe_utilities::serialisation::{deserialise, serialise};
+use rustc_serialize::{Decodable, Encodable};
+use std::collections::HashMap;
+use std::collections::hash_map::Iter;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use utils;
+use xor_name::XorName;
+
+const STATE_DIR: &'static str = "state";
+// Number of journal records after which the journal is folded into a new snapshot.
+const MAX_JOURNAL_RECORDS: usize = 1000;
+
+#[derive(RustcEncodable, RustcDecodable)]
+enum JournalRecord<V> {
+    Insert(XorName, V),
+    Remove(XorName),
+}
+
+pub struct StateStore<V> {
+    entries: HashMap<XorName, V>,
+    snapshot_path: PathBuf,
+    journal_path: PathBuf,
+    journal: File,
+    journal_records: usize,
+}
+
+impl<V: Encodable + Decodable> StateStore<V> {
+    /// Loads the state called `name` from the configured root directory, or creates empty state
+    /// if there is none on disk yet.
+    pub fn open(config: &Config, name: &str) -> Result<StateStore<V>, InternalError> {
+        let dir = config.root_dir().join(STATE_DIR);
+        try!(fs::create_dir_all(&dir));
+        let snapshot_path = dir.join(format!("{}.snapshot", name));
+        let journal_path = dir.join(format!("{}.journal", name));
+
+        let mut entries = if snapshot_path.exists() {
+            try!(deserialise::<HashMap<XorName, V>>(&try!(read_file(&snapshot_path))))
+        } else {
+            HashMap::new()
+        };
+        if journal_path.exists() {
+            let journal = try!(read_file(&journal_path));
+            let length = replay(&mut entries, &journal);
+            if length != journal.len() {
+                // Drop the torn or corrupt tail so it can never be followed by new records.
+                warn!("Truncating {} from {} to {} bytes at its last complete record",
+                      journal_path.display(),
+                      journal.len(),
+                      length);
+                try!(try!(OpenOptions::new().write(true).open(&journal_path))
+                         .set_len(length as u64));
+            }
+        }
+        if !entries.is_empty() {
+            info!("Loaded {} {} entries from {}",
+                  entries.len(),
+                  name,
+                  dir.display());
+        }
+
+        // Fold anything replayed from the journal into a new snapshot and start a fresh journal.
+        try!(write_snapshot(&snapshot_path, &entries));
+        let journal = try!(File::create(&journal_path));
+
+        Ok(StateStore {
+            entries: entries,
+            snapshot_path: snapshot_path,
+            journal_path: journal_path,
+            journal: journal,
+            journal_records: 0,
+        })
+    }
+
+    pub fn get(&self, name: &XorName) -> Option<&V> {
+        self.entries.get(name)
+    }
+
+    pub fn contains_key(&self, name: &XorName) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    pub fn iter(&self) -> Iter<XorName, V> {
+        self.entries.iter()
+    }
+
+    pub fn insert(&mut self, name: XorName, value: V) -> Option<V> {
+        self.append(&JournalRecord::Insert(name, &value));
+        self.entries.insert(name, value)
+    }
+
+    pub fn remove(&mut self, name: &XorName) -> Option<V> {
+        let removed = self.entries.remove(name);
+        if removed.is_some() {
+            let record: JournalRecord<&V> = JournalRecord::Remove(*name);
+            self.append(&record);
+        }
+        removed
+    }
+
+    /// Applies `f` to the entry for `name` if it exists, recording the updated entry unless `f`
+    /// left it unchanged.
+    pub fn update<F, R>(&mut self, name: &XorName, f: F) -> Option<R>
+        where F: FnOnce(&mut V) -> R
+    {
+        self.apply(name, f, false)
+    }
+
+    /// Removes every entry from memory only.  The entries stay on disk until the store is next
+    /// compacted, so reopening the store before then restores them.
+    pub fn clear_in_memory(&mut self) {
+        self.entries.clear();
+    }
+
+    // Applies `f` to the entry for `name`, recording the result if it changed the entry or if
+    // `force` is set.
+    fn apply<F, R>(&mut self, name: &XorName, f: F, force: bool) -> Option<R>
+        where F: FnOnce(&mut V) -> R
+    {
+        let (result, record) = match self.entries.get_mut(name) {
+            Some(value) => {
+                let before = serialise(&*value).ok();
+                let result = f(value);
+                if !force && before.is_some() && before == serialise(&*value).ok() {
+                    return Some(result);
+                }
+                (result, serialise(&JournalRecord::Insert(*name, &*value)))
+            }
+            None => return None,
+        };
+        self.append_serialised(record);
+        Some(result)
+    }
+
+    fn append<T: Encodable>(&mut self, record: &JournalRecord<T>) {
+        let serialised_record = serialise(record);
+        self.append_serialised(serialised_record)
+    }
+
+    fn append_serialised<E>(&mut self, serialised_record: Result<Vec<u8>, E>) {
+        let serialised_record = match serialised_record {
+            Ok(serialised_record) => serialised_record,
+            Err(_) => {
+                error!("Failed to serialise journal record for {}",
+                       self.journal_path.display());
+                return;
+            }
+        };
+        if self.journal_records >= MAX_JOURNAL_RECORDS {
+            if let Err(error) = self.compact() {
+                error!("Failed to compact {}: {:?}", self.journal_path.display(), error);
+            }
+        }
+        if let Err(error) = utils::write_record(&mut self.journal, &serialised_record)
+                                .and_then(|()| self.journal.sync_data()) {
+            error!("Failed to write to {}: {:?}", self.journal_path.display(), error);
+            return;
+        }
+        self.journal_records += 1;
+    }
+
+    fn compact(&mut self) -> Result<(), InternalError> {
+        try!(write_snapshot(&self.snapshot_path, &self.entries));
+        self.journal = try!(OpenOptions::new()
+                                .write(true)
+                                .truncate(true)
+                                .create(true)
+                                .open(&self.journal_path));
+        self.journal_records = 0;
+        Ok(())
+    }
+}
+
+impl<V: Default + Encodable + Decodable> StateStore<V> {
+    /// As `update`, but inserts a default entry first if none exists for `name`.
+    pub fn update_or_default<F, R>(&mut self, name: XorName, f: F) -> R
+        where F: FnOnce(&mut V) -> R
+    {
+        // A new entry is recorded even if `f` leaves it at its default value.
+        let inserted = !self.entries.contains_key(&name);
+        if inserted {
+            let _ = self.entries.insert(name, V::default());
+        }
+        unwrap_option!(self.apply(&name, f, inserted),
+                       "Entry has just been inserted")
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, InternalError> {
+    let mut contents = Vec::new();
+    let _ = try!(try!(File::open(path)).read_to_end(&mut contents));
+    Ok(contents)
+}
+
+// The snapshot is written to a temporary file which then replaces the old snapshot, so a crash
+// part way through never leaves a truncated snapshot behind.
+fn write_snapshot<V: Encodable>(path: &Path,
+                                entries: &HashMap<XorName, V>)
+                                -> Result<(), InternalError> {
+    let temp_path = path.with_extension("snapshot.tmp");
+    {
+        let mut file = try!(File::create(&temp_path));
+        try!(file.write_all(&try!(serialise(entries))));
+        try!(file.sync_all());
+    }
+    Ok(try!(fs::rename(&temp_path, path)))
+}
+
+// Applies all complete journal records to `entries`, returning the length of the journal up to
+// the end of the last one applied.  A torn record at the end of the journal (e.g. from a crash
+// mid-write) is ignored.
+fn replay<V: Decodable>(entries: &mut HashMap<XorName, V>, journal: &[u8]) -> usize {
+    let mut applied = 0;
+    for record in utils::read_records(journal).0 {
+        match deserialise::<JournalRecord<V>>(record) {
+            Ok(JournalRecord::Insert(name, value)) => {
+                let _ = entries.insert(name, value);
+            }
+            Ok(JournalRecord::Remove(name)) => {
+                let _ = entries.remove(&name);
+            }
+            Err(error) => {
+                warn!("Stopped replaying journal at corrupt record: {:?}", error);
+                break;
+            }
+        }
+        applied += 4 + record.len();
+    }
+    applied
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+mod test {
+    use super::*;
+    use rand::random;
+    use std::io::Write;
+    use utils;
+    use xor_name::XorName;
+
+    #[test]
+    fn reopen_restores_state() {
+        let config = utils::test_config();
+        let (name_0, name_1, name_2) = (random::<XorName>(), random::<XorName>(), random());
+        {
+            let mut store = unwrap_result!(StateStore::<u64>::open(&config, "Test"));
+            assert!(store.insert(name_0, 0).is_none());
+            assert!(store.insert(name_1, 1).is_none());
+            assert!(store.insert(name_2, 2).is_none());
+            assert_eq!(store.update(&name_1, |value| *value += 10), Some(()));
+            assert_eq!(store.remove(&name_2), Some(2));
+            assert_eq!(store.update_or_default(name_2, |value| *value), 0);
+        }
+
+        let store = unwrap_result!(StateStore::<u64>::open(&config, "Test"));
+        assert_eq!(store.iter().count(), 3);
+        assert_eq!(store.get(&name_0), Some(&0));
+        assert_eq!(store.get(&name_1), Some(&11));
+        assert_eq!(store.get(&name_2), Some(&0));
+    }
+
+    #[test]
+    fn torn_journal_record_is_ignored() {
+        let config = utils::test_config();
+        let name = random::<XorName>();
+        {
+            let mut store = unwrap_result!(StateStore::<u64>::open(&config, "Test"));
+            let _ = store.insert(name, 1);
+            // Simulate a crash part way through writing the next record.
+            unwrap_result!(store.journal.write_all(&[0, 0, 1]));
+        }
+
+        let store = unwrap_result!(StateStore::<u64>::open(&config, "Test"));
+        assert_eq!(store.iter().count(), 1);
+        assert_eq!(store.get(&name), Some(&1));
+    }
+
+    #[test]
+    fn unchanged_update_is_not_journaled() {
+        let config = utils::test_config();
+        let (name_0, name_1) = (random::<XorName>(), random::<XorName>());
+        {
+            let mut store = unwrap_result!(StateStore::<u64>::open(&config, "Test"));
+            let _ = store.insert(name_0, 1);
+            assert_eq!(store.journal_records, 1);
+            assert_eq!(store.update(&name_0, |value| *value), Some(1));
+            assert_eq!(store.journal_records, 1);
+            // A new default entry is still recorded.
+            assert_eq!(store.update_or_default(name_1, |value| *value), 0);
+            assert_eq!(store.journal_records, 2);
+        }
+
+        let store = unwrap_result!(StateStore::<u64>::open(&config, "Test"));
+        assert_eq!(store.get(&name_0), Some(&1));
+        assert_eq!(store.get(&name_1), Some(&0));
+    }
+
+    #[test]
+    fn clear_in_memory_keeps_persisted_entries() {
+        let config = utils::test_config();
+        let (name_0, name_1) = (random::<XorName>(), random::<XorName>());
+        {
+            let mut store = unwrap_result!(StateStore::<u64>::open(&config, "Test"));
+            let _ = store.insert(name_0, 0);
+            store.clear_in_memory();
+            assert_eq!(store.iter().count(), 0);
+            let _ = store.insert(name_1, 1);
+        }
+
+        let store = unwrap_result!(StateStore::<u64>::open(&config, "Test"));
+        assert_eq!(store.iter().count(), 2);
+        assert_eq!(store.get(&name_0), Some(&0));
+        assert_eq!(store.get(&name_1), Some(&1));
+    }
+}
@@ -15,31 +15,60 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-use personas::{immutable_data_manager, maid_manager, pmid_manager, mpid_manager};
-use routing::{PlainData, StructuredData};
+use maidsafe_utilities::serialisation::{self, SerialisationError};
+use rustc_serialize::{Decodable, Encodable};
 use xor_name::XorName;
 
+/// An account transfer between members of a close group.  The `value` is opaque here: it is the
+/// serialised refresh value of the persona named by `persona`.
 #[derive(Debug, Clone, Eq, PartialEq, RustcEncodable, RustcDecodable)]
 pub struct Refresh {
+    pub persona: String,
     pub name: XorName,
-    pub value: RefreshValue,
+    pub value: Vec<u8>,
 }
 
 impl Refresh {
-    pub fn new(name: &XorName, value: RefreshValue) -> Refresh {
-        Refresh {
+    pub fn new<T: Encodable>(persona: &str,
+                             name: &XorName,
+                             value: &T)
+                             -> Result<Refresh, SerialisationError> {
+        Ok(Refresh {
+            persona: persona.to_owned(),
             name: name.clone(),
-            value: value,
-        }
+            value: try!(serialisation::serialise(value)),
+        })
     }
+
+    pub fn value<T: Decodable>(&self) -> Result<T, SerialisationError> {
+        serialisation::deserialise(&self.value)
+    }
+}
+
+/// Notification from a PmidNode that it no longer holds the chunk `data_name` (of `size` bytes),
+/// e.g. because it was evicted to make room for another chunk or found to be corrupt.  It is sent
+/// to the PmidNode's managers, who pass it on to the chunk's managers.
+///
+/// If `handing_off` is set, the PmidNode still holds the chunk but is about to leave the network,
+/// so the chunk's managers can still retrieve it from the PmidNode while replicating it.
+#[derive(Debug, Clone, Eq, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct DataLost {
+    pub data_name: XorName,
+    pub size: u64,
+    pub handing_off: bool,
 }
 
+/// A proof-of-storage audit of a chunk holder, sent between the chunk's ImmutableDataManagers and
+/// the holder's PmidNode.  The holder answers a `Challenge` with the hash of the nonce followed by
+/// the chunk, which can't be calculated without holding the whole chunk.  Answers are checked
+/// against those from the chunk's other holders.
 #[derive(Debug, Clone, Eq, PartialEq, RustcEncodable, RustcDecodable)]
-pub enum RefreshValue {
-    MaidManagerAccount(maid_manager::Account),
-    ImmutableDataManagerAccount(immutable_data_manager::Account),
-    StructuredDataManager(StructuredData),
-    PmidManagerAccount(pmid_manager::Account),
-    // mpid_manager: account, outbox messages, inbox headers
-    MpidManagerAccount(mpid_manager::Account, Vec<PlainData>, Vec<PlainData>),
+pub enum Audit {
+    Challenge {
+        nonce: Vec<u8>,
+    },
+    Response {
+        nonce: Vec<u8>,
+        digest: Vec<u8>,
+    },
 }
@@ -15,8 +15,11 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+#[cfg(all(test, feature = "use-mock-routing"))]
+use config_handler::Config;
 use routing::Authority;
 use sodiumoxide::crypto::hash::sha512;
+use std::io::{self, Write};
 use xor_name::XorName;
 
 pub fn client_name(authority: &Authority) -> XorName {
@@ -26,6 +29,70 @@ pub fn client_name(authority: &Authority) -> XorName {
     }
 }
 
+// Returns the median of `values`, or 0 if empty.  For an even number of values, the mean of the
+// middle two is returned.
+pub fn median(values: &[u64]) -> u64 {
+    if values.is_empty() {
+        return 0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    let middle = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[middle - 1] + sorted[middle]) / 2
+    } else {
+        sorted[middle]
+    }
+}
+
+// Returns the answer to a proof-of-storage audit challenge: the hash of `nonce` followed by the
+// chunk as held in the chunk store.
+pub fn audit_digest(nonce: &[u8], serialised_chunk: &[u8]) -> Vec<u8> {
+    let mut audited = nonce.to_vec();
+    audited.extend_from_slice(serialised_chunk);
+    sha512::hash(&audited).0.to_vec()
+}
+
+// Writes `record` preceded by its length as four big-endian bytes, as used by the on-disk journals.
+pub fn write_record<W: Write>(writer: &mut W, record: &[u8]) -> io::Result<()> {
+    let length = record.len() as u32;
+    let header = [(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8, length as u8];
+    writer.write_all(&header).and_then(|()| writer.write_all(record))
+}
+
+// Splits `data` into the records written to it by `write_record`, returning them along with the
+// length of `data` up to the end of the last complete one.  A torn record at the end (e.g. from a
+// crash mid-write) is left out.
+pub fn read_records(data: &[u8]) -> (Vec<&[u8]>, usize) {
+    let mut records = Vec::new();
+    let mut complete = 0;
+    while complete + 4 <= data.len() {
+        let length = ((data[complete] as usize) << 24) | ((data[complete + 1] as usize) << 16) |
+                     ((data[complete + 2] as usize) << 8) |
+                     data[complete + 3] as usize;
+        let start = complete + 4;
+        if start + length > data.len() {
+            break;
+        }
+        records.push(&data[start..start + length]);
+        complete = start + length;
+    }
+    (records, complete)
+}
+
+// Returns a default config, but with a unique random root directory so that concurrently-running
+// tests don't share chunk stores.
+#[cfg(all(test, feature = "use-mock-routing"))]
+pub fn test_config() -> Config {
+    use rand;
+    use std::env;
+    let root_dir = env::temp_dir().join(format!("safe_vault_test_{:016x}", rand::random::<u64>()));
+    Config {
+        root_dir: Some(root_dir.to_string_lossy().into_owned()),
+        ..Config::default()
+    }
+}
+
 #[cfg(all(test, feature = "use-mock-routing"))]
 pub fn generate_random_vec_u8(size: usize) -> Vec<u8> {
     use rand::{self, Rng};
@@ -15,122 +15,231 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+#[cfg(unix)]
+use admin::{self, AdminCommand, AdminResponse};
 use ctrlc::CtrlC;
-use maidsafe_utilities::serialisation;
-use routing::{Authority, Data, DataRequest, Event, RequestContent, RequestMessage,
-              ResponseContent, ResponseMessage, RoutingMessage};
+use routing::{Event, RequestMessage, ResponseMessage, RoutingError};
+#[cfg(unix)]
+use rustc_serialize::json::Json;
+use std::cmp::min;
+#[cfg(unix)]
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread;
+use std::time::Duration;
+use time::{self, SteadyTime};
 use xor_name::XorName;
 
+use clock;
+use config_handler::Config;
 use error::InternalError;
+use journal::{Journal, Record};
+#[cfg(unix)]
+use logger;
+use personas::Registry;
 use personas::immutable_data_manager::ImmutableDataManager;
 use personas::maid_manager::MaidManager;
 use personas::mpid_manager::MpidManager;
 use personas::pmid_manager::PmidManager;
 use personas::pmid_node::PmidNode;
 use personas::structured_data_manager::StructuredDataManager;
-use types::{Refresh, RefreshValue};
 
 #[cfg(not(all(test, feature = "use-mock-routing")))]
-pub type RoutingNode = ::routing::Node;
+pub type RoutingNode = ::journal::Node;
 
 #[cfg(all(test, feature = "use-mock-routing"))]
 pub type RoutingNode = ::mock_routing::MockRoutingNode;
 
+const INITIAL_RECONNECT_DELAY_MS: u64 = 500;
+const MAX_RECONNECT_DELAY_MS: u64 = 60_000;
+// How often a vault waiting to reconnect checks whether it has been stopped.
+const RECONNECT_POLL_INTERVAL_MS: u64 = 100;
+
+// Everything handled by the vault's event loop.
+enum VaultEvent {
+    Routing(Event),
+    // Sent periodically by a timer thread, so timeouts are enforced even when routing is quiet.
+    Tick,
+    // Hand off our state to the network, then stop.
+    Drain,
+    // A command from the admin socket, with the channel on which to send the result.
+    #[cfg(unix)]
+    Admin(AdminCommand, Sender<AdminResponse>),
+}
+
 #[allow(unused)]
 /// Main struct to hold all personas and Routing instance
 pub struct Vault {
-    immutable_data_manager: ImmutableDataManager,
-    maid_manager: MaidManager,
-    mpid_manager: MpidManager,
-    pmid_manager: PmidManager,
-    pmid_node: PmidNode,
-    structured_data_manager: StructuredDataManager,
+    personas: Registry,
     stop_receiver: Option<Receiver<()>>,
     app_event_sender: Option<Sender<Event>>,
+    tick_interval: Duration,
+    // Sender for the current event loop, if it's running.
+    event_sender: Arc<Mutex<Option<Sender<VaultEvent>>>>,
+    // Journal of the vault's inputs and outputs, if recording.
+    journal: Option<Arc<Mutex<Journal>>>,
+    // Used to stop the vault once it has drained.
+    stop_sender: Option<Sender<()>>,
+    drain_timeout: time::Duration,
+    // While draining, the time by which we leave the network even if not yet drained.
+    drain_deadline: Option<SteadyTime>,
 }
 
 impl Vault {
-    pub fn run() {
+    /// Runs the vault until it's stopped, recording to a journal at `record_path` if given.
+    pub fn run(config: Config, record_path: Option<&Path>) {
         let (stop_sender, stop_receiver) = mpsc::channel();
 
-        // Handle Ctrl+C to properly stop the vault instance.
+        // TODO - Keep retrying to construct new Vault until returns Ok() rather than using unwrap?
+        let mut vault = unwrap_result!(Vault::new(None, stop_receiver, config.clone()));
+        if let Some(record_path) = record_path {
+            let journal = unwrap_result!(Journal::create(record_path));
+            vault.record_to(Arc::new(Mutex::new(journal)));
+        }
+        vault.stop_sender = Some(stop_sender.clone());
+        vault.start_admin(&config, stop_sender.clone());
+
+        // Ctrl+C drains the vault before stopping it, unless it's not connected to the network,
+        // in which case it's stopped straight away.  A second Ctrl+C skips the rest of the drain.
         // TODO: this should probably be moved over to main.
+        let event_sender = vault.event_sender.clone();
         CtrlC::set_handler(move || {
-            let _ = stop_sender.send(());
+            if !send_event(&event_sender, VaultEvent::Drain) {
+                let _ = stop_sender.send(());
+            }
         });
 
-        // TODO - Keep retrying to construct new Vault until returns Ok() rather than using unwrap?
-        let _ = unwrap_result!(unwrap_result!(Vault::new(None, stop_receiver)).do_run());
+        let _ = unwrap_result!(vault.do_run());
     }
 
-    fn new(app_event_sender: Option<Sender<Event>>,
-           stop_receiver: Receiver<()>)
-           -> Result<Vault, InternalError> {
+    pub fn new(app_event_sender: Option<Sender<Event>>,
+               stop_receiver: Receiver<()>,
+               config: Config)
+               -> Result<Vault, InternalError> {
         ::sodiumoxide::init();
 
+        let mut personas = Registry::new();
+        personas.register(Box::new(try!(ImmutableDataManager::new(&config))));
+        personas.register(Box::new(try!(MaidManager::new(&config))));
+        personas.register(Box::new(try!(MpidManager::new(&config))));
+        personas.register(Box::new(try!(PmidManager::new(&config))));
+        personas.register(Box::new(try!(PmidNode::new(&config))));
+        personas.register(Box::new(try!(StructuredDataManager::new(&config))));
+
         Ok(Vault {
-            immutable_data_manager: ImmutableDataManager::new(),
-            maid_manager: MaidManager::new(),
-            mpid_manager: MpidManager::new(),
-            pmid_manager: PmidManager::new(),
-            pmid_node: try!(PmidNode::new()),
-            structured_data_manager: StructuredDataManager::new(),
+            personas: personas,
             stop_receiver: Some(stop_receiver),
             app_event_sender: app_event_sender,
+            tick_interval: Duration::from_millis(config.tick_interval_ms()),
+            event_sender: Arc::new(Mutex::new(None)),
+            journal: None,
+            stop_sender: None,
+            drain_timeout: time::Duration::seconds(config.drain_timeout_secs() as i64),
+            drain_deadline: None,
         })
     }
 
-    fn do_run(&mut self) -> Result<(), InternalError> {
-        let (routing_sender, routing_receiver) = mpsc::channel();
+    // Starts listening for operators' commands.  The vault can still run without the admin
+    // socket, so failing to start it isn't fatal.
+    #[cfg(unix)]
+    fn start_admin(&self, config: &Config, stop_sender: Sender<()>) {
+        let event_sender = self.event_sender.clone();
+        let stop_sender = Mutex::new(stop_sender);
+        let result = admin::start(&config.admin_socket_path(), move |command| {
+            match command {
+                AdminCommand::Shutdown => {
+                    let _ = stop_sender.lock().unwrap().send(());
+                    Ok(Json::Null)
+                }
+                AdminCommand::Drain => {
+                    if send_event(&event_sender, VaultEvent::Drain) {
+                        Ok(Json::Null)
+                    } else {
+                        Err("The vault isn't connected to the network".to_owned())
+                    }
+                }
+                AdminCommand::LogLevel(level) => {
+                    logger::set_level(level);
+                    info!("Log level changed to {}", level);
+                    Ok(Json::Null)
+                }
+                command => forward_admin_command(&event_sender, command),
+            }
+        });
+        if let Err(error) = result {
+            warn!("Failed to start admin socket: {:?}", error);
+        }
+    }
 
-        let routing_node = try!(RoutingNode::new(routing_sender));
-        let routing_node1 = Arc::new(Mutex::new(Some(routing_node)));
+    #[cfg(not(unix))]
+    fn start_admin(&self, _config: &Config, _stop_sender: Sender<()>) {}
+
+    fn do_run(&mut self) -> Result<(), InternalError> {
+        let routing_node1 = Arc::new(Mutex::new(None));
         let routing_node2 = routing_node1.clone();
+        let stopped1 = Arc::new(AtomicBool::new(false));
+        let stopped2 = stopped1.clone();
 
         // Take the stop_receiver from self, so we can move it into the stop thread.
         let stop_receiver = self.stop_receiver.take().unwrap();
 
-        // Listen for stop event and destroy the routing node if one is received.  Destroying it
-        // will close the routing event channel, stopping the main event loop.
+        // Listen for stop event and destroy the routing node if one is received.  The main event
+        // loop stops on the next event it receives after that.
         let stop_thread_handle = thread::spawn(move || {
             let _ = stop_receiver.recv();
+            stopped1.store(true, Ordering::SeqCst);
             let _ = routing_node1.lock().unwrap().take();
 
             stop_receiver
         });
 
-        for event in routing_receiver.iter() {
-            let routing_node = routing_node2.lock().unwrap();
+        // Each time routing disconnects, the routing node is discarded and a new one created, with
+        // an exponentially increasing delay between failed attempts.
+        let mut attempt = 0u32;
+        loop {
+            attempt += 1;
+            info!("Connecting to the network (attempt {})", attempt);
+            let (routing_sender, routing_receiver) = mpsc::channel();
+            match new_routing_node(routing_sender, self.journal.clone()) {
+                Ok(routing_node) => {
+                    let mut current = routing_node2.lock().unwrap();
+                    // Checked while holding the lock, so the stop thread can't miss the new node.
+                    if stopped2.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    *current = Some(routing_node);
+                }
+                Err(error) => warn!("Failed to create routing node: {:?}", error),
+            }
 
-            if routing_node.is_none() {
+            let event_receiver = self.start_event_threads(routing_receiver);
+            if self.run_event_loop(&routing_node2, event_receiver) {
+                attempt = 0;
+            }
+            let _ = self.event_sender.lock().unwrap().take();
+            let _ = routing_node2.lock().unwrap().take();
+            if self.drain_deadline.take().is_some() {
+                info!("Disconnected while draining, so not reconnecting");
+                self.stop();
+            }
+            if stopped2.load(Ordering::SeqCst) {
                 break;
             }
 
-            let routing_node = routing_node.as_ref().unwrap();
-
-            trace!("Vault {} received an event from routing: {:?}",
-                   unwrap_result!(routing_node.name()),
-                   event);
-
-            let _ = self.app_event_sender
-                        .clone()
-                        .and_then(|sender| Some(sender.send(event.clone())));
-
-            if let Err(error) = match event {
-                Event::Request(request) => self.on_request(routing_node, request),
-                Event::Response(response) => self.on_response(routing_node, response),
-                Event::NodeAdded(node_added) => self.on_node_added(routing_node, node_added),
-                Event::NodeLost(node_lost) => self.on_node_lost(routing_node, node_lost),
-                Event::Connected => self.on_connected(),
-                Event::Disconnected => self.on_disconnected(),
-            } {
-                warn!("Failed to handle event: {:?}", error);
+            let delay_ms = min(INITIAL_RECONNECT_DELAY_MS << min(attempt, 16),
+                               MAX_RECONNECT_DELAY_MS);
+            info!("Disconnected from the network, reconnecting in {} ms", delay_ms);
+            let mut waited_ms = 0;
+            while waited_ms < delay_ms && !stopped2.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(RECONNECT_POLL_INTERVAL_MS));
+                waited_ms += RECONNECT_POLL_INTERVAL_MS;
+            }
+            if stopped2.load(Ordering::SeqCst) {
+                break;
             }
-
-            self.pmid_manager.check_timeout(routing_node);
         }
 
         // Return the stop_receiver back to self, in case we want to call do_run again.
@@ -139,171 +248,224 @@ impl Vault {
         Ok(())
     }
 
-    fn on_request(&mut self,
-                  routing_node: &RoutingNode,
-                  request: RequestMessage)
-                  -> Result<(), InternalError> {
-        match (&request.src, &request.dst, &request.content) {
-            // ================== Get ==================
-            (&Authority::Client{ .. },
-             &Authority::NaeManager(_),
-             &RequestContent::Get(DataRequest::Immutable(_, _), _)) => {
-                self.immutable_data_manager.handle_get(routing_node, &request)
-            }
-            (&Authority::Client{ .. },
-             &Authority::NaeManager(_),
-             &RequestContent::Get(DataRequest::Structured(_, _), _)) => {
-                self.structured_data_manager.handle_get(routing_node, &request)
-            }
-            (&Authority::NaeManager(_),
-             &Authority::ManagedNode(_),
-             &RequestContent::Get(DataRequest::Immutable(_, _), _)) => {
-                self.pmid_node.handle_get(routing_node, &request)
-            }
-            // ================== Put ==================
-            (&Authority::Client{ .. },
-             &Authority::ClientManager(_),
-             &RequestContent::Put(Data::Immutable(_), _)) |
-            (&Authority::Client{ .. },
-             &Authority::ClientManager(_),
-             &RequestContent::Put(Data::Structured(_), _)) => {
-                self.maid_manager.handle_put(routing_node, &request)
-            }
-            (&Authority::Client{ .. },
-             &Authority::ClientManager(_),
-             &RequestContent::Put(Data::Plain(_), _)) |
-            (&Authority::ClientManager(_),
-             &Authority::ClientManager(_),
-             &RequestContent::Put(Data::Plain(_), _)) => {
-                self.mpid_manager.handle_put(routing_node, &request)
-            }
-            (&Authority::ClientManager(_),
-             &Authority::NaeManager(_),
-             &RequestContent::Put(Data::Immutable(_), _)) => {
-                self.immutable_data_manager.handle_put(routing_node, &request)
+    // Starts a thread forwarding routing's events to the returned receiver, and a timer thread
+    // sending it ticks.  Both threads exit once the receiver has been dropped and, for the
+    // forwarding thread, the routing node too.
+    fn start_event_threads(&self, routing_receiver: Receiver<Event>) -> Receiver<VaultEvent> {
+        let (event_sender, event_receiver) = mpsc::channel();
+        let tick_sender = event_sender.clone();
+        *self.event_sender.lock().unwrap() = Some(event_sender.clone());
+        let _ = thread::spawn(move || {
+            for event in routing_receiver.iter() {
+                if event_sender.send(VaultEvent::Routing(event)).is_err() {
+                    break;
+                }
             }
-            (&Authority::ClientManager(_),
-             &Authority::NaeManager(_),
-             &RequestContent::Put(Data::Structured(_), _)) => {
-                self.structured_data_manager.handle_put(routing_node, &request)
-            }
-            (&Authority::NaeManager(_),
-             &Authority::NodeManager(_),
-             &RequestContent::Put(Data::Immutable(_), _)) => {
-                self.pmid_manager.handle_put(routing_node, &request)
+        });
+        let tick_interval = self.tick_interval;
+        let _ = thread::spawn(move || {
+            while tick_sender.send(VaultEvent::Tick).is_ok() {
+                thread::sleep(tick_interval);
             }
-            (&Authority::NodeManager(_),
-             &Authority::ManagedNode(_),
-             &RequestContent::Put(Data::Immutable(_), _)) => {
-                self.pmid_node.handle_put(routing_node, &request)
+        });
+        event_receiver
+    }
+
+    // Handles events until the routing node is destroyed or disconnects.  Returns whether it
+    // connected to the network first.
+    fn run_event_loop(&mut self,
+                      routing_node: &Arc<Mutex<Option<RoutingNode>>>,
+                      event_receiver: Receiver<VaultEvent>)
+                      -> bool {
+        let mut connected = false;
+        for vault_event in event_receiver.iter() {
+            let routing_node = routing_node.lock().unwrap();
+
+            if routing_node.is_none() {
+                break;
             }
-            // ================== Post ==================
-            (&Authority::Client{ .. },
-             &Authority::NaeManager(_),
-             &RequestContent::Post(Data::Structured(_), _)) => {
-                self.structured_data_manager.handle_post(routing_node, &request)
+
+            let routing_node = routing_node.as_ref().unwrap();
+
+            let event = match vault_event {
+                VaultEvent::Routing(event) => event,
+                VaultEvent::Tick => {
+                    self.handle_tick(routing_node);
+                    self.check_drained();
+                    continue;
+                }
+                VaultEvent::Drain => {
+                    self.start_drain(routing_node);
+                    continue;
+                }
+                #[cfg(unix)]
+                VaultEvent::Admin(command, reply_sender) => {
+                    let _ = reply_sender.send(self.handle_admin_command(routing_node, command));
+                    continue;
+                }
+            };
+            let disconnected = match event {
+                Event::Connected => {
+                    connected = true;
+                    false
+                }
+                Event::Disconnected => true,
+                _ => false,
+            };
+            self.handle_event(routing_node, event);
+            if disconnected {
+                break;
             }
-            (&Authority::Client{ .. },
-             &Authority::ClientManager(_),
-             &RequestContent::Post(Data::Plain(_), _)) |
-            (&Authority::ClientManager(_),
-             &Authority::ClientManager(_),
-             &RequestContent::Post(Data::Plain(_), _)) => {
-                self.mpid_manager.handle_post(routing_node, &request)
+        }
+        connected
+    }
+
+    /// Records the vault's inputs to `journal` from now on.  The routing node recording the
+    /// vault's outputs must be given the same journal.
+    pub fn record_to(&mut self, journal: Arc<Mutex<Journal>>) {
+        self.journal = Some(journal);
+    }
+
+    /// Handles a single event from routing.
+    pub fn handle_event(&mut self, routing_node: &RoutingNode, event: Event) {
+        trace!("Vault {} received an event from routing: {:?}",
+               unwrap_result!(routing_node.name()),
+               event);
+
+        let _ = self.app_event_sender
+                    .clone()
+                    .and_then(|sender| Some(sender.send(event.clone())));
+        if let Some(ref journal) = self.journal {
+            unwrap_result!(journal.lock()).append(Record::from_event(&event));
+        }
+
+        if let Err(error) = match event {
+            Event::Request(request) => self.on_request(routing_node, request),
+            Event::Response(response) => self.on_response(routing_node, response),
+            Event::NodeAdded(node_added) => self.on_node_added(routing_node, node_added),
+            Event::NodeLost(node_lost) => self.on_node_lost(routing_node, node_lost),
+            Event::Connected => self.on_connected(),
+            Event::Disconnected => self.on_disconnected(),
+        } {
+            warn!("Failed to handle event: {:?}", error);
+        }
+    }
+
+    /// Gives the personas a chance to act on any timeouts which have elapsed.
+    pub fn handle_tick(&mut self, routing_node: &RoutingNode) {
+        if let Some(ref journal) = self.journal {
+            unwrap_result!(journal.lock()).append(Record::Tick);
+        }
+        self.personas.on_tick(routing_node);
+    }
+
+    // Pushes our state to the groups taking over from us.  We then keep running until the personas
+    // have finished handing off their state, or the drain times out.
+    fn start_drain(&mut self, routing_node: &RoutingNode) {
+        if self.drain_deadline.is_some() {
+            info!("Stopping without waiting for the drain to finish");
+            self.stop();
+            return;
+        }
+        info!("Draining before leaving the network");
+        self.personas.on_drain(routing_node);
+        self.drain_deadline = Some(clock::now() + self.drain_timeout);
+    }
+
+    fn check_drained(&mut self) {
+        let deadline = match self.drain_deadline {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        if self.personas.is_drained() {
+            info!("Drained, leaving the network");
+        } else if clock::now() >= deadline {
+            warn!("Timed out draining, leaving the network anyway");
+        } else {
+            return;
+        }
+        self.drain_deadline = None;
+        self.stop();
+    }
+
+    fn stop(&self) {
+        if let Some(ref stop_sender) = self.stop_sender {
+            let _ = stop_sender.send(());
+        }
+    }
+
+    #[cfg(unix)]
+    fn handle_admin_command(&self,
+                            routing_node: &RoutingNode,
+                            command: AdminCommand)
+                            -> AdminResponse {
+        let mut result = BTreeMap::new();
+        match command {
+            AdminCommand::Accounts => {
+                for persona in self.personas.iter() {
+                    let names = persona.account_names()
+                                       .iter()
+                                       .map(|name| Json::String(admin::name_to_hex(name)))
+                                       .collect();
+                    let _ = result.insert(persona.name().to_owned(), Json::Array(names));
+                }
             }
-            // ================== Delete ==================
-            (&Authority::Client{ .. },
-             &Authority::ClientManager(_),
-             &RequestContent::Delete(Data::Plain(_), _)) => {
-                self.mpid_manager.handle_delete(routing_node, &request)
+            AdminCommand::ChunkStores => {
+                for persona in self.personas.iter() {
+                    for (store_name, used_space, max_space) in persona.chunk_store_usage() {
+                        let mut usage = BTreeMap::new();
+                        let _ = usage.insert("used_space".to_owned(), Json::U64(used_space));
+                        let _ = usage.insert("max_space".to_owned(), Json::U64(max_space));
+                        let _ = result.insert(store_name.to_owned(), Json::Object(usage));
+                    }
+                }
             }
-            (&Authority::Client{ .. },
-             &Authority::NaeManager(_),
-             &RequestContent::Delete(Data::Structured(_), _)) => {
-                self.structured_data_manager.handle_delete(routing_node, &request)
+            AdminCommand::Ongoing => {
+                for persona in self.personas.iter() {
+                    let requests = persona.ongoing_requests()
+                                          .into_iter()
+                                          .map(Json::String)
+                                          .collect();
+                    let _ = result.insert(persona.name().to_owned(), Json::Array(requests));
+                }
             }
-            // ================== Refresh ==================
-            (src, dst, &RequestContent::Refresh(ref serialised_refresh)) => {
-                self.on_refresh(src, dst, serialised_refresh)
+            AdminCommand::Metrics => return Ok(Json::String(self.personas.metrics())),
+            AdminCommand::CloseGroup(name) => {
+                return match routing_node.close_group(name) {
+                    Ok(Some(group)) => {
+                        Ok(Json::Array(group.iter()
+                                            .map(|member| Json::String(admin::name_to_hex(member)))
+                                            .collect()))
+                    }
+                    Ok(None) => Err(format!("Not in the close group of {}", name)),
+                    Err(error) => Err(format!("Failed to get close group: {:?}", error)),
+                };
             }
-            // ================== Invalid Request ==================
-            _ => Err(InternalError::UnknownMessageType(RoutingMessage::Request(request.clone()))),
+            command => return Err(format!("{:?} should be handled by the admin thread", command)),
         }
+        Ok(Json::Object(result))
+    }
+
+    fn on_request(&mut self,
+                  routing_node: &RoutingNode,
+                  request: RequestMessage)
+                  -> Result<(), InternalError> {
+        self.personas.on_request(routing_node, &request)
     }
 
     fn on_response(&mut self,
                    routing_node: &RoutingNode,
                    response: ResponseMessage)
                    -> Result<(), InternalError> {
-        match (&response.src, &response.dst, &response.content) {
-            // ================== GetSuccess ==================
-            (&Authority::ManagedNode(_),
-             &Authority::NaeManager(_),
-             &ResponseContent::GetSuccess(Data::Immutable(_), _)) => {
-                self.immutable_data_manager.handle_get_success(routing_node, &response)
-            }
-            // ================== GetFailure ==================
-            (&Authority::ManagedNode(ref pmid_node),
-             &Authority::NaeManager(_),
-             &ResponseContent::GetFailure{ ref id, ref request, ref external_error_indicator }) => {
-                self.immutable_data_manager
-                    .handle_get_failure(routing_node,
-                                        pmid_node,
-                                        id,
-                                        request,
-                                        external_error_indicator)
-            }
-            // ================== PutSuccess ==================
-            (&Authority::NaeManager(_),
-             &Authority::ClientManager(_),
-             &ResponseContent::PutSuccess(_, ref message_id)) => {
-                self.maid_manager.handle_put_success(routing_node, message_id)
-            }
-            (&Authority::NodeManager(ref pmid_node),
-             &Authority::NaeManager(_),
-             &ResponseContent::PutSuccess(_, ref message_id)) => {
-                self.immutable_data_manager.handle_put_success(pmid_node, message_id)
-            }
-            (&Authority::ManagedNode(ref pmid_node),
-             &Authority::NodeManager(_),
-             &ResponseContent::PutSuccess(_, ref message_id)) => {
-                self.pmid_manager.handle_put_success(routing_node, pmid_node, message_id)
-            }
-            // ================== PutFailure ==================
-            (&Authority::NaeManager(_),
-             &Authority::ClientManager(_),
-             &ResponseContent::PutFailure{ ref id, ref external_error_indicator, .. }) => {
-                self.maid_manager.handle_put_failure(routing_node, id, external_error_indicator)
-            }
-            (&Authority::NodeManager(ref pmid_node),
-             &Authority::NaeManager(_),
-             &ResponseContent::PutFailure{ ref id, .. }) => {
-                self.immutable_data_manager.handle_put_failure(routing_node, pmid_node, id)
-            }
-            (&Authority::ManagedNode(_),
-             &Authority::NodeManager(_),
-             &ResponseContent::PutFailure{ ref request, .. }) => {
-                self.pmid_manager.handle_put_failure(routing_node, request)
-            }
-            (&Authority::ClientManager(_),
-             &Authority::ClientManager(_),
-             &ResponseContent::PutFailure{ ref request, .. }) => {
-                self.mpid_manager.handle_put_failure(routing_node, request)
-            }
-            // ================== Invalid Response ==================
-            _ => Err(InternalError::UnknownMessageType(RoutingMessage::Response(response.clone()))),
-        }
+        self.personas.on_response(routing_node, &response)
     }
 
     fn on_node_added(&mut self,
                      routing_node: &RoutingNode,
                      node_added: XorName)
                      -> Result<(), InternalError> {
-        self.maid_manager.handle_churn(routing_node);
-        self.immutable_data_manager.handle_node_added(routing_node, node_added);
-        self.structured_data_manager.handle_churn(routing_node);
-        self.pmid_manager.handle_churn(routing_node);
-        self.mpid_manager.handle_churn(routing_node);
+        self.personas.on_node_added(routing_node, &node_added);
+        self.log_stats();
         Ok(())
     }
 
@@ -311,14 +473,17 @@ impl Vault {
                     routing_node: &RoutingNode,
                     node_lost: XorName)
                     -> Result<(), InternalError> {
-        self.maid_manager.handle_churn(routing_node);
-        self.immutable_data_manager.handle_node_lost(routing_node, node_lost);
-        self.structured_data_manager.handle_churn(routing_node);
-        self.pmid_manager.handle_churn(routing_node);
-        self.mpid_manager.handle_churn(routing_node);
+        self.personas.on_node_lost(routing_node, &node_lost);
+        self.log_stats();
         Ok(())
     }
 
+    fn log_stats(&self) {
+        for (persona_name, stat_name, value) in self.personas.stats() {
+            info!("{} {}: {}", persona_name, stat_name, value);
+        }
+    }
+
     fn on_connected(&self) -> Result<(), InternalError> {
         // TODO: what is expected to be done here?
         debug!("Vault connected");
@@ -326,52 +491,51 @@ impl Vault {
         Ok(())
     }
 
-    fn on_disconnected(&self) -> Result<(), InternalError> {
-        // TODO: restart event loop with new routing object, discarding all current data
-        debug!("Vault disconnected");
+    fn on_disconnected(&mut self) -> Result<(), InternalError> {
+        warn!("Vault disconnected");
+        self.personas.on_disconnected();
         Ok(())
     }
+}
 
-    fn on_refresh(&mut self,
-                  src: &Authority,
-                  dst: &Authority,
-                  serialised_refresh: &Vec<u8>)
-                  -> Result<(), InternalError> {
-        let refresh = try!(serialisation::deserialise::<Refresh>(serialised_refresh));
-        match (src, dst, &refresh.value) {
-            (&Authority::ClientManager(_),
-             &Authority::ClientManager(_),
-             &RefreshValue::MaidManagerAccount(ref account)) => {
-                Ok(self.maid_manager.handle_refresh(refresh.name, account.clone()))
-            }
-            (&Authority::ClientManager(_),
-             &Authority::ClientManager(_),
-             &RefreshValue::MpidManagerAccount(ref account,
-                                               ref stored_messages,
-                                               ref received_headers)) => {
-                Ok(self.mpid_manager
-                       .handle_refresh(refresh.name, account, stored_messages, received_headers))
-            }
-            (&Authority::NaeManager(_),
-             &Authority::NaeManager(_),
-             &RefreshValue::ImmutableDataManagerAccount(ref account)) => {
-                Ok(self.immutable_data_manager.handle_refresh(refresh.name, account.clone()))
-            }
-            (&Authority::NaeManager(_),
-             &Authority::NaeManager(_),
-             &RefreshValue::StructuredDataManager(ref structured_data)) => {
-                self.structured_data_manager.handle_refresh(structured_data.clone())
-            }
-            (&Authority::NodeManager(_),
-             &Authority::NodeManager(_),
-             &RefreshValue::PmidManagerAccount(ref account)) => {
-                Ok(self.pmid_manager.handle_refresh(refresh.name, account.clone()))
-            }
-            _ => Err(InternalError::UnknownRefreshType(src.clone(), dst.clone(), refresh.clone())),
-        }
+
+// Passes `event` to the event loop, returning whether it's running.
+fn send_event(event_sender: &Arc<Mutex<Option<Sender<VaultEvent>>>>, event: VaultEvent) -> bool {
+    event_sender.lock()
+                .unwrap()
+                .as_ref()
+                .map_or(false, |sender| sender.send(event).is_ok())
+}
+
+// Passes `command` to the event loop and waits for the result.
+#[cfg(unix)]
+fn forward_admin_command(event_sender: &Arc<Mutex<Option<Sender<VaultEvent>>>>,
+                         command: AdminCommand)
+                         -> AdminResponse {
+    let (reply_sender, reply_receiver) = mpsc::channel();
+    if !send_event(event_sender, VaultEvent::Admin(command, reply_sender)) {
+        return Err("The vault isn't connected to the network".to_owned());
     }
+    reply_receiver.recv()
+                  .unwrap_or_else(|_| Err("The vault stopped before answering".to_owned()))
 }
 
+#[cfg(not(all(test, feature = "use-mock-routing")))]
+fn new_routing_node(event_sender: Sender<Event>,
+                    journal: Option<Arc<Mutex<Journal>>>)
+                    -> Result<RoutingNode, RoutingError> {
+    RoutingNode::new(event_sender, journal)
+}
+
+#[cfg(all(test, feature = "use-mock-routing"))]
+fn new_routing_node(event_sender: Sender<Event>,
+                    journal: Option<Arc<Mutex<Journal>>>)
+                    -> Result<RoutingNode, RoutingError> {
+    match journal {
+        Some(journal) => RoutingNode::with_journal(event_sender, journal),
+        None => RoutingNode::new(event_sender),
+    }
+}
 
 
 // #[cfg(all(test, not(feature = "use-mock-routing")))]